@@ -0,0 +1,167 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use consts::BACKUP_DIR;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utils::write_atomically;
+
+const JOURNAL: &str = "journal";
+const SAVED: &str = "saved";
+const CREATED: &str = "created";
+
+/// A set of original files saved before a run modifies them.
+///
+/// Every run gets its own directory inside `BACKUP_DIR`, containing a copy of each saved file
+/// and a `journal` file listing what has to be done to restore them. Files which didn't exist
+/// before the run are recorded too, so that `undo` can remove them.
+pub struct Backup {
+    dir: PathBuf,
+    journal: File,
+    saved: HashSet<PathBuf>,
+}
+
+impl Backup {
+    /// Creates a new backup set in `work_dir`.
+    pub fn new(work_dir: &Path) -> io::Result<Backup> {
+        let root = work_dir.join(BACKUP_DIR);
+        fs::create_dir_all(&root)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut id = 0;
+        let dir = loop {
+            let dir = root.join(format!("{:020}-{:04}", now, id));
+            match fs::create_dir(&dir) {
+                Ok(()) => break dir,
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => id += 1,
+                Err(e) => return Err(e),
+            }
+        };
+        let journal = File::create(dir.join(JOURNAL))?;
+        Ok(Backup {
+            dir,
+            journal,
+            saved: HashSet::new(),
+        })
+    }
+
+    /// Saves the current content of `path` (or the fact that it doesn't exist yet) so it can be
+    /// restored by [`undo`]. Saving the same file twice is a no-op.
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        let path = absolute_path(path)?;
+        if self.saved.contains(&path) {
+            return Ok(());
+        }
+        if path.is_file() {
+            let copy = format!("{}.orig", self.saved.len());
+            fs::copy(&path, self.dir.join(&copy))?;
+            writeln!(self.journal, "{} {} {}", SAVED, copy, path.display())?;
+        } else {
+            writeln!(self.journal, "{} {}", CREATED, path.display())?;
+        }
+        self.journal.sync_data()?;
+        self.saved.insert(path);
+        Ok(())
+    }
+}
+
+fn absolute_path(path: &Path) -> io::Result<PathBuf> {
+    if path.exists() {
+        return path.canonicalize();
+    }
+    // The file doesn't exist yet so we can only canonicalize its parent.
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.canonicalize()?,
+        _ => Path::new(".").canonicalize()?,
+    };
+    match path.file_name() {
+        Some(name) => Ok(parent.join(name)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' isn't a file path", path.display()),
+        )),
+    }
+}
+
+fn last_run(work_dir: &Path) -> io::Result<Option<PathBuf>> {
+    let root = work_dir.join(BACKUP_DIR);
+    if !root.is_dir() {
+        return Ok(None);
+    }
+    let mut runs = vec![];
+    for entry in fs::read_dir(&root)? {
+        let entry = entry?;
+        if entry.path().join(JOURNAL).is_file() {
+            runs.push(entry.path());
+        }
+    }
+    runs.sort();
+    Ok(runs.pop())
+}
+
+/// Restores the files saved by the last run in `work_dir` and removes its backup set.
+///
+/// Returns the list of restored (or removed) files, or `None` if there is no run to undo.
+pub fn undo(work_dir: &Path) -> io::Result<Option<Vec<PathBuf>>> {
+    let dir = match last_run(work_dir)? {
+        Some(dir) => dir,
+        None => return Ok(None),
+    };
+    let journal = BufReader::new(File::open(dir.join(JOURNAL))?);
+    let mut restored = vec![];
+
+    for line in journal.lines() {
+        let line = line?;
+        let mut parts = line.splitn(2, ' ');
+        match (parts.next(), parts.next()) {
+            (Some(SAVED), Some(rest)) => {
+                let mut parts = rest.splitn(2, ' ');
+                if let (Some(copy), Some(path)) = (parts.next(), parts.next()) {
+                    let content = fs::read(dir.join(copy))?;
+                    write_atomically(Path::new(path), &content)?;
+                    restored.push(PathBuf::from(path));
+                    continue;
+                }
+            }
+            (Some(CREATED), Some(path)) => {
+                match fs::remove_file(path) {
+                    Ok(()) => {}
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+                restored.push(PathBuf::from(path));
+                continue;
+            }
+            (Some(""), None) => continue,
+            _ => {}
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Invalid line in '{}': `{}`",
+                dir.join(JOURNAL).display(),
+                line
+            ),
+        ));
+    }
+    fs::remove_dir_all(&dir)?;
+    // We don't need the backup directory anymore if it's empty.
+    let _ = fs::remove_dir(work_dir.join(BACKUP_DIR));
+    Ok(Some(restored))
+}
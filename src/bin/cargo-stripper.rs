@@ -0,0 +1,292 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `cargo stripper`: runs rustdoc-stripper on the packages of a Cargo workspace, each of them
+//! having its own comments file.
+
+extern crate stripper_lib;
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use stripper_lib::diagnostics::Stderr;
+use stripper_lib::{Config, Package, Workspace, OUTPUT_COMMENT_FILE};
+use stripper_lib::{Diagnostic, Diagnostics};
+
+const HELP: &str = "\
+Usage: cargo stripper [OPTIONS] [COMMAND] [RUSTDOC-STRIPPER OPTIONS]
+
+Run rustdoc-stripper on the source directory of packages of the workspace
+
+Options:
+    -h, --help                    Display this help
+    -p, --package <NAME>          Package to run on, can be repeated as much as
+                                  needed (the one of the current directory or
+                                  the default members of the workspace by
+                                  default)
+        --workspace               Run on all the members of the workspace
+        --exclude <NAME>          Don't run on this package, can be repeated as
+                                  much as needed
+        --manifest-path <FILE>    Cargo.toml file to use (the one of the
+                                  current directory or of its closest parent by
+                                  default)
+    -o, --comment-file <FILE>     Comments file of each package, relative to
+                                  its directory, \"{package}\" being replaced by
+                                  the package name (comments.md by default)
+
+The other arguments are given to rustdoc-stripper (see rustdoc-stripper --help),
+except --dir which is set to the source directory of each package (the common
+parent of its targets, or src if some of them are outside of it, the other ones
+being skipped).
+";
+
+/// Arguments of the rustdoc-stripper commands which don't use a comments file.
+const WITHOUT_COMMENT_FILE: &[&str] = &["stats", "undo", "-u", "--undo", "completions", "help"];
+
+#[derive(Default)]
+struct Args {
+    help: bool,
+    packages: Vec<String>,
+    workspace: bool,
+    exclude: Vec<String>,
+    manifest_path: Option<String>,
+    comment_file: Option<String>,
+    /// Arguments given to rustdoc-stripper for every package.
+    forwarded: Vec<String>,
+}
+
+fn parse<I: Iterator<Item = String>>(input: I) -> Result<Args, String> {
+    let mut args = Args::default();
+    let mut input = input.peekable();
+    // `cargo stripper` runs `cargo-stripper stripper`.
+    if input.peek().map(|a| a == "stripper") == Some(true) {
+        input.next();
+    }
+    while let Some(arg) = input.next() {
+        if arg == "--" {
+            args.forwarded.push(arg);
+            args.forwarded.extend(input);
+            break;
+        }
+        let (name, inline) = match arg.find('=') {
+            Some(pos) if arg.starts_with("--") => (&arg[..pos], Some(arg[pos + 1..].to_owned())),
+            _ if arg.len() > 2 && (arg.starts_with("-p") || arg.starts_with("-o")) => {
+                (&arg[..2], Some(arg[2..].to_owned()))
+            }
+            _ => (&arg[..], None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| input.next())
+                .ok_or_else(|| format!("{} expects a value", name))
+        };
+        match name {
+            "-h" | "--help" => args.help = true,
+            "-p" | "--package" => args.packages.push(value()?),
+            "--workspace" | "--all" => args.workspace = true,
+            "--exclude" => args.exclude.push(value()?),
+            "--manifest-path" => args.manifest_path = Some(value()?),
+            "-o" | "--comment-file" => args.comment_file = Some(value()?),
+            "-d" | "--dir" => {
+                return Err(format!(
+                    "{} cannot be used, the source directory of each package is used",
+                    name
+                ))
+            }
+            _ => args.forwarded.push(arg.clone()),
+        }
+    }
+    Ok(args)
+}
+
+/// Returns the packages to run on.
+fn select<'a>(
+    args: &Args,
+    workspace: &'a Workspace,
+    manifest: &Path,
+) -> Result<Vec<&'a Package>, String> {
+    let find = |name: &str| {
+        workspace
+            .members
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| format!("Package `{}` not found in the workspace", name))
+    };
+    if !args.packages.is_empty() {
+        return args.packages.iter().map(|name| find(name)).collect();
+    }
+    let excluded = |p: &&Package| !args.exclude.contains(&p.name);
+    if args.workspace {
+        return Ok(workspace.members.iter().filter(excluded).collect());
+    }
+    // The package of the manifest if it's one, the default members otherwise.
+    let dir = manifest.parent();
+    match workspace.members.iter().find(|p| Some(&*p.dir) == dir) {
+        Some(package) => Ok(vec![package]),
+        None => workspace
+            .default_members
+            .iter()
+            .map(|name| find(name))
+            .filter(|p| p.as_ref().map(excluded).unwrap_or(true))
+            .collect(),
+    }
+}
+
+/// The `rustdoc-stripper` binary next to this one or, if there is none, the one of the `PATH`.
+fn rustdoc_stripper() -> PathBuf {
+    let name = format!("rustdoc-stripper{}", env::consts::EXE_SUFFIX);
+    env::current_exe()
+        .ok()
+        .map(|p| p.with_file_name(&name))
+        .filter(|p| p.is_file())
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+fn emit(diagnostic: Diagnostic) {
+    Stderr.emit(&diagnostic);
+}
+
+fn main() {
+    let args = match parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            emit(Diagnostic::error(e));
+            emit(Diagnostic::info(
+                "Rerun with -h option for more information",
+            ));
+            process::exit(2);
+        }
+    };
+    if args.help {
+        print!("{}", HELP);
+        return;
+    }
+    let manifest = match args.manifest_path {
+        Some(ref path) => Some(PathBuf::from(path)),
+        None => env::current_dir()
+            .ok()
+            .and_then(|dir| Workspace::find_manifest(&dir)),
+    };
+    let manifest = match manifest.map(|m| m.canonicalize().map_err(|e| (m, e))) {
+        Some(Ok(manifest)) => manifest,
+        Some(Err((m, e))) => {
+            emit(Diagnostic::error(format!(
+                "Cannot read '{}': {}",
+                m.display(),
+                e
+            )));
+            process::exit(1);
+        }
+        None => {
+            emit(Diagnostic::error(
+                "Could not find a Cargo.toml file in the current directory or its parents",
+            ));
+            process::exit(1);
+        }
+    };
+    let workspace = match Workspace::load(&manifest) {
+        Ok(workspace) => workspace,
+        Err(e) => {
+            emit(Diagnostic::error(format!("Invalid manifest: {}", e)));
+            process::exit(1);
+        }
+    };
+    let packages = match select(&args, &workspace, &manifest) {
+        Ok(packages) => packages,
+        Err(e) => {
+            emit(Diagnostic::error(e));
+            process::exit(1);
+        }
+    };
+    let bin = rustdoc_stripper();
+    let no_config = args.forwarded.iter().any(|a| a == "--no-config");
+    let uses_comment_file = !args
+        .forwarded
+        .iter()
+        .any(|a| WITHOUT_COMMENT_FILE.contains(&&a[..]));
+    let takes_dir = !args
+        .forwarded
+        .first()
+        .is_some_and(|a| a == "help" || a == "completions");
+    let mut code = 0;
+    for package in packages {
+        let source_dir = package.source_dir();
+        let comment_file = match args.comment_file {
+            _ if !uses_comment_file => None,
+            Some(ref file) => Some(file.replace("{package}", &package.name)),
+            // The comments file set by the configuration of the package, if any, is used.
+            None => match Config::find(&source_dir) {
+                Ok(Some(ref c))
+                    if !no_config
+                        && c.comment_file.is_some()
+                        && c.path.starts_with(&package.dir) =>
+                {
+                    None
+                }
+                _ => Some(OUTPUT_COMMENT_FILE.to_owned()),
+            },
+        };
+        // The options of rustdoc-stripper follow its command, and precede the
+        // files given after `--`.
+        let split = args
+            .forwarded
+            .iter()
+            .position(|a| a == "--")
+            .unwrap_or(args.forwarded.len());
+        let mut command = Command::new(&bin);
+        command.args(&args.forwarded[..split]);
+        if takes_dir {
+            command.arg("--dir").arg(&source_dir);
+        }
+        if let Some(comment_file) = comment_file {
+            command
+                .arg("--comment-file")
+                .arg(package.dir.join(comment_file));
+        }
+        command.args(&args.forwarded[split..]);
+        emit(Diagnostic::info(format!(
+            "Package `{}` ({})",
+            package.name,
+            source_dir.display()
+        )));
+        for target in package.outside_targets() {
+            emit(
+                Diagnostic::warning(format!(
+                    "Target outside of the source directory `{}`, skipped",
+                    source_dir.display()
+                ))
+                .with_path(target.display().to_string()),
+            );
+        }
+        match command.status() {
+            Ok(status) => match status.code() {
+                Some(0) => {}
+                // The command line is invalid, it'll be the same for the other packages.
+                Some(2) => process::exit(2),
+                _ => code = 1,
+            },
+            Err(e) => {
+                emit(Diagnostic::error(format!(
+                    "Cannot run '{}': {}",
+                    bin.display(),
+                    e
+                )));
+                process::exit(1);
+            }
+        }
+    }
+    process::exit(code);
+}
@@ -0,0 +1,132 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use consts::CACHE_FILE;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use utils::write_atomically;
+
+const HEADER: &str = "rustdoc-stripper-cache 1";
+const STRIP: &str = "strip";
+const REGENERATE: &str = "regenerate";
+
+/// Returns a hash of `data` which doesn't change between runs (64-bit FNV-1a).
+pub fn hash(data: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in data {
+        h ^= u64::from(*b);
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h
+}
+
+/// Remembers which files don't need to be processed again.
+///
+/// Only the hashes of files for which stripping or regenerating changes nothing are stored: for
+/// stripping, files without doc comments; for regenerating, files where none of the entries of
+/// the comments file (also identified by a hash) can be put. Such files can then be skipped as
+/// long as neither their content nor the entries change. The doc comments of the files aren't
+/// stored, so the files which have some are always processed.
+pub struct Cache {
+    path: PathBuf,
+    strip: HashMap<String, u64>,
+    regenerate: HashMap<String, (u64, u64)>,
+}
+
+impl Cache {
+    /// Loads the cache of `work_dir`. If there is none or it can't be read, an empty one is
+    /// returned.
+    pub fn load(work_dir: &Path) -> Cache {
+        let mut cache = Cache {
+            path: work_dir.join(CACHE_FILE),
+            strip: HashMap::new(),
+            regenerate: HashMap::new(),
+        };
+        let content = match fs::read_to_string(&cache.path) {
+            Ok(c) => c,
+            Err(_) => return cache,
+        };
+        let mut lines = content.lines();
+        if lines.next() != Some(HEADER) {
+            return cache;
+        }
+        for line in lines {
+            let mut parts = line.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some(STRIP), Some(rest)) => {
+                    let mut parts = rest.splitn(2, ' ');
+                    if let (Some(Ok(h)), Some(path)) = (parts.next().map(parse_hash), parts.next())
+                    {
+                        cache.strip.insert(path.to_owned(), h);
+                    }
+                }
+                (Some(REGENERATE), Some(rest)) => {
+                    let mut parts = rest.splitn(3, ' ');
+                    if let (Some(Ok(h)), Some(Ok(entries)), Some(path)) = (
+                        parts.next().map(parse_hash),
+                        parts.next().map(parse_hash),
+                        parts.next(),
+                    ) {
+                        cache.regenerate.insert(path.to_owned(), (h, entries));
+                    }
+                }
+                _ => {}
+            }
+        }
+        cache
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut out = format!("{}\n", HEADER);
+        let mut strip = self.strip.iter().collect::<Vec<_>>();
+        strip.sort();
+        for (path, h) in strip {
+            out.push_str(&format!("{} {:016x} {}\n", STRIP, h, path));
+        }
+        let mut regenerate = self.regenerate.iter().collect::<Vec<_>>();
+        regenerate.sort();
+        for (path, &(h, entries)) in regenerate {
+            out.push_str(&format!(
+                "{} {:016x} {:016x} {}\n",
+                REGENERATE, h, entries, path
+            ));
+        }
+        write_atomically(&self.path, out.as_bytes())
+    }
+
+    /// Returns `true` if the file `path` with a content hashed as `content` has no doc comments.
+    pub fn is_stripped(&self, path: &str, content: u64) -> bool {
+        self.strip.get(path) == Some(&content)
+    }
+
+    pub fn set_stripped(&mut self, path: &str, content: u64) {
+        self.strip.insert(path.to_owned(), content);
+    }
+
+    /// Returns `true` if none of the entries hashed as `entries` can be put into the file `path`
+    /// with a content hashed as `content`.
+    pub fn is_regenerated(&self, path: &str, content: u64, entries: u64) -> bool {
+        self.regenerate.get(path) == Some(&(content, entries))
+    }
+
+    pub fn set_regenerated(&mut self, path: &str, content: u64, entries: u64) {
+        self.regenerate.insert(path.to_owned(), (content, entries));
+    }
+}
+
+fn parse_hash(s: &str) -> Result<u64, ::std::num::ParseIntError> {
+    u64::from_str_radix(s, 16)
+}
@@ -0,0 +1,879 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use stripper_lib::OUTPUT_COMMENT_FILE;
+use stripper_lib::{
+    Config, DocKind, FileFilter, Format, ItemFilter, OnConflict, Pattern, Selector, Visibility,
+};
+
+const BIN: &str = "rustdoc-stripper";
+
+/// What has to be done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Strip,
+    Regenerate,
+    Undo,
+    Stats,
+    Coverage,
+    Completions,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Strip => "strip",
+            Action::Regenerate => "regenerate",
+            Action::Undo => "undo",
+            Action::Stats => "stats",
+            Action::Coverage => "coverage",
+            Action::Completions => "completions",
+        }
+    }
+}
+
+/// When to color the messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Color {
+    /// Only if the standard error output is a terminal and `NO_COLOR` isn't set.
+    Auto,
+    Always,
+    Never,
+}
+
+/// How the messages are printed on the standard error output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MessageFormat {
+    Human,
+    /// One JSON object per line.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Options which make no sense with the `diff` command since nothing is written.
+const NOT_FOR_DIFF: &[&str] = &["no_file_output", "force", "backup", "prune_comment_file"];
+
+/// A tool to manipulate rustdoc comments.
+///
+/// Without command, the files are stripped (or regenerated with -g, restored with -u).
+#[derive(Parser)]
+#[command(
+    name = BIN,
+    version,
+    args_conflicts_with_subcommands = true,
+    group(ArgGroup::new("action").multiple(false)),
+    after_help = "IMPORTANT: Only files ending with '.rs' will be stripped/regenerated."
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Same as the strip command
+    #[arg(short, long, group = "action")]
+    strip: bool,
+    /// Same as the regenerate command
+    #[arg(short = 'g', long, group = "action")]
+    regenerate: bool,
+    /// Same as the undo command
+    #[arg(short, long, group = "action")]
+    undo: bool,
+    #[command(flatten)]
+    options: AllOptions,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Strip the doc comments of the files and save them into the comments file (this is the
+    /// default command)
+    Strip(StripArgs),
+    /// Put the doc comments of the comments file back into the files
+    Regenerate(RegenerateArgs),
+    /// Don't modify anything, exit with an error if some files would be stripped (or
+    /// regenerated with -g)
+    Check(PreviewArgs),
+    /// Display the changes which stripping (or regenerating with -g) would make to the files,
+    /// without modifying them (with -c, exit with an error if there are some)
+    Diff(PreviewArgs),
+    /// Display how many items the files contain and how many of them have doc comments
+    Stats(StatsArgs),
+    /// Display the documentation coverage of each file and module by item kind, the items
+    /// having doc comments in the comments file being documented as well
+    Coverage(CoverageArgs),
+    /// Restore the files saved by the last run made with the --backup option
+    Undo(UndoArgs),
+    /// Print the completion script of the given shell
+    Completions { shell: Shell },
+}
+
+/// Options of every command going through a directory.
+#[derive(clap::Args)]
+struct Walk {
+    /// Directory to work on (the current one by default)
+    #[arg(short, long, value_name = "DIR")]
+    dir: Option<String>,
+    /// Activate verbose mode
+    #[arg(short, long)]
+    verbose: bool,
+    /// Only display warnings and errors
+    #[arg(short, long)]
+    quiet: bool,
+    /// Color the warnings and errors
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    color: Color,
+    /// How to display the messages on stderr
+    #[arg(long, value_name = "FMT", default_value = "human")]
+    message_format: MessageFormat,
+    /// Read the settings from this file instead of looking for a "rustdoc-stripper.toml" file
+    /// (or a Cargo.toml file with a [package.metadata.rustdoc-stripper] table)
+    #[arg(long, value_name = "FILE")]
+    config: Option<String>,
+    /// Don't read the settings from a configuration file
+    #[arg(long)]
+    no_config: bool,
+}
+
+#[derive(clap::Args)]
+struct CommentFile {
+    /// File where the doc comments are saved to/loaded from (comments.md by default)
+    #[arg(short = 'o', long, value_name = "FILE")]
+    comment_file: Option<String>,
+}
+
+/// Options of the commands going through the items of the files.
+#[derive(clap::Args)]
+struct Counting {
+    /// Ignore the files and directories matching the given glob ("*_generated.rs",
+    /// "tests/fixtures/") or, if prefixed with "re:", regex, can be repeated as much as needed
+    #[arg(short, long, value_name = "PATTERN", value_parser = pattern)]
+    ignore: Vec<String>,
+    /// Only strip/regenerate/count the doc comments of the items with this visibility: "pub",
+    /// "pub(crate)", "pub(super)", "pub(in PATH)" or "private", can be repeated as much as needed
+    #[arg(long, value_name = "VISIBILITY")]
+    visibility: Vec<Visibility>,
+    /// Macros in hierarchy will be ignored (so only macros with doc comments will appear in the
+    /// comments file)
+    #[arg(short = 'm', long)]
+    ignore_macros: bool,
+    /// Number of threads used to process files (1 by default, 0 means one per CPU)
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<usize>,
+}
+
+/// Options selecting the files to go through.
+#[derive(clap::Args)]
+struct Filtering {
+    /// Only go through the files matching the given pattern (or inside a matching directory),
+    /// can be repeated as much as needed
+    #[arg(short = 'I', long, value_name = "PATTERN", value_parser = pattern)]
+    include: Vec<String>,
+    /// Ignore what the .gitignore, .ignore and .git/info/exclude files ignore
+    #[arg(short = 'G', long)]
+    gitignore: bool,
+    /// Go through symbolic links, which are skipped by default (every directory is only gone
+    /// through once)
+    #[arg(short = 'L', long)]
+    follow_links: bool,
+    /// Go through the target directories of Cargo, which are skipped by default
+    #[arg(long)]
+    no_skip_target: bool,
+}
+
+/// Options of the commands modifying the files.
+#[derive(clap::Args)]
+struct Modifying {
+    /// Files to process instead of going through the directory to work on
+    #[arg(value_name = "FILES")]
+    files: Vec<String>,
+    /// Only process the files listed in the given file ("-" for the standard input), one per
+    /// line or NUL-separated, in addition to the ones given as arguments
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<String>,
+    /// Only strip/regenerate the doc comments of the items matching the given selector
+    /// ("src/lib.rs@struct Foo", "impl Foo::fn *"), can be repeated as much as needed
+    #[arg(long, value_name = "SELECTOR", value_parser = selector)]
+    only: Vec<String>,
+    /// Leave the doc comments of the items matching the given selector where they are, can be
+    /// repeated as much as needed
+    #[arg(long, value_name = "SELECTOR", value_parser = selector)]
+    exclude: Vec<String>,
+    /// Only strip/regenerate the doc comments of this kind: "module" (inner doc comments of
+    /// files and modules), "field" or an item kind ("fn", "struct", "variant"...), can be
+    /// repeated as much as needed
+    #[arg(long, value_name = "KIND")]
+    only_kind: Vec<DocKind>,
+    /// Leave the doc comments of this kind where they are, can be repeated as much as needed
+    #[arg(long, value_name = "KIND")]
+    exclude_kind: Vec<DocKind>,
+    /// Save the original of every file modified by the run in the ".rustdoc-stripper" folder of
+    /// the working directory
+    #[arg(short, long)]
+    backup: bool,
+    /// Same as the check command
+    #[arg(short, long)]
+    check: bool,
+    /// Skip the files where the last runs found nothing to strip or regenerate and which haven't
+    /// changed since, remembered in the ".rustdoc-stripper-cache" file of the working directory
+    #[arg(short = 'C', long)]
+    skip_unaffected: bool,
+}
+
+#[derive(clap::Args)]
+struct StripOnly {
+    /// Display the doc comments on stdout instead of saving them into the comments file
+    #[arg(short, long)]
+    no_file_output: bool,
+    /// Remove confirmation demands
+    #[arg(short, long)]
+    force: bool,
+    /// Write a hash of the declaration of each item in its entry, so that a fuzzy regeneration
+    /// can find the item once renamed
+    #[arg(long)]
+    signatures: bool,
+}
+
+#[derive(clap::Args)]
+struct RegenerateOnly {
+    /// Leave the items which already have doc comments untouched (same as "--on-conflict skip")
+    #[arg(short = 'x', long)]
+    ignore_doc_commented: bool,
+    /// What to do when regenerating the doc comments of an item which already has some: "skip",
+    /// "replace", "append" (default), "prepend" or "fail"
+    #[arg(short = 'e', long, value_name = "POLICY")]
+    on_conflict: Option<OnConflict>,
+    /// Put doc comments whose item can't be found on the item it has most likely been renamed or
+    /// moved to (matches are only displayed otherwise)
+    #[arg(short = 'z', long)]
+    fuzzy: bool,
+    /// Remove the doc comments which have been put back from the comments file (and delete it if
+    /// none remain)
+    #[arg(short, long)]
+    prune_comment_file: bool,
+    /// Write the list of modified files and of doc comments which couldn't be put back (with the
+    /// reason) as JSON into this file
+    #[arg(short = 'J', long, value_name = "FILE")]
+    json_report: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct Reporting {
+    /// How to display the report of a regeneration, the stats or the coverage: "text" (default)
+    /// or "json"
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<Format>,
+}
+
+#[derive(clap::Args)]
+struct StripArgs {
+    #[command(flatten)]
+    walk: Walk,
+    #[command(flatten)]
+    comment_file: CommentFile,
+    #[command(flatten)]
+    counting: Counting,
+    #[command(flatten)]
+    filtering: Filtering,
+    #[command(flatten)]
+    modifying: Modifying,
+    #[command(flatten)]
+    strip: StripOnly,
+}
+
+#[derive(clap::Args)]
+struct RegenerateArgs {
+    #[command(flatten)]
+    walk: Walk,
+    #[command(flatten)]
+    comment_file: CommentFile,
+    #[command(flatten)]
+    counting: Counting,
+    #[command(flatten)]
+    filtering: Filtering,
+    #[command(flatten)]
+    modifying: Modifying,
+    #[command(flatten)]
+    regenerate: RegenerateOnly,
+    #[command(flatten)]
+    reporting: Reporting,
+}
+
+#[derive(clap::Args)]
+struct StatsArgs {
+    #[command(flatten)]
+    walk: Walk,
+    #[command(flatten)]
+    counting: Counting,
+    #[command(flatten)]
+    filtering: Filtering,
+    #[command(flatten)]
+    reporting: Reporting,
+}
+
+#[derive(clap::Args)]
+struct CoverageArgs {
+    #[command(flatten)]
+    walk: Walk,
+    #[command(flatten)]
+    comment_file: CommentFile,
+    #[command(flatten)]
+    counting: Counting,
+    #[command(flatten)]
+    filtering: Filtering,
+    #[command(flatten)]
+    reporting: Reporting,
+    /// Exit with an error if less than this percentage of the items are documented
+    #[arg(long, value_name = "PERCENT", value_parser = percent)]
+    fail_under: Option<f64>,
+}
+
+#[derive(clap::Args)]
+struct UndoArgs {
+    #[command(flatten)]
+    walk: Walk,
+}
+
+/// The options of the strip and regenerate commands, checked against the one which is run.
+#[derive(clap::Args)]
+struct AllOptions {
+    #[command(flatten)]
+    walk: Walk,
+    #[command(flatten)]
+    comment_file: CommentFile,
+    #[command(flatten)]
+    counting: Counting,
+    #[command(flatten)]
+    filtering: Filtering,
+    #[command(flatten)]
+    modifying: Modifying,
+    #[command(flatten)]
+    strip: StripOnly,
+    #[command(flatten)]
+    regenerate: RegenerateOnly,
+    #[command(flatten)]
+    reporting: Reporting,
+}
+
+#[derive(clap::Args)]
+struct PreviewArgs {
+    /// Regenerate the files instead of stripping them
+    #[arg(short = 'g', long)]
+    regenerate: bool,
+    #[command(flatten)]
+    options: AllOptions,
+}
+
+fn pattern(value: &str) -> Result<String, String> {
+    Pattern::new(value).map(|_| value.to_owned())
+}
+
+fn selector(value: &str) -> Result<String, String> {
+    Selector::new(value).map(|_| value.to_owned())
+}
+
+fn percent(value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|p| (0. ..=100.).contains(p))
+        .ok_or_else(|| "expected a percentage between 0 and 100".to_owned())
+}
+
+/// The parsed command line.
+pub struct Args {
+    /// `None` if neither a command nor one of `-s`, `-g` and `-u` has been given.
+    pub action: Option<Action>,
+    /// `check` command or `--check` option.
+    pub check: bool,
+    /// `diff` command.
+    pub diff: bool,
+    pub shell: Option<Shell>,
+    pub directory: String,
+    pub comment_file: String,
+    pub files_to_ignore: Vec<String>,
+    pub files_to_include: Vec<String>,
+    /// Selectors of the items whose doc comments are stripped/regenerated.
+    pub only: Vec<String>,
+    /// Selectors of the items whose doc comments are left where they are.
+    pub exclude: Vec<String>,
+    /// Kinds of the doc comments which are stripped/regenerated.
+    pub only_kinds: Vec<DocKind>,
+    /// Kinds of the doc comments which are left where they are.
+    pub exclude_kinds: Vec<DocKind>,
+    /// Visibilities of the items whose doc comments are stripped/regenerated/counted.
+    pub visibility: Vec<Visibility>,
+    /// Files to process instead of going through the directory, relative to the current one.
+    pub files: Vec<String>,
+    pub files_from: Option<String>,
+    pub gitignore: bool,
+    pub skip_target: bool,
+    pub follow_links: bool,
+    pub stdout_output: bool,
+    pub verbose: bool,
+    pub quiet: bool,
+    pub color: Color,
+    pub message_format: MessageFormat,
+    pub force: bool,
+    pub ignore_macros: bool,
+    pub on_conflict: OnConflict,
+    pub backup: bool,
+    pub jobs: usize,
+    pub skip_unaffected: bool,
+    pub fuzzy: bool,
+    pub signatures: bool,
+    pub prune: bool,
+    pub json_report: Option<String>,
+    pub format: Format,
+    /// Minimum documentation coverage, in percent.
+    pub fail_under: Option<f64>,
+    pub config: Option<String>,
+    pub no_config: bool,
+    /// Options given on the command line (their ids), which take precedence over the
+    /// configuration file.
+    given: Vec<String>,
+}
+
+impl Default for Args {
+    fn default() -> Args {
+        Args {
+            action: None,
+            check: false,
+            diff: false,
+            shell: None,
+            directory: ".".to_owned(),
+            comment_file: OUTPUT_COMMENT_FILE.to_owned(),
+            files_to_ignore: vec![],
+            files_to_include: vec![],
+            only: vec![],
+            exclude: vec![],
+            only_kinds: vec![],
+            exclude_kinds: vec![],
+            visibility: vec![],
+            files: vec![],
+            files_from: None,
+            gitignore: false,
+            skip_target: true,
+            follow_links: false,
+            stdout_output: false,
+            verbose: false,
+            quiet: false,
+            color: Color::Auto,
+            message_format: MessageFormat::Human,
+            force: false,
+            ignore_macros: false,
+            on_conflict: OnConflict::Append,
+            backup: false,
+            jobs: 1,
+            skip_unaffected: false,
+            fuzzy: false,
+            signatures: false,
+            prune: false,
+            json_report: None,
+            format: Format::Text,
+            fail_under: None,
+            config: None,
+            no_config: false,
+            given: vec![],
+        }
+    }
+}
+
+impl Walk {
+    fn apply(self, args: &mut Args) {
+        if let Some(dir) = self.dir {
+            args.directory = dir;
+        }
+        args.verbose = self.verbose;
+        args.quiet = self.quiet;
+        args.color = self.color;
+        args.message_format = self.message_format;
+        args.config = self.config;
+        args.no_config = self.no_config;
+    }
+}
+
+impl CommentFile {
+    fn apply(self, args: &mut Args) {
+        if let Some(comment_file) = self.comment_file {
+            args.comment_file = comment_file;
+        }
+    }
+}
+
+impl Counting {
+    fn apply(self, args: &mut Args) {
+        args.files_to_ignore = self.ignore;
+        args.visibility = self.visibility;
+        args.ignore_macros = self.ignore_macros;
+        if let Some(jobs) = self.jobs {
+            args.jobs = jobs;
+        }
+    }
+}
+
+impl Filtering {
+    fn apply(self, args: &mut Args) {
+        args.files_to_include = self.include;
+        args.gitignore = self.gitignore;
+        args.follow_links = self.follow_links;
+        args.skip_target = !self.no_skip_target;
+    }
+}
+
+impl Modifying {
+    fn apply(self, args: &mut Args) {
+        args.files = self.files;
+        args.files_from = self.files_from;
+        args.only = self.only;
+        args.exclude = self.exclude;
+        args.only_kinds = self.only_kind;
+        args.exclude_kinds = self.exclude_kind;
+        args.backup = self.backup;
+        args.check |= self.check;
+        args.skip_unaffected = self.skip_unaffected;
+    }
+}
+
+impl StripOnly {
+    fn apply(self, args: &mut Args) {
+        args.stdout_output = self.no_file_output;
+        args.force = self.force;
+        args.signatures = self.signatures;
+    }
+}
+
+impl RegenerateOnly {
+    fn apply(self, args: &mut Args) {
+        if self.ignore_doc_commented {
+            args.on_conflict = OnConflict::Skip;
+        }
+        if let Some(policy) = self.on_conflict {
+            args.on_conflict = policy;
+        }
+        args.fuzzy = self.fuzzy;
+        args.prune = self.prune_comment_file;
+        args.json_report = self.json_report;
+    }
+}
+
+impl Reporting {
+    fn apply(self, args: &mut Args) {
+        if let Some(format) = self.format {
+            args.format = format;
+        }
+    }
+}
+
+impl AllOptions {
+    fn apply(self, args: &mut Args) {
+        self.walk.apply(args);
+        self.comment_file.apply(args);
+        self.counting.apply(args);
+        self.filtering.apply(args);
+        self.modifying.apply(args);
+        self.strip.apply(args);
+        self.regenerate.apply(args);
+        self.reporting.apply(args);
+    }
+}
+
+impl Args {
+    /// Uses the settings of `config` which haven't been given on the command line.
+    pub fn apply_config(&mut self, config: Config) {
+        let given_options = self.given.clone();
+        let given = |id: &str| given_options.iter().any(|g| g == id);
+        if !given("dir") {
+            // By default, the directory of the configuration file is used.
+            let config_dir = config.path.parent().map(|p| p.to_path_buf());
+            let dir = config.dir.or(config_dir).unwrap_or_default();
+            self.directory = if dir == Path::new("") {
+                ".".to_owned()
+            } else {
+                dir.display().to_string()
+            };
+        }
+        if let (Some(comment_file), false) = (config.comment_file, given("comment_file")) {
+            self.comment_file = comment_file.display().to_string();
+        }
+        if let (Some(ignore), false) = (config.ignore, given("ignore")) {
+            self.files_to_ignore = ignore;
+        }
+        if let (Some(include), false) = (config.include, given("include")) {
+            self.files_to_include = include;
+        }
+        if let (Some(only), false) = (config.only, given("only")) {
+            self.only = only;
+        }
+        if let (Some(exclude), false) = (config.exclude, given("exclude")) {
+            self.exclude = exclude;
+        }
+        if let (Some(kinds), false) = (config.only_kinds, given("only_kind")) {
+            self.only_kinds = kinds;
+        }
+        if let (Some(kinds), false) = (config.exclude_kinds, given("exclude_kind")) {
+            self.exclude_kinds = kinds;
+        }
+        if let (Some(visibility), false) = (config.visibility, given("visibility")) {
+            self.visibility = visibility;
+        }
+        if let (Some(skip_target), false) = (config.skip_target, given("no_skip_target")) {
+            self.skip_target = skip_target;
+        }
+        if let (Some(policy), false) = (
+            config.on_conflict,
+            given("on_conflict") || given("ignore_doc_commented"),
+        ) {
+            self.on_conflict = policy;
+        }
+        if let (Some(format), false) = (config.format, given("format")) {
+            self.format = format;
+        }
+        if let (Some(jobs), false) = (config.jobs, given("jobs")) {
+            self.jobs = jobs;
+        }
+        if let (Some(percent), false) = (config.fail_under, given("fail_under")) {
+            self.fail_under = Some(percent);
+        }
+        let flags = [
+            (config.ignore_macros, &mut self.ignore_macros),
+            (config.backup, &mut self.backup),
+            (config.skip_unaffected, &mut self.skip_unaffected),
+            (config.fuzzy, &mut self.fuzzy),
+            (config.signatures, &mut self.signatures),
+            (config.prune_comment_file, &mut self.prune),
+            (config.gitignore, &mut self.gitignore),
+            (config.follow_links, &mut self.follow_links),
+        ];
+        for (value, flag) in flags {
+            // Flags can only be enabled on the command line.
+            if let Some(value) = value {
+                *flag |= value;
+            }
+        }
+    }
+
+    /// The filter selecting the files to go through.
+    pub fn file_filter(&self) -> Result<FileFilter, String> {
+        let patterns = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| Pattern::new(p))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(FileFilter {
+            include: patterns(&self.files_to_include)?,
+            exclude: patterns(&self.files_to_ignore)?,
+            gitignore: self.gitignore,
+            skip_target: self.skip_target,
+            follow_links: self.follow_links,
+        })
+    }
+
+    /// The filter selecting the items whose doc comments are stripped/regenerated.
+    pub fn item_filter(&self) -> Result<ItemFilter, String> {
+        let selectors = |selectors: &[String]| {
+            selectors
+                .iter()
+                .map(|s| Selector::new(s))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(ItemFilter {
+            only: selectors(&self.only)?,
+            exclude: selectors(&self.exclude)?,
+            only_kinds: self.only_kinds.clone(),
+            exclude_kinds: self.exclude_kinds.clone(),
+            visibility: self.visibility.clone(),
+        })
+    }
+
+    /// The action to run, `strip` being the default one.
+    pub fn action(&self) -> Action {
+        self.action.unwrap_or(Action::Strip)
+    }
+}
+
+/// Returns an error if one of the options of `matches`, parsed by `parsed`, can't be used with
+/// the `action` command (or with the `diff` one if `diff` is `true`).
+fn check_options(
+    command: &mut clap::Command,
+    parsed: &clap::Command,
+    matches: &ArgMatches,
+    action: Action,
+    diff: bool,
+) -> Result<(), clap::Error> {
+    let allowed = command
+        .find_subcommand(action.name())
+        .map(|c| {
+            c.get_arguments()
+                .map(|a| a.get_id().as_str().to_owned())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    for arg in parsed.get_arguments() {
+        let id = arg.get_id().as_str();
+        if matches.value_source(id) != Some(ValueSource::CommandLine)
+            || ["strip", "regenerate", "undo", "help", "version"].contains(&id)
+        {
+            continue;
+        }
+        let name = match arg.get_long() {
+            Some(long) => format!("'--{}'", long),
+            None => format!("'{}'", id.to_uppercase()),
+        };
+        let message = if diff && NOT_FOR_DIFF.contains(&id) {
+            format!("{} cannot be used with the diff command", name)
+        } else if !allowed.iter().any(|a| a == id) {
+            format!("{} cannot be used with the {} command", name, action.name())
+        } else {
+            continue;
+        };
+        return Err(command.error(ErrorKind::ArgumentConflict, message));
+    }
+    Ok(())
+}
+
+/// Returns the ids of the options of `matches` given on the command line.
+fn given_options(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .ids()
+        .map(|id| id.as_str())
+        .filter(|&id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        .map(|id| id.to_owned())
+        .collect()
+}
+
+/// Parses the command line arguments (with the program name).
+pub fn parse<I, T>(input: I) -> Result<Args, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let mut command = Cli::command();
+    let matches = command.try_get_matches_from_mut(input)?;
+    let cli = Cli::from_arg_matches(&matches)?;
+    let mut args = Args::default();
+
+    match cli.command {
+        Some(Command::Strip(a)) => {
+            args.action = Some(Action::Strip);
+            a.walk.apply(&mut args);
+            a.comment_file.apply(&mut args);
+            a.counting.apply(&mut args);
+            a.filtering.apply(&mut args);
+            a.modifying.apply(&mut args);
+            a.strip.apply(&mut args);
+        }
+        Some(Command::Regenerate(a)) => {
+            args.action = Some(Action::Regenerate);
+            a.walk.apply(&mut args);
+            a.comment_file.apply(&mut args);
+            a.counting.apply(&mut args);
+            a.filtering.apply(&mut args);
+            a.modifying.apply(&mut args);
+            a.regenerate.apply(&mut args);
+            a.reporting.apply(&mut args);
+        }
+        Some(Command::Check(a)) | Some(Command::Diff(a)) => {
+            let (name, sub_matches) = matches.subcommand().expect("a command has been given");
+            let action = if a.regenerate {
+                Action::Regenerate
+            } else {
+                Action::Strip
+            };
+            let parsed = command
+                .find_subcommand(name)
+                .expect("the command exists")
+                .clone();
+            check_options(&mut command, &parsed, sub_matches, action, name == "diff")?;
+            args.action = Some(action);
+            args.check = name == "check";
+            args.diff = name == "diff";
+            a.options.apply(&mut args);
+        }
+        Some(Command::Stats(a)) => {
+            args.action = Some(Action::Stats);
+            a.walk.apply(&mut args);
+            a.counting.apply(&mut args);
+            a.filtering.apply(&mut args);
+            a.reporting.apply(&mut args);
+        }
+        Some(Command::Coverage(a)) => {
+            args.action = Some(Action::Coverage);
+            a.walk.apply(&mut args);
+            a.comment_file.apply(&mut args);
+            a.counting.apply(&mut args);
+            a.filtering.apply(&mut args);
+            a.reporting.apply(&mut args);
+            args.fail_under = a.fail_under;
+        }
+        Some(Command::Undo(a)) => {
+            args.action = Some(Action::Undo);
+            a.walk.apply(&mut args);
+        }
+        Some(Command::Completions { shell }) => {
+            args.action = Some(Action::Completions);
+            args.shell = Some(shell);
+        }
+        None => {
+            args.action = if cli.regenerate {
+                Some(Action::Regenerate)
+            } else if cli.undo {
+                Some(Action::Undo)
+            } else if cli.strip {
+                Some(Action::Strip)
+            } else {
+                None
+            };
+            // Files can be given without command, but a word which isn't a command nor a Rust
+            // file is more likely to be a mistyped command.
+            if let (None, Some(file)) = (args.action, cli.options.modifying.files.first()) {
+                if !file.ends_with(".rs") {
+                    return Err(command.error(
+                        ErrorKind::InvalidSubcommand,
+                        format!("unrecognized subcommand '{}'", file),
+                    ));
+                }
+            }
+            let parsed = command.clone();
+            check_options(&mut command, &parsed, &matches, args.action(), false)?;
+            cli.options.apply(&mut args);
+        }
+    }
+    args.given = match matches.subcommand() {
+        Some((_, sub_matches)) => given_options(sub_matches),
+        None => given_options(&matches),
+    };
+    Ok(args)
+}
+
+/// Returns the completion script of `shell`.
+pub fn completions(shell: Shell) -> String {
+    let shell = match shell {
+        Shell::Bash => clap_complete::Shell::Bash,
+        Shell::Zsh => clap_complete::Shell::Zsh,
+        Shell::Fish => clap_complete::Shell::Fish,
+    };
+    let mut out = vec![];
+    clap_complete::generate(shell, &mut Cli::command(), BIN, &mut out);
+    String::from_utf8_lossy(&out).into_owned()
+}
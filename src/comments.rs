@@ -0,0 +1,541 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Model of the comments file.
+//!
+//! A comments file is made of sections, one per source file (or `*` for the entries which can
+//! go into any file), each containing the doc comments of the items of the file:
+//!
+//! ```text
+//! <!-- file src/lib.rs -->
+//! <!-- file_comment -->
+//! The crate documentation.
+//! <!-- struct Foo -->
+//! The documentation of `Foo`.
+//! <!-- file_comment mod foo -->
+//! The inner documentation of `foo`.
+//! ```
+
+use consts::{END_INFO, FILE, FILE_COMMENT, MOD_COMMENT, SIGNATURE};
+use error::{FileError, ParsePathError};
+use regenerate::parse_mod_line;
+use std::fmt::{self, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use types::TypeStruct;
+use utils::write_atomically;
+use vfs::{DiskFs, FileSystem};
+
+/// The files a [`Section`] applies to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FileSelector {
+    /// `<!-- file * -->`: the entries go into the first file containing their item.
+    All,
+    /// A file, relative to the directory to work on.
+    File(String),
+}
+
+impl FileSelector {
+    /// Returns `true` if the section applies to `path`.
+    pub fn matches(&self, path: &str) -> bool {
+        match *self {
+            FileSelector::All => true,
+            FileSelector::File(ref f) => f == path,
+        }
+    }
+
+    pub fn file(&self) -> Option<&str> {
+        match *self {
+            FileSelector::All => None,
+            FileSelector::File(ref f) => Some(f),
+        }
+    }
+}
+
+impl Display for FileSelector {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.file().unwrap_or("*"))
+    }
+}
+
+impl<'a> From<&'a str> for FileSelector {
+    fn from(s: &'a str) -> FileSelector {
+        if s == "*" {
+            FileSelector::All
+        } else {
+            FileSelector::File(s.to_owned())
+        }
+    }
+}
+
+/// What a [`DocEntry`] documents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EntryKind {
+    /// `<!-- path -->`: the outer doc comments of the item (`///` or `/** */`).
+    Item,
+    /// `<!-- file_comment [path] -->`: the inner doc comments (`//!` or `/*! */`) of the item, or
+    /// of the file if there is no path.
+    FileComment,
+}
+
+/// The doc comments of an item. Entries are equal if they have the same path, kind and lines,
+/// however their header has been written in the comments file and whatever their signature.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DocEntry {
+    /// The item, `None` for the inner doc comments of the file.
+    pub path: Option<TypeStruct>,
+    pub kind: EntryKind,
+    /// The lines of the doc comments, without the comment markers.
+    pub lines: Vec<String>,
+    /// The hash of the declaration of the item (see
+    /// [`StripOptions::signatures`](::StripOptions::signatures)), written as ` @<hex>` at the end
+    /// of the header.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub signature: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    header: Header,
+    /// The line endings of the header and of the lines as they were read.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    endings: Endings,
+}
+
+impl PartialEq for DocEntry {
+    fn eq(&self, other: &DocEntry) -> bool {
+        self.path == other.path && self.kind == other.kind && self.lines == other.lines
+    }
+}
+
+impl Eq for DocEntry {}
+
+impl Hash for DocEntry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        self.kind.hash(state);
+        self.lines.hash(state);
+    }
+}
+
+/// The header line of an entry as it was read, to write it back as is.
+#[derive(Clone, Debug, Default)]
+enum Header {
+    #[default]
+    New,
+    Read(String),
+    /// Lines at the start of a section without header, used as the file comment.
+    Missing,
+}
+
+impl DocEntry {
+    pub fn new(path: Option<TypeStruct>, kind: EntryKind, lines: Vec<String>) -> DocEntry {
+        DocEntry {
+            path,
+            kind,
+            lines,
+            signature: None,
+            header: Header::New,
+            endings: Endings::default(),
+        }
+    }
+
+    /// Returns `true` if the entry is about this `kind` of doc comments of `path`.
+    pub fn is(&self, kind: EntryKind, path: Option<&TypeStruct>) -> bool {
+        self.kind == kind && self.path.as_ref() == path
+    }
+
+    /// Writes the header line, returns its line ending (`None` if there is no header).
+    fn write_header(&self, out: &mut String, line_ending: &'static str) -> Option<&'static str> {
+        match self.header {
+            Header::Read(ref h)
+                if parse_header(h).is_ok_and(|h| {
+                    h.is_some_and(|(k, p, s)| self.is(k, p.as_ref()) && s == self.signature)
+                }) =>
+            {
+                out.push_str(h);
+            }
+            Header::Missing if self.is(EntryKind::FileComment, None) => return None,
+            _ => {
+                out.push_str(match self.kind {
+                    EntryKind::Item => MOD_COMMENT,
+                    EntryKind::FileComment => FILE_COMMENT,
+                });
+                match (self.kind, &self.path) {
+                    (EntryKind::Item, Some(p)) => out.push_str(&format!("{:?}", p)),
+                    (EntryKind::FileComment, Some(p)) => out.push_str(&format!(" {:?}", p)),
+                    (_, None) => {}
+                }
+                if let Some(signature) = self.signature {
+                    out.push_str(&format!("{}{:016x}", SIGNATURE, signature));
+                }
+                out.push_str(END_INFO);
+            }
+        }
+        let ending = self.endings.header.unwrap_or(line_ending);
+        out.push_str(ending);
+        Some(ending)
+    }
+
+    /// Writes the lines, returns the line ending of the last one (`None` if there are none).
+    fn write_lines(&self, out: &mut String, line_ending: &'static str) -> Option<&'static str> {
+        let mut last = None;
+        for (pos, line) in self.lines.iter().enumerate() {
+            let ending = self.endings.lines.get(pos).cloned().unwrap_or(line_ending);
+            out.push_str(line);
+            out.push_str(ending);
+            last = Some(ending);
+        }
+        last
+    }
+}
+
+/// Line endings as they were read, `None` or missing ones being the one of the file.
+#[derive(Clone, Debug, Default)]
+struct Endings {
+    header: Option<&'static str>,
+    lines: Vec<&'static str>,
+}
+
+/// The entries of a file (or of all files). Sections are equal if they have the same file and
+/// entries, however their header has been written in the comments file.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Section {
+    pub file: FileSelector,
+    pub entries: Vec<DocEntry>,
+    /// The header line as it was read and its line ending.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    header: Option<(String, &'static str)>,
+}
+
+impl PartialEq for Section {
+    fn eq(&self, other: &Section) -> bool {
+        self.file == other.file && self.entries == other.entries
+    }
+}
+
+impl Eq for Section {}
+
+impl Hash for Section {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.file.hash(state);
+        self.entries.hash(state);
+    }
+}
+
+impl Section {
+    pub fn new(file: FileSelector) -> Section {
+        Section {
+            file,
+            entries: vec![],
+            header: None,
+        }
+    }
+
+    /// Writes the header line, returns its line ending.
+    fn write_header(&self, out: &mut String, line_ending: &'static str) -> &'static str {
+        match self.header {
+            Some((ref h, ending)) if FileSelector::from(file_of_header(h)) == self.file => {
+                out.push_str(h);
+                out.push_str(ending);
+                ending
+            }
+            _ => {
+                out.push_str(FILE);
+                out.push_str(&self.file.to_string());
+                out.push_str(END_INFO);
+                out.push_str(line_ending);
+                line_ending
+            }
+        }
+    }
+
+    pub fn get(&self, kind: EntryKind, path: Option<&TypeStruct>) -> Option<&DocEntry> {
+        self.entries.iter().find(|e| e.is(kind, path))
+    }
+}
+
+/// A comments file. Parsing it and writing it back gives the same content: the headers and the
+/// line endings are kept as they were read, the line ending of the first line being used for the
+/// new lines.
+#[derive(Clone, Debug)]
+pub struct CommentsFile {
+    pub sections: Vec<Section>,
+    line_ending: &'static str,
+    final_newline: bool,
+}
+
+impl Default for CommentsFile {
+    fn default() -> CommentsFile {
+        CommentsFile {
+            sections: vec![],
+            line_ending: "\n",
+            final_newline: true,
+        }
+    }
+}
+
+type ParsedHeader = (EntryKind, Option<TypeStruct>, Option<u64>);
+
+/// Returns the file selector of the section header `line`: what is between `<!-- file` and
+/// `-->`, anything after it being ignored.
+fn file_of_header(line: &str) -> &str {
+    let file = line.strip_prefix(FILE).unwrap_or(line);
+    match file.find(END_INFO) {
+        Some(end) => &file[..end],
+        None => file,
+    }
+}
+
+/// Returns the kind, the path and the signature of the entry header `line`, `None` if it isn't
+/// one.
+fn parse_header(line: &str) -> Result<Option<ParsedHeader>, ParsePathError> {
+    let kind = if line.starts_with(FILE_COMMENT) {
+        EntryKind::FileComment
+    } else if line.starts_with(MOD_COMMENT) && !line.starts_with(FILE) {
+        EntryKind::Item
+    } else {
+        return Ok(None);
+    };
+    let signed = line
+        .strip_suffix(END_INFO)
+        .and_then(|l| l.rsplit_once(SIGNATURE))
+        .filter(|&(_, s)| s.len() == 16)
+        .and_then(|(l, s)| u64::from_str_radix(s, 16).ok().map(|s| (l, s)));
+    match signed {
+        Some((line, signature)) => {
+            parse_mod_line(line).map(|path| Some((kind, path, Some(signature))))
+        }
+        None => parse_mod_line(line).map(|path| Some((kind, path, None))),
+    }
+}
+
+impl CommentsFile {
+    pub fn new() -> CommentsFile {
+        CommentsFile::default()
+    }
+
+    /// Parses the content of a comments file.
+    ///
+    /// The `path` of the returned error is empty since it isn't known here.
+    pub fn parse(content: &str) -> Result<CommentsFile, FileError> {
+        let mut ret = CommentsFile::new();
+        if content.is_empty() {
+            return Ok(ret);
+        }
+        let content = match content.strip_suffix('\n') {
+            Some(c) => c,
+            None => {
+                ret.final_newline = false;
+                content
+            }
+        };
+        if content
+            .split('\n')
+            .next()
+            .is_some_and(|l| l.ends_with('\r'))
+        {
+            ret.line_ending = "\r\n";
+        }
+        let last = content.matches('\n').count();
+        for (pos, line) in content.split('\n').enumerate() {
+            // Without final newline, a `\r` ending the last line is a part of it.
+            let (line, ending) = match line.strip_suffix('\r') {
+                Some(line) if pos < last || ret.final_newline => (line, "\r\n"),
+                _ => (line, "\n"),
+            };
+            if line.starts_with(FILE) {
+                let mut section = Section::new(FileSelector::from(file_of_header(line)));
+                section.header = Some((line.to_owned(), ending));
+                ret.sections.push(section);
+                continue;
+            }
+            let section = match ret.sections.last_mut() {
+                Some(s) => s,
+                None => {
+                    return Err(FileError::new(
+                        "",
+                        Some(pos + 1),
+                        format!("Unrecognized format on line: `{}`", line),
+                    ))
+                }
+            };
+            let header = parse_header(line).map_err(|e| {
+                FileError::new("", Some(pos + 1), format!("Invalid item path: {}", e))
+            })?;
+            if let Some((kind, path, signature)) = header {
+                section.entries.push(DocEntry {
+                    signature,
+                    header: Header::Read(line.to_owned()),
+                    endings: Endings {
+                        header: Some(ending),
+                        lines: vec![],
+                    },
+                    ..DocEntry::new(path, kind, vec![])
+                });
+            } else {
+                if section.entries.is_empty() {
+                    section.entries.push(DocEntry {
+                        header: Header::Missing,
+                        ..DocEntry::new(None, EntryKind::FileComment, vec![])
+                    });
+                }
+                let entry = section.entries.last_mut().unwrap();
+                entry.lines.push(line.to_owned());
+                entry.endings.lines.push(ending);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Reads the comments file `path`.
+    pub fn read(path: &Path) -> Result<CommentsFile, FileError> {
+        CommentsFile::read_from(&DiskFs, path)
+    }
+
+    /// Same as [`CommentsFile::read`] but reads `path` from `fs`.
+    pub fn read_from(fs: &dyn FileSystem, path: &Path) -> Result<CommentsFile, FileError> {
+        let path_str = path.display().to_string();
+        let content = fs
+            .read_to_string(path)
+            .map_err(|e| FileError::new(&path_str, None, e.to_string()))?;
+        CommentsFile::parse(&content).map_err(|e| FileError {
+            path: path_str,
+            ..e
+        })
+    }
+
+    /// Writes the comments file into `path`.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        write_atomically(path, self.to_string().as_bytes())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.iter().all(|s| s.entries.is_empty())
+    }
+
+    /// Returns the first section applying to `file`.
+    pub fn section(&self, file: &FileSelector) -> Option<&Section> {
+        self.sections.iter().find(|s| s.file == *file)
+    }
+
+    /// Returns the entry of `file` documenting this `kind` of doc comments of `path`.
+    pub fn get(
+        &self,
+        file: &FileSelector,
+        kind: EntryKind,
+        path: Option<&TypeStruct>,
+    ) -> Option<&DocEntry> {
+        self.sections
+            .iter()
+            .filter(|s| s.file == *file)
+            .find_map(|s| s.get(kind, path))
+    }
+
+    /// Adds `entry` to the section of `file` (created if needed). If the section already has an
+    /// entry for the same item and kind, it's replaced and returned.
+    pub fn insert(&mut self, file: FileSelector, entry: DocEntry) -> Option<DocEntry> {
+        for section in self.sections.iter_mut().filter(|s| s.file == file) {
+            if let Some(e) = section
+                .entries
+                .iter_mut()
+                .find(|e| e.is(entry.kind, entry.path.as_ref()))
+            {
+                return Some(std::mem::replace(e, entry));
+            }
+        }
+        match self.sections.iter_mut().find(|s| s.file == file) {
+            Some(section) => section.entries.push(entry),
+            None => {
+                let mut section = Section::new(file);
+                section.entries.push(entry);
+                self.sections.push(section);
+            }
+        }
+        None
+    }
+
+    /// Removes the entry of `file` documenting this `kind` of doc comments of `path`. The section
+    /// is removed if it becomes empty.
+    pub fn remove(
+        &mut self,
+        file: &FileSelector,
+        kind: EntryKind,
+        path: Option<&TypeStruct>,
+    ) -> Option<DocEntry> {
+        for (i, section) in self.sections.iter_mut().enumerate() {
+            if section.file != *file {
+                continue;
+            }
+            if let Some(pos) = section.entries.iter().position(|e| e.is(kind, path)) {
+                let entry = section.entries.remove(pos);
+                if section.entries.is_empty() {
+                    self.sections.remove(i);
+                }
+                return Some(entry);
+            }
+        }
+        None
+    }
+
+    /// Iterates over the entries, with the files they apply to, in the order of the file.
+    pub fn entries(&self) -> impl Iterator<Item = (&FileSelector, &DocEntry)> {
+        self.sections
+            .iter()
+            .flat_map(|s| s.entries.iter().map(move |e| (&s.file, e)))
+    }
+
+    /// Adds the entries of `other`, replacing the ones for the same item, kind and file.
+    pub fn merge(&mut self, other: CommentsFile) {
+        for section in other.sections {
+            for entry in section.entries {
+                self.insert(section.file.clone(), entry);
+            }
+        }
+    }
+}
+
+impl FromStr for CommentsFile {
+    type Err = FileError;
+
+    fn from_str(s: &str) -> Result<CommentsFile, FileError> {
+        CommentsFile::parse(s)
+    }
+}
+
+impl Display for CommentsFile {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut out = String::new();
+        let mut last = "";
+        for section in &self.sections {
+            last = section.write_header(&mut out, self.line_ending);
+            for entry in &section.entries {
+                last = entry
+                    .write_header(&mut out, self.line_ending)
+                    .unwrap_or(last);
+                last = entry
+                    .write_lines(&mut out, self.line_ending)
+                    .unwrap_or(last);
+            }
+        }
+        if !self.final_newline {
+            let len = out.len() - last.len();
+            out.truncate(len);
+        }
+        f.write_str(&out)
+    }
+}
@@ -0,0 +1,304 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use consts::CONFIG_FILE;
+use error::FileError;
+use pattern::Pattern;
+use regenerate::OnConflict;
+use selector::{DocKind, Selector};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml::de::{DeTable, DeValue};
+use toml::Spanned;
+use types::Visibility;
+
+/// How reports are displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "Unknown format '{}', expected one of: text, json",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings read from a `rustdoc-stripper.toml` file or from the
+/// `[package.metadata.rustdoc-stripper]` table of a `Cargo.toml` file.
+///
+/// Settings which aren't in the file are `None`. Paths are relative to the directory of the
+/// file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    /// File the settings have been read from.
+    pub path: PathBuf,
+    /// Directory to work on.
+    pub dir: Option<PathBuf>,
+    pub comment_file: Option<PathBuf>,
+    /// Patterns of the files to ignore, relative to the directory to work on.
+    pub ignore: Option<Vec<String>>,
+    /// Patterns of the files to go through, relative to the directory to work on.
+    pub include: Option<Vec<String>>,
+    /// Selectors of the items whose doc comments are stripped/regenerated.
+    pub only: Option<Vec<String>>,
+    /// Selectors of the items whose doc comments are left where they are.
+    pub exclude: Option<Vec<String>>,
+    /// Kinds of the doc comments which are stripped/regenerated.
+    pub only_kinds: Option<Vec<DocKind>>,
+    /// Kinds of the doc comments which are left where they are.
+    pub exclude_kinds: Option<Vec<DocKind>>,
+    /// Visibilities of the items whose doc comments are stripped/regenerated/counted.
+    pub visibility: Option<Vec<Visibility>>,
+    pub gitignore: Option<bool>,
+    pub skip_target: Option<bool>,
+    pub follow_links: Option<bool>,
+    pub ignore_macros: Option<bool>,
+    pub on_conflict: Option<OnConflict>,
+    pub format: Option<Format>,
+    pub jobs: Option<usize>,
+    /// Minimum documentation coverage, in percent.
+    pub fail_under: Option<f64>,
+    pub backup: Option<bool>,
+    pub skip_unaffected: Option<bool>,
+    pub fuzzy: Option<bool>,
+    pub signatures: Option<bool>,
+    pub prune_comment_file: Option<bool>,
+}
+
+impl Config {
+    /// Looks for the configuration in `start` and then in its parents: the first directory which
+    /// contains either a `rustdoc-stripper.toml` file or a `Cargo.toml` file with a
+    /// `[package.metadata.rustdoc-stripper]` table is used (the former having priority).
+    pub fn find(start: &Path) -> Result<Option<Config>, FileError> {
+        let start = fs::canonicalize(start).unwrap_or_else(|_| start.to_path_buf());
+        for dir in start.ancestors() {
+            let path = dir.join(CONFIG_FILE);
+            if path.is_file() {
+                return Config::load(&path).map(Some);
+            }
+            let path = dir.join("Cargo.toml");
+            if path.is_file() {
+                if let Some(config) = Config::load_cargo(&path)? {
+                    return Ok(Some(config));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads a `rustdoc-stripper.toml` file.
+    pub fn load(path: &Path) -> Result<Config, FileError> {
+        let content = read(path)?;
+        Config::parse(&content, path)
+    }
+
+    /// Reads the `[package.metadata.rustdoc-stripper]` table of a `Cargo.toml` file, if any.
+    pub fn load_cargo(path: &Path) -> Result<Option<Config>, FileError> {
+        let content = read(path)?;
+        let manifest = parse_toml(&content, path)?;
+        let settings = manifest
+            .get("package")
+            .and_then(|p| p.get_ref().get("metadata"))
+            .and_then(|m| m.get_ref().get("rustdoc-stripper"));
+        match settings {
+            Some(settings) => match settings.get_ref().as_table() {
+                Some(table) => Config::from_settings(&content, table, path).map(Some),
+                None => Err(FileError::new(
+                    &path.display().to_string(),
+                    Some(line_of(&content, settings.span().start)),
+                    "`package.metadata.rustdoc-stripper` expects a table".to_owned(),
+                )),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Parses the settings of `content`, the content of the `rustdoc-stripper.toml` file `path`.
+    pub fn parse(content: &str, path: &Path) -> Result<Config, FileError> {
+        let settings = parse_toml(content, path)?;
+        Config::from_settings(content, &settings, path)
+    }
+
+    fn from_settings(content: &str, settings: &DeTable, path: &Path) -> Result<Config, FileError> {
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let err = |line: usize, message: String| {
+            FileError::new(&path.display().to_string(), Some(line), message)
+        };
+        let mut config = Config {
+            path: path.to_path_buf(),
+            ..Config::default()
+        };
+        for (key, value) in settings {
+            let key: &str = key.get_ref();
+            let line = line_of(content, value.span().start);
+            let value = value.get_ref();
+            let expect = |what: &str| err(line, format!("`{}` expects {}", key, what));
+            match key {
+                "dir" => {
+                    config.dir = Some(base.join(string(value).ok_or_else(|| expect("a string"))?))
+                }
+                "comment-file" => {
+                    config.comment_file =
+                        Some(base.join(string(value).ok_or_else(|| expect("a string"))?))
+                }
+                "ignore" | "include" => {
+                    let patterns = strings(value).ok_or_else(|| expect("an array of strings"))?;
+                    for pattern in &patterns {
+                        Pattern::new(pattern).map_err(|e| err(line, e))?;
+                    }
+                    if key == "ignore" {
+                        config.ignore = Some(patterns);
+                    } else {
+                        config.include = Some(patterns);
+                    }
+                }
+                "only" | "exclude" => {
+                    let selectors = strings(value).ok_or_else(|| expect("an array of strings"))?;
+                    for selector in &selectors {
+                        Selector::new(selector).map_err(|e| err(line, e))?;
+                    }
+                    if key == "only" {
+                        config.only = Some(selectors);
+                    } else {
+                        config.exclude = Some(selectors);
+                    }
+                }
+                "only-kind" | "exclude-kind" => {
+                    let kinds = strings(value)
+                        .ok_or_else(|| expect("an array of strings"))?
+                        .iter()
+                        .map(|k| k.parse())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| err(line, e))?;
+                    if key == "only-kind" {
+                        config.only_kinds = Some(kinds);
+                    } else {
+                        config.exclude_kinds = Some(kinds);
+                    }
+                }
+                "visibility" => {
+                    config.visibility = Some(
+                        strings(value)
+                            .ok_or_else(|| expect("an array of strings"))?
+                            .iter()
+                            .map(|v| v.parse())
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(|e| err(line, e))?,
+                    );
+                }
+                "on-conflict" => {
+                    let policy = string(value).ok_or_else(|| expect("a string"))?;
+                    config.on_conflict = Some(policy.parse().map_err(|e| err(line, e))?);
+                }
+                "format" => {
+                    let format = string(value).ok_or_else(|| expect("a string"))?;
+                    config.format = Some(format.parse().map_err(|e| err(line, e))?);
+                }
+                "jobs" => match integer(value) {
+                    Some(n) if n >= 0 => config.jobs = Some(n as usize),
+                    _ => return Err(expect("a positive integer")),
+                },
+                "fail-under" => match number(value) {
+                    Some(n) if (0.0..=100.0).contains(&n) => config.fail_under = Some(n),
+                    _ => return Err(expect("a number between 0 and 100")),
+                },
+                "ignore-macros" | "backup" | "skip-unaffected" | "fuzzy" | "signatures"
+                | "prune-comment-file" | "gitignore" | "skip-target" | "follow-links" => {
+                    let b = match value.as_bool() {
+                        Some(b) => Some(b),
+                        None => return Err(expect("a boolean")),
+                    };
+                    match key {
+                        "ignore-macros" => config.ignore_macros = b,
+                        "backup" => config.backup = b,
+                        "skip-unaffected" => config.skip_unaffected = b,
+                        "fuzzy" => config.fuzzy = b,
+                        "signatures" => config.signatures = b,
+                        "gitignore" => config.gitignore = b,
+                        "skip-target" => config.skip_target = b,
+                        "follow-links" => config.follow_links = b,
+                        _ => config.prune_comment_file = b,
+                    }
+                }
+                _ => return Err(err(line, format!("Unknown setting `{}`", key))),
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn read(path: &Path) -> Result<String, FileError> {
+    fs::read_to_string(path).map_err(|e| {
+        FileError::new(
+            &path.display().to_string(),
+            None,
+            format!("Unable to read file: {}", e),
+        )
+    })
+}
+
+/// Parses the TOML `content` of the file `path`.
+pub(crate) fn parse_toml<'a>(content: &'a str, path: &Path) -> Result<DeTable<'a>, FileError> {
+    DeTable::parse(content)
+        .map(Spanned::into_inner)
+        .map_err(|e| {
+            FileError::new(
+                &path.display().to_string(),
+                e.span().map(|span| line_of(content, span.start)),
+                e.message().to_owned(),
+            )
+        })
+}
+
+/// Returns the line (starting at 1) of the byte `pos` of `content`.
+pub(crate) fn line_of(content: &str, pos: usize) -> usize {
+    content[..pos.min(content.len())].matches('\n').count() + 1
+}
+
+pub(crate) fn string(value: &DeValue) -> Option<String> {
+    value.as_str().map(str::to_owned)
+}
+
+pub(crate) fn strings(value: &DeValue) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|v| string(v.get_ref()))
+        .collect()
+}
+
+fn integer(value: &DeValue) -> Option<i64> {
+    let n = value.as_integer()?;
+    i64::from_str_radix(n.as_str(), n.radix()).ok()
+}
+
+/// Returns the value of an integer or a float.
+fn number(value: &DeValue) -> Option<f64> {
+    match value.as_float() {
+        Some(n) => n.as_str().parse().ok(),
+        None => integer(value).map(|n| n as f64),
+    }
+}
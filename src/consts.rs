@@ -16,4 +16,8 @@ pub const MOD_COMMENT: &str = "<!-- ";
 pub const FILE_COMMENT: &str = "<!-- file_comment";
 pub const FILE: &str = "<!-- file ";
 pub const END_INFO: &str = " -->";
+pub const SIGNATURE: &str = " @";
 pub const OUTPUT_COMMENT_FILE: &str = "comments.md";
+pub const BACKUP_DIR: &str = ".rustdoc-stripper";
+pub const CACHE_FILE: &str = ".rustdoc-stripper-cache";
+pub const CONFIG_FILE: &str = "rustdoc-stripper.toml";
@@ -0,0 +1,391 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use comments::CommentsFile;
+use diagnostics::{Diagnostics, Sink};
+use error::FileError;
+use filter::FileFilter;
+use regenerate::{file_items, infos_of};
+use report::json_string;
+use strip::build_event_list;
+use types::{Type, TypeStruct, Visibility};
+use utils::{list_files_of, parallel_map};
+use vfs::DiskFs;
+
+/// Settings used when computing the documentation coverage, see [`collect_coverage`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageOptions {
+    pub(crate) verbose: bool,
+    pub(crate) ignore_macros: bool,
+    pub(crate) jobs: usize,
+    pub(crate) filter: FileFilter,
+    pub(crate) visibility: Vec<Visibility>,
+    pub(crate) comment_file: Option<PathBuf>,
+    pub(crate) diagnostics: Sink,
+}
+
+impl Default for CoverageOptions {
+    fn default() -> CoverageOptions {
+        CoverageOptions {
+            verbose: false,
+            ignore_macros: false,
+            jobs: 1,
+            filter: FileFilter::new(),
+            visibility: vec![],
+            comment_file: None,
+            diagnostics: Sink::default(),
+        }
+    }
+}
+
+impl CoverageOptions {
+    pub fn new() -> CoverageOptions {
+        CoverageOptions::default()
+    }
+
+    /// Prints the files which are gone through.
+    pub fn verbose(mut self, verbose: bool) -> CoverageOptions {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Sends the diagnostics of the run (the files gone through when verbose, the warnings...)
+    /// to `sink` instead of printing them on the standard error output.
+    pub fn diagnostics(mut self, sink: Arc<dyn Diagnostics>) -> CoverageOptions {
+        self.diagnostics = Sink::new(sink);
+        self
+    }
+
+    /// Macros in hierarchy are ignored, as when the comments file has been written with this
+    /// setting.
+    pub fn ignore_macros(mut self, ignore_macros: bool) -> CoverageOptions {
+        self.ignore_macros = ignore_macros;
+        self
+    }
+
+    /// Number of threads processing the files, `0` meaning one per available CPU.
+    pub fn jobs(mut self, jobs: usize) -> CoverageOptions {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Which files of the directory to work on are gone through, all the Rust files by default.
+    pub fn filter(mut self, filter: FileFilter) -> CoverageOptions {
+        self.filter = filter;
+        self
+    }
+
+    /// If not empty, only the items having one of these visibilities (see
+    /// [`TypeStruct::effective_visibility`]) are counted.
+    pub fn visibility(mut self, visibility: Vec<Visibility>) -> CoverageOptions {
+        self.visibility = visibility;
+        self
+    }
+
+    /// The items having an entry in this comments file (which isn't relative to the directory to
+    /// work on) are documented as well, so the coverage of a stripped directory can be computed
+    /// without regenerating it.
+    pub fn comment_file(mut self, comment_file: Option<PathBuf>) -> CoverageOptions {
+        self.comment_file = comment_file;
+        self
+    }
+}
+
+/// How many items there are and how many of them have doc comments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Counts {
+    pub items: usize,
+    pub documented: usize,
+}
+
+impl Counts {
+    /// The percentage of documented items, `100` if there are no items.
+    pub fn percent(&self) -> f64 {
+        if self.items == 0 {
+            100.
+        } else {
+            self.documented as f64 * 100. / self.items as f64
+        }
+    }
+
+    fn add(&mut self, documented: bool) {
+        self.items += 1;
+        if documented {
+            self.documented += 1;
+        }
+    }
+
+    fn to_json(self) -> String {
+        format!(
+            "\"items\": {}, \"documented\": {}",
+            self.items, self.documented
+        )
+    }
+}
+
+impl Display for Counts {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.documented, self.items)
+    }
+}
+
+/// The documentation coverage of a group of items, in total and by kind.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Coverage {
+    pub counts: Counts,
+    /// Fields are counted as [`Type::Variant`]s.
+    pub kinds: BTreeMap<Type, Counts>,
+}
+
+impl Coverage {
+    fn add(&mut self, ty: Type, documented: bool) {
+        self.counts.add(documented);
+        self.kinds.entry(ty).or_default().add(documented);
+    }
+
+    fn merge(&mut self, other: &Coverage) {
+        self.counts.items += other.counts.items;
+        self.counts.documented += other.counts.documented;
+        for (ty, counts) in &other.kinds {
+            let c = self.kinds.entry(*ty).or_default();
+            c.items += counts.items;
+            c.documented += counts.documented;
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let kinds = self
+            .kinds
+            .iter()
+            .map(|(ty, counts)| format!("\"{}\": {{{}}}", ty, counts.to_json()))
+            .collect::<Vec<_>>();
+        format!(
+            "{}, \"kinds\": {{{}}}",
+            self.counts.to_json(),
+            kinds.join(", ")
+        )
+    }
+}
+
+/// The documentation coverage of a file.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FileCoverage {
+    /// Path of the file, relative to the working directory.
+    pub path: String,
+    /// Coverage of all the items of the file.
+    pub coverage: Coverage,
+    /// Coverage of the items of each inline module of the file (`mod a::mod b`), in the order of
+    /// appearance. The items of a module aren't counted in the ones of its parent modules.
+    pub modules: Vec<(String, Coverage)>,
+}
+
+impl FileCoverage {
+    fn add(&mut self, item: &TypeStruct, documented: bool) {
+        self.coverage.add(item.ty, documented);
+        let mut parent = item.parent.as_deref();
+        while let Some(p) = parent {
+            if p.ty == Type::Mod {
+                let name = p.to_string();
+                let pos = match self.modules.iter().position(|(m, _)| *m == name) {
+                    Some(pos) => pos,
+                    None => {
+                        self.modules.push((name, Coverage::default()));
+                        self.modules.len() - 1
+                    }
+                };
+                self.modules[pos].1.add(item.ty, documented);
+                return;
+            }
+            parent = p.parent.as_deref();
+        }
+    }
+}
+
+/// The documentation coverage of the files of a directory.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CoverageReport {
+    pub files: Vec<FileCoverage>,
+    pub total: Coverage,
+}
+
+impl CoverageReport {
+    pub fn to_json(&self) -> String {
+        let mut out = format!(
+            "{{\n  \"total\": {{{}}},\n  \"files\": [",
+            self.total.to_json()
+        );
+        for (pos, file) in self.files.iter().enumerate() {
+            if pos > 0 {
+                out.push(',');
+            }
+            let modules = file
+                .modules
+                .iter()
+                .map(|(name, coverage)| {
+                    format!(
+                        "\n        {{\"module\": {}, {}}}",
+                        json_string(Some(name)),
+                        coverage.to_json()
+                    )
+                })
+                .collect::<Vec<_>>();
+            out.push_str(&format!(
+                "\n    {{\n      \"file\": {},\n      {},\n      \"modules\": [{}{}]\n    }}",
+                json_string(Some(&file.path)),
+                file.coverage.to_json(),
+                modules.join(","),
+                if modules.is_empty() { "" } else { "\n      " }
+            ));
+        }
+        if !self.files.is_empty() {
+            out.push_str("\n  ");
+        }
+        out.push_str("]\n}\n");
+        out
+    }
+}
+
+/// Displays a line per file and per module (indented below its file) and then the total, each
+/// one with its documented items out of its items, the percentage and the details by kind.
+impl Display for CoverageReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut rows = vec![];
+        for file in &self.files {
+            rows.push((file.path.clone(), &file.coverage));
+            for (name, coverage) in &file.modules {
+                rows.push((format!("  {}", name), coverage));
+            }
+        }
+        rows.push(("Total".to_owned(), &self.total));
+        let name_width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0);
+        let counts_width = rows
+            .iter()
+            .map(|r| r.1.counts.to_string().len())
+            .max()
+            .unwrap_or(0);
+        for (pos, (name, coverage)) in rows.iter().enumerate() {
+            if pos > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "{:<nw$}  {:>cw$}  {:>5.1}%",
+                name,
+                coverage.counts.to_string(),
+                coverage.counts.percent(),
+                nw = name_width,
+                cw = counts_width
+            )?;
+            let kinds = coverage
+                .kinds
+                .iter()
+                .map(|(ty, counts)| format!("{} {}", ty, counts))
+                .collect::<Vec<_>>();
+            if !kinds.is_empty() {
+                write!(f, "  ({})", kinds.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if `item` is inside a function, rustdoc doesn't document it.
+fn in_fn(item: &TypeStruct) -> bool {
+    let mut parent = item.parent.as_deref();
+    while let Some(p) = parent {
+        if p.ty == Type::Fn {
+            return true;
+        }
+        parent = p.parent.as_deref();
+    }
+    false
+}
+
+/// Computes the documentation coverage of the files of `directory` kept by the filter of
+/// `options`. An item is documented if it has doc comments in its file or an entry in the
+/// comments file of `options`, if any. The items inside functions aren't counted.
+pub fn collect_coverage(
+    directory: &Path,
+    options: &CoverageOptions,
+) -> Result<CoverageReport, Vec<FileError>> {
+    let infos = match options.comment_file {
+        Some(ref path) => infos_of(
+            CommentsFile::read(path).map_err(|e| vec![e])?,
+            options.ignore_macros,
+        ),
+        None => Default::default(),
+    };
+    let entries = |file: Option<&str>| {
+        infos
+            .get(&file.map(|f| f.to_owned()))
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.0.as_ref())
+            .collect::<Vec<_>>()
+    };
+    let everywhere = entries(None);
+    // The files which could be listed are gone through anyway, so that all errors are reported.
+    let (files, mut errors) = list_files_of(
+        &DiskFs,
+        &options.diagnostics,
+        directory,
+        &options.filter,
+        options.verbose,
+    );
+    let mut report = CoverageReport::default();
+    for (path, ret) in files.iter().zip(parallel_map(&files, options.jobs, |path| {
+        build_event_list(&directory.join(path)).map(|p| file_items(&p, options.ignore_macros))
+    })) {
+        let items = match ret {
+            Ok(items) => items,
+            Err(e) => {
+                errors.push(FileError::new(
+                    path,
+                    None,
+                    format!("Unable to read file: {}", e),
+                ));
+                continue;
+            }
+        };
+        let in_comment_file = entries(Some(path))
+            .into_iter()
+            .chain(everywhere.iter().cloned())
+            .collect::<HashSet<_>>();
+        let mut file = FileCoverage {
+            path: path.clone(),
+            ..FileCoverage::default()
+        };
+        for (item, documented) in items {
+            if item.ty != Type::Unknown
+                && !in_fn(&item)
+                && (options.visibility.is_empty()
+                    || options.visibility.contains(&item.effective_visibility()))
+            {
+                file.add(&item, documented || in_comment_file.contains(&item));
+            }
+        }
+        report.total.merge(&file.coverage);
+        report.files.push(file);
+    }
+    if errors.is_empty() {
+        Ok(report)
+    } else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use error::FileError;
+use report::json_string;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Progress of the run, what is done with the files when in verbose mode.
+    Info,
+    /// Something which may not be what was expected but doesn't prevent the run.
+    Warning,
+    /// Something which prevents a file from being processed.
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A message about the run, optionally about a file (and a line of it).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Path of the file, relative to the directory to work on when walking through it.
+    pub path: Option<String>,
+    /// Line (starting at 1) the message is about, if any.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(severity: Severity, message: S) -> Diagnostic {
+        Diagnostic {
+            severity,
+            path: None,
+            line: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn info<S: Into<String>>(message: S) -> Diagnostic {
+        Diagnostic::new(Severity::Info, message)
+    }
+
+    pub fn warning<S: Into<String>>(message: S) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message)
+    }
+
+    pub fn error<S: Into<String>>(message: S) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message)
+    }
+
+    pub fn with_path<S: Into<String>>(mut self, path: S) -> Diagnostic {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_line(mut self, line: usize) -> Diagnostic {
+        self.line = Some(line);
+        self
+    }
+
+    /// Returns the diagnostic as a JSON object on one line.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"severity\": \"{}\", \"path\": {}, \"line\": {}, \"message\": {}}}",
+            self.severity,
+            json_string(self.path.as_ref().map(|s| &s[..])),
+            self.line
+                .map(|l| l.to_string())
+                .unwrap_or_else(|| "null".to_owned()),
+            json_string(Some(&self.message))
+        )
+    }
+}
+
+impl<'a> From<&'a FileError> for Diagnostic {
+    fn from(e: &'a FileError) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            path: Some(e.path.clone()),
+            line: e.line,
+            message: e.message.clone(),
+        }
+    }
+}
+
+impl From<FileError> for Diagnostic {
+    fn from(e: FileError) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            path: Some(e.path),
+            line: e.line,
+            message: e.message,
+        }
+    }
+}
+
+/// Displays the diagnostic as it is printed by default: warnings and errors are prefixed with
+/// their severity and verbose messages about a file with `->`.
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Info if self.path.is_some() => write!(f, "-> ")?,
+            Severity::Info => {}
+            s => write!(f, "{}: ", s)?,
+        }
+        if let Some(ref path) = self.path {
+            match self.line {
+                Some(line) => write!(f, "{}:{}", path, line)?,
+                None => write!(f, "{}", path)?,
+            }
+            if self.message.is_empty() {
+                return Ok(());
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Receives the diagnostics of the run. It can be called from several threads at once.
+pub trait Diagnostics: Send + Sync {
+    fn emit(&self, diagnostic: &Diagnostic);
+}
+
+impl<D: Diagnostics + ?Sized> Diagnostics for Arc<D> {
+    fn emit(&self, diagnostic: &Diagnostic) {
+        (**self).emit(diagnostic)
+    }
+}
+
+/// Prints the diagnostics on the standard error output. This is the default sink.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stderr;
+
+impl Diagnostics for Stderr {
+    fn emit(&self, diagnostic: &Diagnostic) {
+        eprintln!("{}", diagnostic);
+    }
+}
+
+/// Keeps the diagnostics in memory.
+#[derive(Debug, Default)]
+pub struct Collector {
+    diagnostics: Mutex<Vec<Diagnostic>>,
+}
+
+impl Collector {
+    pub fn new() -> Collector {
+        Collector::default()
+    }
+
+    /// Returns the diagnostics received so far and forgets them.
+    pub fn take(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.diagnostics.lock().unwrap_or_else(|e| e.into_inner());
+        ::std::mem::take(&mut *diagnostics)
+    }
+}
+
+impl Diagnostics for Collector {
+    fn emit(&self, diagnostic: &Diagnostic) {
+        self.diagnostics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(diagnostic.clone());
+    }
+}
+
+/// The sink the diagnostics of a run are sent to, kept in its options: [`Stderr`] by default.
+#[derive(Clone)]
+pub(crate) struct Sink(Arc<dyn Diagnostics>);
+
+impl Sink {
+    pub(crate) fn new(sink: Arc<dyn Diagnostics>) -> Sink {
+        Sink(sink)
+    }
+}
+
+impl Default for Sink {
+    fn default() -> Sink {
+        Sink(Arc::new(Stderr))
+    }
+}
+
+impl Debug for Sink {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Sink")
+    }
+}
+
+/// Two sinks are equal if they are the same object.
+impl PartialEq for Sink {
+    fn eq(&self, other: &Sink) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Diagnostics for Sink {
+    fn emit(&self, diagnostic: &Diagnostic) {
+        self.0.emit(diagnostic)
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Returns the shortest list of edits turning `a` into `b` (Myers' algorithm).
+fn edit_script(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = n + m;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    // For each `d`, the furthest reaching `x` of the diagonals `-d..=d` before the step.
+    let mut trace = vec![];
+
+    'outer: for d in 0..=max {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        let mut k = -d;
+        while k <= d {
+            let idx = |k: isize| (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                break 'outer;
+            }
+            k += 2;
+        }
+    }
+
+    let mut edits = vec![];
+    let mut x = n;
+    let mut y = m;
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 { 0 } else { get(prev_k) };
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(prev_y as usize));
+            } else {
+                edits.push(Edit::Delete(prev_x as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+fn push_line(out: &mut String, prefix: char, line: &str) {
+    out.push(prefix);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// Returns the differences between `old` and `new` in the unified format, with three lines of
+/// context, using `path` in the headers. Returns an empty string if there are none.
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    let a = old.split_inclusive('\n').collect::<Vec<_>>();
+    let b = new.split_inclusive('\n').collect::<Vec<_>>();
+    let edits = edit_script(&a, &b);
+    let changes = edits
+        .iter()
+        .enumerate()
+        .filter(|&(_, e)| !matches!(*e, Edit::Equal(..)))
+        .map(|(pos, _)| pos)
+        .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let mut pos = 0;
+    while pos < changes.len() {
+        let start = changes[pos].saturating_sub(CONTEXT);
+        let mut end = changes[pos];
+        while pos < changes.len() && changes[pos] <= end + 2 * CONTEXT + 1 {
+            end = changes[pos];
+            pos += 1;
+        }
+        let end = (end + CONTEXT + 1).min(edits.len());
+        let hunk = &edits[start..end];
+
+        // Lines of `a` and `b` before the hunk.
+        let (a_start, b_start) = match edits[start] {
+            Edit::Equal(i, j) => (i, j),
+            Edit::Delete(i) => (i, b_before(&edits[..start])),
+            Edit::Insert(j) => (a_before(&edits[..start]), j),
+        };
+        let a_len = hunk
+            .iter()
+            .filter(|e| !matches!(**e, Edit::Insert(_)))
+            .count();
+        let b_len = hunk
+            .iter()
+            .filter(|e| !matches!(**e, Edit::Delete(_)))
+            .count();
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            if a_len > 0 { a_start + 1 } else { a_start },
+            a_len,
+            if b_len > 0 { b_start + 1 } else { b_start },
+            b_len
+        ));
+        for edit in hunk {
+            match *edit {
+                Edit::Equal(i, _) => push_line(&mut out, ' ', a[i]),
+                Edit::Delete(i) => push_line(&mut out, '-', a[i]),
+                Edit::Insert(j) => push_line(&mut out, '+', b[j]),
+            }
+        }
+    }
+    out
+}
+
+fn a_before(edits: &[Edit]) -> usize {
+    edits
+        .iter()
+        .filter(|e| !matches!(**e, Edit::Insert(_)))
+        .count()
+}
+
+fn b_before(edits: &[Edit]) -> usize {
+    edits
+        .iter()
+        .filter(|e| !matches!(**e, Edit::Delete(_)))
+        .count()
+}
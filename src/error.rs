@@ -0,0 +1,72 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+
+/// An error which prevents a file from being stripped or regenerated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileError {
+    /// Path of the file, as given to the stripping/regenerating functions.
+    pub path: String,
+    /// Line (starting at 1) where the error occurred, if any.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl FileError {
+    pub fn new<S: Into<String>>(path: &str, line: Option<usize>, message: S) -> FileError {
+        FileError {
+            path: path.to_owned(),
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path, line, self.message),
+            None => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+impl error::Error for FileError {}
+
+/// An error met when parsing an item path (see [`TypeStruct`](crate::TypeStruct)).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePathError {
+    /// Position (in bytes) in the parsed string where the error occurred.
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParsePathError {
+    pub fn new<S: Into<String>>(position: usize, message: S) -> ParsePathError {
+        ParsePathError {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ParsePathError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} (at byte {})", self.message, self.position)
+    }
+}
+
+impl error::Error for ParsePathError {}
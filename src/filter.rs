@@ -0,0 +1,225 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::path::{Component, Path};
+use vfs::FileSystem;
+
+use pattern::Pattern;
+
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// Which files of the directory to work on are gone through. Paths are matched relative to this
+/// directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileFilter {
+    /// If not empty, only the files matching one of these patterns (or inside a matching
+    /// directory) are kept.
+    pub include: Vec<Pattern>,
+    /// Files and directories matching one of these patterns are skipped.
+    pub exclude: Vec<Pattern>,
+    /// Skips what the `.gitignore`, `.ignore` and `.git/info/exclude` files ignore.
+    pub gitignore: bool,
+    /// Skips the `target` directories created by Cargo.
+    pub skip_target: bool,
+    /// Goes through symbolic links (every directory only being gone through once) instead of
+    /// skipping them.
+    pub follow_links: bool,
+}
+
+impl Default for FileFilter {
+    fn default() -> FileFilter {
+        FileFilter {
+            include: vec![],
+            exclude: vec![],
+            gitignore: false,
+            skip_target: true,
+            follow_links: false,
+        }
+    }
+}
+
+impl FileFilter {
+    pub fn new() -> FileFilter {
+        FileFilter::default()
+    }
+
+    /// Builds a filter skipping exactly the given paths and nothing else: unlike the default
+    /// one, it goes through the `target` directories and follows symbolic links.
+    pub fn ignoring<S: AsRef<Path>>(files_to_ignore: &[S]) -> FileFilter {
+        FileFilter {
+            exclude: files_to_ignore
+                .iter()
+                .map(|f| Pattern::literal(&slash_path(f.as_ref())))
+                .collect(),
+            skip_target: false,
+            follow_links: true,
+            ..FileFilter::default()
+        }
+    }
+
+    /// Returns `true` if the file `path` isn't excluded and matches the include patterns. The
+    /// `.gitignore` files and the `target` directories aren't taken into account.
+    pub fn keeps(&self, path: &str) -> bool {
+        !self
+            .exclude
+            .iter()
+            .any(|p| p.matches_path_or_parent(path, false))
+            && self.is_included(path)
+    }
+
+    pub(crate) fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        self.exclude.iter().any(|p| p.matches(path, is_dir))
+    }
+
+    pub(crate) fn is_included(&self, path: &str) -> bool {
+        self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|p| p.matches_path_or_parent(path, false))
+    }
+}
+
+/// Returns `true` if `path` is a `target` directory created by Cargo.
+pub(crate) fn is_cargo_target(fs: &dyn FileSystem, path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == "target")
+        && (fs.is_file(&path.join("CACHEDIR.TAG"))
+            || fs.is_file(&path.join(".rustc_info.json"))
+            || fs.is_file(&path.with_file_name("Cargo.toml")))
+}
+
+/// Converts `path` to a string using `/` as separator.
+pub(crate) fn slash_path(path: &Path) -> String {
+    let mut ret = String::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => continue,
+            Component::RootDir => {}
+            c => ret.push_str(&c.as_os_str().to_string_lossy()),
+        }
+        if !ret.ends_with('/') {
+            ret.push('/');
+        }
+    }
+    if ret.len() > 1 {
+        ret.pop();
+    }
+    ret
+}
+
+struct IgnoreFile {
+    /// The directory containing the file, relative to the root and ending with `/` (empty for
+    /// the root).
+    dir: String,
+    rules: Gitignore,
+}
+
+/// The `.gitignore` and `.ignore` files applying to the directory being walked through.
+pub(crate) struct IgnoreFiles {
+    /// The directory to work on, relative to the root (the git repository if any).
+    prefix: String,
+    files: Vec<IgnoreFile>,
+}
+
+impl IgnoreFiles {
+    /// Loads the ignore files of `work_dir` and of its parents up to the root of the git
+    /// repository containing it.
+    pub(crate) fn new(fs: &dyn FileSystem, work_dir: &Path) -> IgnoreFiles {
+        let mut ret = IgnoreFiles {
+            prefix: String::new(),
+            files: vec![],
+        };
+        let work_dir = match fs.canonicalize(work_dir) {
+            Ok(w) => w,
+            Err(_) => return ret,
+        };
+        let root = work_dir.ancestors().find(|d| fs.exists(&d.join(".git")));
+        let root = match root {
+            Some(root) => {
+                ret.load(
+                    fs,
+                    &root.join(".git").join("info").join("exclude"),
+                    String::new(),
+                );
+                root
+            }
+            None => &work_dir,
+        };
+        let mut dirs = work_dir
+            .ancestors()
+            .take_while(|d| d.starts_with(root))
+            .collect::<Vec<_>>();
+        dirs.reverse();
+        for dir in dirs {
+            let rel = slash_path(dir.strip_prefix(root).unwrap_or(dir));
+            let rel = if rel.is_empty() { rel } else { rel + "/" };
+            for name in IGNORE_FILES {
+                ret.load(fs, &dir.join(name), rel.clone());
+            }
+            ret.prefix = rel;
+        }
+        ret
+    }
+
+    fn load(&mut self, fs: &dyn FileSystem, path: &Path, dir: String) -> bool {
+        let content = match fs.read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => return false,
+        };
+        let mut builder = GitignoreBuilder::new("");
+        for line in content.lines() {
+            // Invalid lines are skipped, like git does.
+            let _ = builder.add_line(None, line);
+        }
+        let rules = match builder.build() {
+            Ok(rules) => rules,
+            Err(_) => return false,
+        };
+        self.files.push(IgnoreFile { dir, rules });
+        true
+    }
+
+    /// Loads the ignore files of `dir` (`rel` being its path relative to the directory to work
+    /// on). Returns how many were found, to be given to `leave`.
+    pub(crate) fn enter(&mut self, fs: &dyn FileSystem, dir: &Path, rel: &str) -> usize {
+        let dir_rel = format!("{}{}/", self.prefix, rel);
+        IGNORE_FILES
+            .iter()
+            .filter(|name| self.load(fs, &dir.join(name), dir_rel.clone()))
+            .count()
+    }
+
+    pub(crate) fn leave(&mut self, count: usize) {
+        let len = self.files.len() - count;
+        self.files.truncate(len);
+    }
+
+    /// Returns `true` if `path` (relative to the directory to work on) is ignored.
+    pub(crate) fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let path = format!("{}{}", self.prefix, path);
+        let mut ignored = false;
+        for file in &self.files {
+            if let Some(sub) = path.strip_prefix(&file.dir) {
+                match file.rules.matched(sub, is_dir) {
+                    Match::Ignore(_) => ignored = true,
+                    Match::Whitelist(_) => ignored = false,
+                    Match::None => {}
+                }
+            }
+        }
+        ignored
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use types::TypeStruct;
+
+/// Why an item has been matched with an orphaned doc entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchKind {
+    /// Same kind and name, but in another parent (e.g. a method moved to another impl).
+    Moved,
+    /// Same kind and parent, with a similar name.
+    Renamed,
+    /// Same kind, parent and signature, with another name.
+    SameSignature,
+}
+
+impl Display for MatchKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            MatchKind::Moved => write!(f, "moved"),
+            MatchKind::Renamed => write!(f, "renamed"),
+            MatchKind::SameSignature => write!(f, "same signature"),
+        }
+    }
+}
+
+/// An item which is likely to be the new location of a doc entry that couldn't be placed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    /// The file the item is in.
+    pub file: String,
+    pub item: TypeStruct,
+    pub kind: MatchKind,
+}
+
+/// An item and its signature if known.
+pub type SignedItem = (TypeStruct, Option<u64>);
+
+/// Looks for the most likely undocumented item of `items` (listed by file) for each orphaned
+/// entry of `orphans` (with the file it was expected in, `None` meaning any file).
+///
+/// Matches in the expected file are preferred, an item can only be suggested once and no
+/// suggestion is made when several items are equally likely. Kinds, parents and names are
+/// compared, as well as the signatures when they are known on both sides (see
+/// [`StripOptions::signatures`](::StripOptions::signatures)).
+pub fn find_matches(
+    orphans: &[(Option<&str>, &TypeStruct, Option<u64>)],
+    items: &[(String, Vec<SignedItem>)],
+) -> Vec<Option<Suggestion>> {
+    let mut taken = items
+        .iter()
+        .map(|(_, i)| vec![false; i.len()])
+        .collect::<Vec<_>>();
+
+    orphans
+        .iter()
+        .map(|&(expected_file, orphan, signature)| {
+            let mut best = None;
+            let mut ambiguous = false;
+
+            for (file_pos, (file, file_items)) in items.iter().enumerate() {
+                let other_file = expected_file.map(|f| f != file).unwrap_or(false);
+                for (pos, &(ref item, item_signature)) in file_items.iter().enumerate() {
+                    if taken[file_pos][pos] {
+                        continue;
+                    }
+                    let signatures = signature.and_then(|s| item_signature.map(|i| (s, i)));
+                    let score = match compare(orphan, item, signatures) {
+                        Some((kind, distance)) => (kind, other_file, distance),
+                        None => continue,
+                    };
+                    match best {
+                        Some((ref best_score, _, _)) if *best_score < score => {}
+                        Some((ref best_score, _, _)) if *best_score == score => ambiguous = true,
+                        _ => {
+                            best = Some((score, file_pos, pos));
+                            ambiguous = false;
+                        }
+                    }
+                }
+            }
+            match best {
+                Some(((kind, _, _), file_pos, pos)) if !ambiguous => {
+                    taken[file_pos][pos] = true;
+                    Some(Suggestion {
+                        file: items[file_pos].0.clone(),
+                        item: items[file_pos].1[pos].0.clone(),
+                        kind,
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// `signatures` are the ones of `orphan` and `item` if both are known.
+fn compare(
+    orphan: &TypeStruct,
+    item: &TypeStruct,
+    signatures: Option<(u64, u64)>,
+) -> Option<(MatchKind, usize)> {
+    if orphan.ty != item.ty || orphan == item {
+        return None;
+    }
+    if orphan.name == item.name {
+        return Some((MatchKind::Moved, 0));
+    }
+    if orphan.parent != item.parent {
+        return None;
+    }
+    let distance = edit_distance(&orphan.name, &item.name);
+    let len = orphan.name.chars().count().max(item.name.chars().count());
+    if distance <= (len / 3).max(1) {
+        Some((MatchKind::Renamed, distance))
+    } else if signatures.is_some_and(|(a, b)| a == b) {
+        Some((MatchKind::SameSignature, distance))
+    } else {
+        None
+    }
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        ::std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
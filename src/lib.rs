@@ -12,16 +12,67 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+extern crate globset;
+extern crate ignore;
+extern crate regex;
+extern crate toml;
+
+pub mod backup;
+pub mod cache;
+pub mod comments;
+pub mod config;
 mod consts;
+pub mod coverage;
+pub mod diagnostics;
+pub mod diff;
+pub mod error;
+pub mod filter;
+pub mod fuzzy;
+pub mod manifest;
+pub mod pattern;
 pub mod regenerate;
+pub mod report;
+pub mod selector;
+pub mod stats;
 pub mod strip;
+pub mod stripper;
 pub mod types;
 pub mod utils;
+pub mod vfs;
 
-pub use consts::{END_INFO, FILE, FILE_COMMENT, MOD_COMMENT, OUTPUT_COMMENT_FILE};
-pub use regenerate::{parse_cmts, regenerate_comments, regenerate_doc_comments};
-pub use strip::strip_comments;
-pub use types::{EventType, Type, TypeStruct};
+pub use backup::{undo, Backup};
+pub use cache::Cache;
+pub use comments::{CommentsFile, DocEntry, EntryKind, FileSelector, Section};
+pub use config::{Config, Format};
+pub use consts::{
+    BACKUP_DIR, CACHE_FILE, CONFIG_FILE, END_INFO, FILE, FILE_COMMENT, MOD_COMMENT,
+    OUTPUT_COMMENT_FILE, SIGNATURE,
+};
+pub use coverage::{
+    collect_coverage, Counts, Coverage, CoverageOptions, CoverageReport, FileCoverage,
+};
+pub use diagnostics::{Diagnostic, Diagnostics, Severity};
+pub use diff::unified_diff;
+pub use error::{FileError, ParsePathError};
+pub use filter::FileFilter;
+pub use manifest::{Package, Workspace};
+pub use pattern::Pattern;
+#[allow(deprecated)]
+pub use regenerate::{
+    parse_cmts, regenerate_comments, regenerate_doc_comments, OnConflict, RegenerateOptions,
+};
+pub use report::Report;
+pub use selector::{DocKind, ItemFilter, Selector};
+pub use stats::{collect_stats, Stats};
+#[allow(deprecated)]
+pub use strip::{strip_comments, strip_file, StripOptions, StrippedFile};
+pub use stripper::Stripper;
+pub use types::{EventType, Type, TypeStruct, Visibility};
 pub use utils::{
-    loop_over_files, write_comment, write_file, write_file_comment, write_file_name, write_item_doc,
+    list_filtered_files, loop_over_files, walk_files, write_atomically, write_comment, write_file,
+    write_file_comment, write_file_name, write_item_doc,
 };
+pub use vfs::{DiskFs, FileSystem, FileType, MemoryFs};
@@ -12,72 +12,86 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+extern crate clap;
+extern crate clap_complete;
 extern crate stripper_lib;
 
-use std::fs::File;
-use std::io::{BufRead, Write};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{BufRead, IsTerminal, Read, Write};
 use std::path::Path;
-use std::{env, io};
+use std::sync::Arc;
+use std::{env, io, process};
 
-use stripper_lib::loop_over_files;
-use stripper_lib::regenerate::regenerate_doc_comments;
-use stripper_lib::strip_comments;
-use stripper_lib::OUTPUT_COMMENT_FILE;
+use stripper_lib::{collect_coverage, collect_stats, unified_diff, Cache, Report, Stripper};
+use stripper_lib::{undo, write_atomically, Backup, FileError};
+use stripper_lib::{Config, CoverageOptions, FileFilter, Format, ItemFilter, OUTPUT_COMMENT_FILE};
+use stripper_lib::{Diagnostic, Diagnostics, Severity};
+use stripper_lib::{RegenerateOptions, StripOptions};
 
-struct ExecOptions {
-    stdout_output: bool,
-    strip: bool,
-    regenerate: bool,
-    ignore_macros: bool,
-    ignore_doc_commented: bool,
+use cli::{Action, Args, Color, MessageFormat};
+
+mod cli;
+
+/// Prints the diagnostics on stderr as asked on the command line.
+struct Printer {
+    quiet: bool,
+    color: bool,
+    json: bool,
 }
 
-fn check_options(args: &mut ExecOptions, to_change: char) -> bool {
-    if to_change == 's' {
-        args.strip = true;
-    } else {
-        args.regenerate = true;
+impl Printer {
+    fn new(args: &Args) -> Printer {
+        Printer {
+            quiet: args.quiet,
+            color: match args.color {
+                Color::Auto => io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+                Color::Always => true,
+                Color::Never => false,
+            },
+            json: args.message_format == MessageFormat::Json,
+        }
     }
-    if args.regenerate && args.strip {
-        println!("You cannot strip and regenerate at the same time!");
-        println!("Rerun with -h option for more information");
-        false
-    } else {
-        true
+
+    fn info<S: Into<String>>(&self, message: S) {
+        self.emit(&Diagnostic::info(message));
+    }
+
+    fn error<S: Into<String>>(&self, message: S) {
+        self.emit(&Diagnostic::error(message));
+    }
+
+    fn file_error(&self, path: &str, message: String) {
+        self.emit(&Diagnostic::error(message).with_path(path));
     }
 }
 
-fn print_help() {
-    println!(
-        r#"Available options for rustdoc-stripper:
-    -h | --help                : Displays this help
-    -s | --strip               : Strips the specified folder's files and create
-                                 a file with doc comments (comments.md by default)
-    -g | --regenerate          : Recreate files with doc comments from reading
-                                 doc comments file (comments.md by default)
-    -n | --no-file-output      : Display doc comments directly on stdout
-    -i | --ignore [filename]   : Ignore the specified file, can be repeated as much
-                                 as needed, only used when stripping files, ignored
-                                 otherwise
-    -d | --dir [directory]     : Specify a directory path to work on, optional
-    -v | --verbose             : Activate verbose mode
-    -f | --force               : Remove confirmation demands
-    -m | --ignore-macros       : Macros in hierarchy will be ignored (so only macros
-                                 with doc comments will appear in the comments file)
-    -o | --comment-file        : Specify the file where you want to save/load doc
-                                 comments
-    -x | --ignore-doc-commented: When regenerating doc comments, if doc comments
-                                 are already present, stored doc comment won't be
-                                 regenerated
-
-By default, rustdoc-stripper is run with -s option:
-./rustdoc-stripper -s
-
-IMPORTANT: Only files ending with '.rs' will be stripped/regenerated."#
-    );
+impl Diagnostics for Printer {
+    fn emit(&self, diagnostic: &Diagnostic) {
+        if self.quiet && diagnostic.severity == Severity::Info {
+            return;
+        }
+        if self.json {
+            eprintln!("{}", diagnostic.to_json());
+            return;
+        }
+        let text = diagnostic.to_string();
+        let color = match diagnostic.severity {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+            Severity::Info => "",
+        };
+        let severity = diagnostic.severity.as_str();
+        match text.strip_prefix(severity) {
+            Some(rest) if self.color && !color.is_empty() => {
+                eprintln!("{}{}\x1b[0m{}", color, severity, rest)
+            }
+            _ => eprintln!("{}", text),
+        }
+    }
 }
 
-fn ask_confirmation(out_file: &str) -> bool {
+fn ask_confirmation(out_file: &str, printer: &Printer) -> bool {
     let r = io::stdin();
     let mut reader = r.lock();
     let mut line = String::new();
@@ -97,9 +111,10 @@ Do you want to continue ? (y/n) "##,
             line = line.trim().to_owned();
             if line != "y" && line != "Y" {
                 if line == "n" || line == "N" {
-                    println!("Aborting...");
+                    printer.info("Aborting...");
                 } else {
-                    println!("Unknown answer: '{}'.\nAborting...", line);
+                    printer.error(format!("Unknown answer: '{}'.", line));
+                    printer.info("Aborting...");
                 }
                 false
             } else {
@@ -107,208 +122,532 @@ Do you want to continue ? (y/n) "##,
             }
         }
         Err(e) => {
-            println!("An error occured: {}.\nAborting...", e);
+            printer.error(format!("An error occured: {}.", e));
+            printer.info("Aborting...");
             false
         }
     }
 }
 
 fn main() {
-    let mut args = ExecOptions {
-        stdout_output: false,
-        strip: false,
-        regenerate: false,
-        ignore_macros: false,
-        ignore_doc_commented: false,
+    let mut args = match cli::parse(env::args_os()) {
+        Ok(args) => args,
+        Err(e) => e.exit(),
     };
-    let mut first = true;
-    let mut wait_filename = false;
-    let mut wait_directory = false;
-    let mut files_to_ignore = vec![];
-    let mut directory = ".".to_owned();
-    let mut verbose = false;
-    let mut force = false;
-    let mut wait_out_file = false;
-    let mut out_file = OUTPUT_COMMENT_FILE.to_owned();
-
-    for argument in env::args() {
-        if first {
-            first = false;
-            continue;
-        }
-        if wait_filename {
-            files_to_ignore.push(argument.clone());
-            wait_filename = false;
-            continue;
-        }
-        if wait_directory {
-            directory = argument.clone();
-            wait_directory = false;
-            continue;
-        }
-        if wait_out_file {
-            out_file = argument.clone();
-            wait_out_file = false;
-            continue;
-        }
-        match &*argument {
-            "-h" | "--help" => {
-                print_help();
-                return;
+    let printer = Arc::new(Printer::new(&args));
+    let action = args.action();
+    if action != Action::Completions && !args.no_config {
+        if let Err(e) = load_config(&mut args, &printer) {
+            printer.error(format!("Invalid configuration: {}", e));
+            process::exit(1);
+        }
+    }
+    if action != Action::Completions && !Path::new(&args.directory).is_dir() {
+        printer.error(format!("'{}' is not a directory.", args.directory));
+        process::exit(1);
+    }
+    let code = match action {
+        Action::Undo => run_undo(&args, &printer),
+        Action::Strip => run_strip(&args, &printer),
+        Action::Regenerate => run_regenerate(&args, &printer),
+        Action::Stats => run_stats(&args, &printer),
+        Action::Coverage => run_coverage(&args, &printer),
+        Action::Completions => {
+            if let Some(shell) = args.shell {
+                print!("{}", cli::completions(shell));
             }
-            "-s" | "--strip" => {
-                if !check_options(&mut args, 's') {
-                    return;
+            0
+        }
+    };
+    process::exit(code);
+}
+
+/// Applies the settings of the configuration file given with `--config` or, if none, of the
+/// first one found from the directory to work on.
+fn load_config(args: &mut Args, printer: &Printer) -> Result<(), FileError> {
+    let config = match args.config {
+        Some(ref path) => {
+            let path = Path::new(path);
+            if path.file_name() == Some(OsStr::new("Cargo.toml")) {
+                Config::load_cargo(path)?
+            } else {
+                Some(Config::load(path)?)
+            }
+        }
+        None => Config::find(args.directory.as_ref())?,
+    };
+    if let Some(config) = config {
+        if args.verbose {
+            printer.info(format!(
+                "Using the configuration of '{}'.",
+                config.path.display()
+            ));
+        }
+        args.apply_config(config);
+    }
+    Ok(())
+}
+
+fn run_undo(args: &Args, printer: &Printer) -> i32 {
+    match undo(args.directory.as_ref()) {
+        Ok(Some(files)) => {
+            for file in files {
+                if args.verbose {
+                    printer
+                        .emit(&Diagnostic::info("restored").with_path(file.display().to_string()));
                 }
             }
-            "-i" | "--ignore" => {
-                wait_filename = true;
+            printer.info("Done !");
+            0
+        }
+        Ok(None) => {
+            printer.error(format!("There is no run to undo in '{}'.", &args.directory));
+            1
+        }
+        Err(e) => {
+            printer.error(format!(
+                "An error occurred while undoing the last run: {}",
+                e
+            ));
+            1
+        }
+    }
+}
+
+/// Returns the filter selecting the files to go through, `None` if a pattern is invalid.
+fn file_filter(args: &Args, printer: &Printer) -> Option<FileFilter> {
+    match args.file_filter() {
+        Ok(filter) => Some(filter),
+        Err(e) => {
+            printer.error(e);
+            None
+        }
+    }
+}
+
+/// Returns the filter selecting the doc comments to work on, `None` if a selector is invalid.
+fn item_filter(args: &Args, printer: &Printer) -> Option<ItemFilter> {
+    match args.item_filter() {
+        Ok(items) => Some(items),
+        Err(e) => {
+            printer.error(e);
+            None
+        }
+    }
+}
+
+/// Returns the files given as arguments or with `--files-from`, relative to the directory to
+/// work on, or `None` if all its files have to be processed. Only the Rust files kept by `filter`
+/// are returned.
+fn listed_files(
+    args: &Args,
+    filter: &FileFilter,
+    printer: &Printer,
+) -> Result<Option<Vec<String>>, String> {
+    let mut paths = args.files.clone();
+    if let Some(ref from) = args.files_from {
+        let content = if from == "-" {
+            let mut content = String::new();
+            io::stdin().read_to_string(&mut content).map(|_| content)
+        } else {
+            fs::read_to_string(from)
+        };
+        let content = content.map_err(|e| format!("Cannot read '{}': {}", from, e))?;
+        let separator = if content.contains('\0') { '\0' } else { '\n' };
+        paths.extend(
+            content
+                .split(separator)
+                .map(|p| p.trim_end_matches('\r'))
+                .filter(|p| !p.is_empty())
+                .map(|p| p.to_owned()),
+        );
+    } else if paths.is_empty() {
+        return Ok(None);
+    }
+    let directory = fs::canonicalize(&args.directory)
+        .map_err(|e| format!("Cannot read '{}': {}", args.directory, e))?;
+    let mut files = vec![];
+    for path in paths {
+        let full_path =
+            fs::canonicalize(&path).map_err(|e| format!("Cannot find '{}': {}", path, e))?;
+        let file = full_path
+            .strip_prefix(&directory)
+            .ok()
+            .and_then(|f| f.to_str())
+            .ok_or_else(|| format!("'{}' isn't inside '{}'", path, args.directory))?;
+        if Path::new(file).extension() != Some(OsStr::new("rs"))
+            || !filter.keeps(&file.replace('\\', "/"))
+        {
+            if args.verbose {
+                printer.emit(&Diagnostic::info("ignored").with_path(path));
             }
-            "-d" | "--dir" => {
-                wait_directory = true;
+        } else if !files.iter().any(|f| f == file) {
+            files.push(file.to_owned());
+        }
+    }
+    Ok(Some(files))
+}
+
+fn run_strip(args: &Args, printer: &Arc<Printer>) -> i32 {
+    let directory = &args.directory;
+    let out_file = &args.comment_file;
+    let comments_path = Path::new(out_file);
+    let filter = match file_filter(args, printer) {
+        Some(filter) => filter,
+        None => return 1,
+    };
+    let items = match item_filter(args, printer) {
+        Some(items) => items,
+        None => return 1,
+    };
+    let listed = match listed_files(args, &filter, printer) {
+        Ok(listed) => listed,
+        Err(e) => {
+            printer.error(e);
+            return 1;
+        }
+    };
+
+    if comments_path.exists() && !args.check && !args.diff && !args.stdout_output {
+        if comments_path.is_file() {
+            if !args.force && !ask_confirmation(out_file, printer) {
+                return 1;
             }
-            "-o" | "--comment-file" => {
-                wait_out_file = true;
+        } else {
+            printer.error(format!("An element called '{}' already exists.", out_file));
+            printer.info("Aborting...");
+            return 1;
+        }
+    }
+    if !args.diff {
+        printer.info("Starting stripping...");
+    }
+    let mut stripper = stripper(args, listed);
+    let options = StripOptions::new()
+        .verbose(args.verbose)
+        .diagnostics(printer.clone())
+        .ignore_macros(args.ignore_macros)
+        .jobs(args.jobs)
+        .filter(filter)
+        .items(items)
+        .signatures(args.signatures);
+    let stripped = match stripper.stripped_files(&options) {
+        Ok(stripped) => stripped,
+        Err(errors) => report_errors(&errors, true, printer),
+    };
+    if args.diff {
+        save_cache(stripper.cache(), printer);
+        let mut changes = vec![];
+        for f in &stripped {
+            let original = match fs::read_to_string(Path::new(directory).join(&f.path)) {
+                Ok(original) => original,
+                Err(e) => {
+                    printer.file_error(&f.path, format!("Unable to read file: {}", e));
+                    return 1;
+                }
+            };
+            changes.push((
+                f.path.clone(),
+                original,
+                f.content.clone().unwrap_or_default(),
+            ));
+        }
+        return print_diff(&changes, args.check);
+    }
+    if args.check {
+        save_cache(stripper.cache(), printer);
+        report_check(stripped.iter().map(|f| &f.path[..]), "stripped");
+    }
+    if args.backup {
+        let mut backup = match start_backup(directory, out_file, args.stdout_output, printer) {
+            Some(backup) => backup,
+            None => return 1,
+        };
+        for f in &stripped {
+            if let Err(e) = backup.save(&Path::new(directory).join(&f.path)) {
+                printer.file_error(&f.path, format!("Cannot save file: {}", e));
+                printer.info("Aborting...");
+                return 1;
             }
-            "-g" | "--regenerate" => {
-                if !check_options(&mut args, 'g') {
-                    return;
+        }
+    }
+    let comments = if args.stdout_output {
+        None
+    } else {
+        Some(stripper.comments(&stripped, &options))
+    };
+    let ret = stripper.write_stripped(&stripped, comments.as_deref());
+    save_cache(stripper.cache(), printer);
+    if let Err(errors) = ret {
+        report_errors(&errors, true, printer);
+    }
+    if args.stdout_output {
+        for f in &stripped {
+            print!("{}", f.comments);
+        }
+    }
+    printer.info("Done !");
+    0
+}
+
+/// Returns the `Stripper` working on the `listed` files (all of them if `None`) and using the
+/// cache if `args` asks for it.
+fn stripper(args: &Args, listed: Option<Vec<String>>) -> Stripper {
+    let mut stripper = Stripper::new(&args.directory, &args.comment_file);
+    if let Some(files) = listed {
+        stripper = stripper.with_files(files);
+    }
+    if args.skip_unaffected {
+        stripper = stripper.with_cache(Cache::load(args.directory.as_ref()));
+    }
+    stripper
+}
+
+fn run_regenerate(args: &Args, printer: &Arc<Printer>) -> i32 {
+    let directory = &args.directory;
+    let filter = match file_filter(args, printer) {
+        Some(filter) => filter,
+        None => return 1,
+    };
+    let listed = match listed_files(args, &filter, printer) {
+        Ok(listed) => listed,
+        Err(e) => {
+            printer.error(e);
+            return 1;
+        }
+    };
+    let items = match item_filter(args, printer) {
+        Some(items) => items,
+        None => return 1,
+    };
+    let options = RegenerateOptions::new()
+        .verbose(args.verbose)
+        .diagnostics(printer.clone())
+        .ignore_macros(args.ignore_macros)
+        .on_conflict(args.on_conflict)
+        .jobs(args.jobs)
+        .fuzzy(args.fuzzy)
+        .update_comment_file(args.prune)
+        .filter(filter)
+        .items(items);
+    if args.diff {
+        let mut stripper = Stripper::new(directory, &args.comment_file);
+        if let Some(files) = listed {
+            stripper = stripper.with_files(files);
+        }
+        let (regenerated, report) = match stripper.preview(&options) {
+            Ok(ret) => ret,
+            Err(errors) => report_errors(&errors, true, printer),
+        };
+        if !write_json_report(args, &report, printer) {
+            return 1;
+        }
+        let mut changes = vec![];
+        for (path, content) in regenerated {
+            match fs::read_to_string(Path::new(directory).join(&path)) {
+                Ok(original) => changes.push((path, original, content)),
+                Err(e) => {
+                    printer.file_error(&path, format!("Unable to read file: {}", e));
+                    return 1;
                 }
             }
-            "-n" | "--no-file-output" => {
-                args.stdout_output = true;
+        }
+        return print_diff(&changes, args.check);
+    }
+    let json = args.format == Format::Json;
+    if !json {
+        printer.info("Starting regeneration...");
+    }
+    let mut stripper = stripper(args, listed);
+    if args.backup && !args.check {
+        // Only the files which are going to be modified are saved.
+        let regenerated = match stripper.preview(&options) {
+            Ok((regenerated, _)) => regenerated,
+            Err(errors) => report_errors(&errors, true, printer),
+        };
+        let mut backup = match start_backup(directory, &args.comment_file, false, printer) {
+            Some(backup) => backup,
+            None => return 1,
+        };
+        for (path, _) in &regenerated {
+            if let Err(e) = backup.save(&Path::new(directory).join(path)) {
+                printer.file_error(path, format!("Cannot save file: {}", e));
+                printer.info("Aborting...");
+                return 1;
             }
-            "-v" | "--verbose" => {
-                verbose = true;
+        }
+    }
+    let ret = if args.check {
+        stripper.check(&options)
+    } else {
+        stripper.regenerate(&options)
+    };
+    save_cache(stripper.cache(), printer);
+    match ret {
+        Ok(report) => {
+            if json {
+                print!("{}", report.to_json());
+            } else {
+                print!("{}", report);
             }
-            "-f" | "--force" => {
-                force = true;
+            if !write_json_report(args, &report, printer) {
+                return 1;
             }
-            "-m" | "--ignore-macros" => {
-                args.ignore_macros = true;
+            if args.check {
+                report_check(report.modified.iter().map(|s| &s[..]), "regenerated");
             }
-            "-x" | "--ignore-doc-commented" => {
-                args.ignore_doc_commented = true;
+        }
+        Err(errors) => report_errors(&errors, false, printer),
+    }
+    if !json {
+        printer.info("Done !");
+    }
+    0
+}
+
+fn run_stats(args: &Args, printer: &Printer) -> i32 {
+    let filter = match file_filter(args, printer) {
+        Some(filter) => filter,
+        None => return 1,
+    };
+    match collect_stats(
+        args.directory.as_ref(),
+        &filter,
+        args.ignore_macros,
+        args.jobs,
+        args.verbose,
+        &args.visibility,
+        printer,
+    ) {
+        Ok(stats) => {
+            match args.format {
+                Format::Text => println!("{}", stats),
+                Format::Json => print!("{}", stats.to_json()),
             }
-            "-" | "--" => {
-                println!("Unknown option: '-'");
-                return;
+            0
+        }
+        Err(errors) => report_errors(&errors, true, printer),
+    }
+}
+
+fn run_coverage(args: &Args, printer: &Arc<Printer>) -> i32 {
+    let filter = match file_filter(args, printer) {
+        Some(filter) => filter,
+        None => return 1,
+    };
+    // The default comments file is only used if it exists, the directory may not be stripped.
+    let comment_file = Path::new(&args.comment_file);
+    let comment_file = if comment_file.is_file() || args.comment_file != OUTPUT_COMMENT_FILE {
+        Some(comment_file.to_path_buf())
+    } else {
+        None
+    };
+    let options = CoverageOptions::new()
+        .verbose(args.verbose)
+        .diagnostics(printer.clone())
+        .ignore_macros(args.ignore_macros)
+        .jobs(args.jobs)
+        .filter(filter)
+        .visibility(args.visibility.clone())
+        .comment_file(comment_file);
+    match collect_coverage(args.directory.as_ref(), &options) {
+        Ok(report) => {
+            match args.format {
+                Format::Text => println!("{}", report),
+                Format::Json => print!("{}", report.to_json()),
             }
-            s => {
-                if !s.starts_with('-') {
-                    println!("Unknown option: '{}'", s);
-                    return;
-                }
-                for c in s[1..].chars() {
-                    match c {
-                        's' | 'g' => {
-                            if !check_options(&mut args, c) {
-                                return;
-                            }
-                        }
-                        'n' => {
-                            args.stdout_output = true;
-                        }
-                        'm' => {
-                            args.ignore_macros = true;
-                        }
-                        'x' => {
-                            args.ignore_doc_commented = true;
-                        }
-                        'h' => {
-                            print_help();
-                            return;
-                        }
-                        'v' => {
-                            verbose = true;
-                        }
-                        'f' => {
-                            force = true;
-                        }
-                        err if err == 'i' || err == 'd' => {
-                            println!(
-                                "'{}' have to be used separately from other options. Example:",
-                                err
-                            );
-                            println!("./rustdoc-stripper -s -{} foo", err);
-                            return;
-                        }
-                        err => {
-                            println!("Unknown option: {}", err);
-                            return;
-                        }
-                    }
+            let percent = report.total.counts.percent();
+            match args.fail_under {
+                Some(min) if percent < min => {
+                    printer.error(format!(
+                        "Documentation coverage is {:.1}%, below the required {}%",
+                        percent, min
+                    ));
+                    1
                 }
+                _ => 0,
             }
         }
+        Err(errors) => report_errors(&errors, true, printer),
     }
-    if wait_filename {
-        println!("[-i | --ignore] option expects a filename. Example:");
-        println!("./rustdoc-stripper -i src/foo.rs");
-        return;
+}
+
+/// Writes the JSON report if one has been asked for, returns `false` if it failed.
+fn write_json_report(args: &Args, report: &Report, printer: &Printer) -> bool {
+    if let Some(ref json_report) = args.json_report {
+        if let Err(e) = write_atomically(Path::new(json_report), report.to_json().as_bytes()) {
+            printer.file_error(json_report, format!("Cannot write file: {}", e));
+            return false;
+        }
     }
-    if wait_directory {
-        println!("[-d | --dir] option expects a directory path. Example:");
-        println!("./rustdoc-stripper -d src/");
-        return;
+    true
+}
+
+/// Prints the differences between the original and the new content of each file. If
+/// `exit_code` is `true`, returns `1` if there are some.
+fn print_diff(changes: &[(String, String, String)], exit_code: bool) -> i32 {
+    for (path, original, content) in changes {
+        print!("{}", unified_diff(path, original, content));
     }
-    if wait_out_file {
-        println!("[-o | --comment-file] option expects a file path. Example:");
-        println!("./rustdoc-stripper -o src/out.md");
-        return;
+    if exit_code && !changes.is_empty() {
+        1
+    } else {
+        0
     }
+}
 
-    if !args.regenerate || args.strip {
-        let comments_path = Path::new(&out_file);
+fn save_cache(cache: Option<&Cache>, printer: &Printer) {
+    if let Some(cache) = cache {
+        if let Err(e) = cache.save() {
+            printer.emit(&Diagnostic::warning(format!("Cannot save cache: {}", e)));
+        }
+    }
+}
 
-        if comments_path.exists() {
-            if comments_path.is_file() {
-                if !force && !ask_confirmation(&out_file) {
-                    return;
-                }
-            } else {
-                println!(
-                    "An element called '{}' already exists. Aborting...",
-                    &out_file
-                );
-                return;
-            }
+fn report_check<'a, I: Iterator<Item = &'a str>>(files: I, action: &str) -> ! {
+    let files = files.collect::<Vec<_>>();
+    if files.is_empty() {
+        println!("Nothing to be {}.", action);
+        process::exit(0);
+    }
+    println!("The following files would be {}:", action);
+    for file in files {
+        println!("  {}", file);
+    }
+    process::exit(1);
+}
+
+fn report_errors(errors: &[FileError], nothing_modified: bool, printer: &Printer) -> ! {
+    for e in errors {
+        printer.emit(&e.into());
+    }
+    if nothing_modified {
+        printer.info("No file has been modified.");
+    }
+    process::exit(1);
+}
+
+fn start_backup(
+    directory: &str,
+    out_file: &str,
+    stdout_output: bool,
+    printer: &Printer,
+) -> Option<Backup> {
+    let mut backup = match Backup::new(directory.as_ref()) {
+        Ok(backup) => backup,
+        Err(e) => {
+            printer.error(format!(
+                "Cannot create backup folder in '{}': {}",
+                directory, e
+            ));
+            return None;
         }
-        println!("Starting stripping...");
-        if args.stdout_output {
-            let stdout = io::stdout();
-            let mut stdout = stdout.lock();
-            loop_over_files(
-                directory.as_ref(),
-                &mut move |w, s| strip_comments(w, s, &mut stdout, args.ignore_macros),
-                &files_to_ignore,
-                verbose,
-            );
-        } else {
-            match File::create(&out_file) {
-                Ok(mut f) => {
-                    loop_over_files(
-                        directory.as_ref(),
-                        &mut |w, s| strip_comments(w, s, &mut f, args.ignore_macros),
-                        &files_to_ignore,
-                        verbose,
-                    );
-                }
-                Err(e) => {
-                    println!("Error while opening \"{}\": {}", &out_file, e);
-                    return;
-                }
-            }
+    };
+    if !stdout_output {
+        if let Err(e) = backup.save(out_file.as_ref()) {
+            printer.file_error(out_file, format!("Cannot save file: {}", e));
+            return None;
         }
-    } else {
-        println!("Starting regeneration...");
-        regenerate_doc_comments(
-            &directory,
-            verbose,
-            &out_file,
-            args.ignore_macros,
-            args.ignore_doc_commented,
-        );
     }
-    println!("Done !");
+    Some(backup)
 }
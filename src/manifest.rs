@@ -0,0 +1,436 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use toml::de::{DeTable, DeValue};
+
+use config::{self, line_of};
+use error::FileError;
+use pattern::Pattern;
+
+const MANIFEST: &str = "Cargo.toml";
+
+/// A package, as described by its `Cargo.toml` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Package {
+    pub name: String,
+    /// Directory of the `Cargo.toml` file.
+    pub dir: PathBuf,
+    /// Root files of the library and binary targets.
+    pub targets: Vec<PathBuf>,
+}
+
+impl Package {
+    /// Reads the package of the `Cargo.toml` file `path`, `None` if it only describes a
+    /// workspace.
+    pub fn load(path: &Path) -> Result<Option<Package>, FileError> {
+        Manifest::read(path).map(|m| m.package)
+    }
+
+    /// The directory containing the source files of the package: the closest common parent of
+    /// the directories of its targets (usually `src`).
+    ///
+    /// When some targets are outside of `src` and this parent would be the package directory
+    /// (or one of its parents), which also contains the tests, examples and build outputs, the
+    /// closest common parent of the targets inside `src` (or of the library if there are none) is
+    /// used instead: the other targets are returned by [`Package::outside_targets`].
+    pub fn source_dir(&self) -> PathBuf {
+        self.split_targets().0
+    }
+
+    /// The root files of the targets which aren't in [`Package::source_dir`].
+    pub fn outside_targets(&self) -> Vec<PathBuf> {
+        self.split_targets().1
+    }
+
+    fn split_targets(&self) -> (PathBuf, Vec<PathBuf>) {
+        let common = match common_dir(&self.targets) {
+            Some(common) => common,
+            None => {
+                let src = self.dir.join("src");
+                return (if src.is_dir() { src } else { self.dir.clone() }, vec![]);
+            }
+        };
+        if !self.dir.starts_with(&common) {
+            return (common, vec![]);
+        }
+        let src = self.dir.join("src");
+        let mut inside = self
+            .targets
+            .iter()
+            .filter(|t| t.starts_with(&src))
+            .cloned()
+            .collect::<Vec<_>>();
+        if inside.is_empty() {
+            // The library comes first.
+            inside.extend(self.targets.first().cloned());
+        }
+        let dir = common_dir(&inside).unwrap_or(common);
+        let outside = self
+            .targets
+            .iter()
+            .filter(|t| !t.starts_with(&dir))
+            .cloned()
+            .collect();
+        (dir, outside)
+    }
+}
+
+/// Returns the closest common parent of the directories of `files`.
+fn common_dir(files: &[PathBuf]) -> Option<PathBuf> {
+    let mut dirs = files.iter().filter_map(|t| t.parent());
+    let mut common = dirs.next()?.to_path_buf();
+    for dir in dirs {
+        while !dir.starts_with(&common) && common.pop() {}
+    }
+    Some(common)
+}
+
+/// The packages of a Cargo workspace, read from their `Cargo.toml` files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workspace {
+    /// Directory of the root `Cargo.toml` file.
+    pub root: PathBuf,
+    pub members: Vec<Package>,
+    /// Names of the packages to use when none is selected: the `default-members` if any, the
+    /// root package if it is one or all the members otherwise.
+    pub default_members: Vec<String>,
+}
+
+impl Workspace {
+    /// Reads the workspace the `Cargo.toml` file `path` belongs to: the one it describes or, if
+    /// it only describes a package, the first one found in its parent directories which has this
+    /// package as member. A package which isn't a member of any workspace is alone in its own.
+    pub fn load(path: &Path) -> Result<Workspace, FileError> {
+        let path = fs::canonicalize(path).map_err(|e| read_error(path, e))?;
+        let manifest = Manifest::read(&path)?;
+        let dir = parent(&path);
+        if manifest.workspace.is_some() {
+            return Workspace::from_root(&dir, manifest);
+        }
+        for ancestor in dir.ancestors().skip(1) {
+            let root_path = ancestor.join(MANIFEST);
+            if !root_path.is_file() {
+                continue;
+            }
+            let root = Manifest::read(&root_path)?;
+            match root.workspace {
+                Some(ref workspace) if workspace.member_dirs(ancestor).contains(&dir) => {
+                    return Workspace::from_root(ancestor, root);
+                }
+                Some(_) => break,
+                None => {}
+            }
+        }
+        let package = manifest.package.ok_or_else(|| {
+            FileError::new(
+                &path.display().to_string(),
+                None,
+                "Neither a package nor a workspace".to_owned(),
+            )
+        })?;
+        Ok(Workspace {
+            root: dir,
+            default_members: vec![package.name.clone()],
+            members: vec![package],
+        })
+    }
+
+    /// Returns the `Cargo.toml` file of `dir` or, if there is none, of its closest parent.
+    pub fn find_manifest(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|d| d.join(MANIFEST))
+            .find(|path| path.is_file())
+    }
+
+    fn from_root(root: &Path, manifest: Manifest) -> Result<Workspace, FileError> {
+        let table = manifest.workspace.unwrap_or_default();
+        let mut members = vec![];
+        let mut dirs = vec![];
+        if let Some(package) = manifest.package {
+            dirs.push(package.dir.clone());
+            members.push(package);
+        }
+        for dir in table.member_dirs(root) {
+            if dirs.contains(&dir) {
+                continue;
+            }
+            if let Some(package) = Manifest::read(&dir.join(MANIFEST))?.package {
+                dirs.push(dir);
+                members.push(package);
+            }
+        }
+        let default_members = match table.default_members {
+            Some(ref default_members) => {
+                let default_dirs = expand_members(root, default_members);
+                members
+                    .iter()
+                    .filter(|p| default_dirs.contains(&p.dir))
+                    .map(|p| p.name.clone())
+                    .collect()
+            }
+            None => {
+                let root_is_package = dirs.first().is_some_and(|d| d == root);
+                members
+                    .iter()
+                    .filter(|p| !root_is_package || p.dir == root)
+                    .map(|p| p.name.clone())
+                    .collect()
+            }
+        };
+        Ok(Workspace {
+            root: root.to_path_buf(),
+            members,
+            default_members,
+        })
+    }
+}
+
+/// The `[workspace]` table of a `Cargo.toml` file.
+#[derive(Default)]
+struct WorkspaceTable {
+    members: Vec<String>,
+    exclude: Vec<String>,
+    default_members: Option<Vec<String>>,
+}
+
+impl WorkspaceTable {
+    fn member_dirs(&self, root: &Path) -> Vec<PathBuf> {
+        let exclude = self
+            .exclude
+            .iter()
+            .map(|e| normalize(&root.join(e)))
+            .collect::<Vec<_>>();
+        expand_members(root, &self.members)
+            .into_iter()
+            .filter(|dir| !exclude.contains(dir))
+            .collect()
+    }
+}
+
+/// The content of a `Cargo.toml` file, to tell where the values which can't be read are.
+struct TomlFile<'a> {
+    content: &'a str,
+    path: &'a Path,
+}
+
+impl<'a> TomlFile<'a> {
+    /// Reads the value `key` of `table` with `read`, `None` if there is none. `what` is the
+    /// kind of value expected, for the error.
+    fn get<'t, 'i, T, F>(
+        &self,
+        table: &'t DeTable<'i>,
+        key: &str,
+        what: &str,
+        read: F,
+    ) -> Result<Option<T>, FileError>
+    where
+        F: FnOnce(&'t DeValue<'i>) -> Option<T>,
+    {
+        match table.get(key) {
+            Some(value) => read(value.get_ref()).map(Some).ok_or_else(|| {
+                FileError::new(
+                    &self.path.display().to_string(),
+                    Some(line_of(self.content, value.span().start)),
+                    format!("`{}` expects {}", key, what),
+                )
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn table<'t, 'i>(
+        &self,
+        table: &'t DeTable<'i>,
+        key: &str,
+    ) -> Result<Option<&'t DeTable<'i>>, FileError> {
+        self.get(table, key, "a table", DeValue::as_table)
+    }
+
+    fn string(&self, table: &DeTable, key: &str) -> Result<Option<String>, FileError> {
+        self.get(table, key, "a string", config::string)
+    }
+
+    fn strings(&self, table: &DeTable, key: &str) -> Result<Option<Vec<String>>, FileError> {
+        self.get(table, key, "an array of strings", config::strings)
+    }
+}
+
+struct Manifest {
+    package: Option<Package>,
+    workspace: Option<WorkspaceTable>,
+}
+
+impl Manifest {
+    fn read(path: &Path) -> Result<Manifest, FileError> {
+        let content = fs::read_to_string(path).map_err(|e| read_error(path, e))?;
+        let file = config::parse_toml(&content, path)?;
+        let toml = TomlFile {
+            content: &content,
+            path,
+        };
+        let dir = parent(path);
+        let workspace = match toml.table(&file, "workspace")? {
+            Some(table) => Some(WorkspaceTable {
+                members: toml.strings(table, "members")?.unwrap_or_default(),
+                exclude: toml.strings(table, "exclude")?.unwrap_or_default(),
+                default_members: toml.strings(table, "default-members")?,
+            }),
+            None => None,
+        };
+        let package = if let Some(package) = toml.table(&file, "package")? {
+            let name = toml.string(package, "name")?.ok_or_else(|| {
+                FileError::new(
+                    &path.display().to_string(),
+                    None,
+                    "The package has no name".to_owned(),
+                )
+            })?;
+            let lib = match toml.table(&file, "lib")? {
+                Some(lib) => toml.string(lib, "path")?,
+                None => None,
+            };
+            let mut targets = vec![dir.join(lib.unwrap_or_else(|| "src/lib.rs".to_owned()))];
+            let bins = toml.get(&file, "bin", "an array of tables", |v| {
+                v.as_array()?
+                    .iter()
+                    .map(|b| b.get_ref().as_table())
+                    .collect::<Option<Vec<_>>>()
+            })?;
+            for bin in bins.unwrap_or_default() {
+                match (toml.string(bin, "path")?, toml.string(bin, "name")?) {
+                    (Some(bin_path), _) => targets.push(dir.join(bin_path)),
+                    (None, Some(bin_name)) => {
+                        if bin_name == name {
+                            targets.push(dir.join("src/main.rs"));
+                        }
+                        targets.push(dir.join("src/bin").join(format!("{}.rs", bin_name)));
+                        targets.push(dir.join("src/bin").join(bin_name).join("main.rs"));
+                    }
+                    (None, None) => {}
+                }
+            }
+            // Binaries found automatically.
+            targets.push(dir.join("src/main.rs"));
+            let mut auto_bins = fs::read_dir(dir.join("src/bin"))
+                .map(|it| {
+                    it.filter_map(|e| e.ok().map(|e| e.path()))
+                        .map(|p| if p.is_dir() { p.join("main.rs") } else { p })
+                        .filter(|p| p.extension().is_some_and(|e| e == "rs"))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            auto_bins.sort();
+            targets.extend(auto_bins);
+
+            let mut existing = vec![];
+            for target in targets {
+                if target.is_file() && !existing.contains(&target) {
+                    existing.push(target);
+                }
+            }
+            Some(Package {
+                name,
+                dir: dir.clone(),
+                targets: existing,
+            })
+        } else {
+            None
+        };
+        Ok(Manifest { package, workspace })
+    }
+}
+
+/// Returns the directories matching the `members` globs which contain a `Cargo.toml` file.
+fn expand_members(root: &Path, members: &[String]) -> Vec<PathBuf> {
+    let mut ret = vec![];
+    for member in members {
+        let mut dirs = vec![root.to_path_buf()];
+        for component in member.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            if !component.contains(['*', '?', '[']) {
+                dirs = dirs
+                    .into_iter()
+                    .map(|d| d.join(component))
+                    .filter(|d| d.is_dir())
+                    .collect();
+                continue;
+            }
+            let pattern = match Pattern::glob(component) {
+                Ok(pattern) => pattern,
+                Err(_) => {
+                    dirs.clear();
+                    break;
+                }
+            };
+            let mut matching = vec![];
+            for dir in dirs {
+                if let Ok(it) = fs::read_dir(&dir) {
+                    let mut entries = it
+                        .filter_map(|e| e.ok().map(|e| e.path()))
+                        .filter(|p| {
+                            p.is_dir()
+                                && p.file_name()
+                                    .is_some_and(|n| pattern.matches(&n.to_string_lossy(), true))
+                        })
+                        .collect::<Vec<_>>();
+                    entries.sort();
+                    matching.extend(entries);
+                }
+            }
+            dirs = matching;
+        }
+        for dir in dirs {
+            let dir = normalize(&dir);
+            if dir.join(MANIFEST).is_file() && !ret.contains(&dir) {
+                ret.push(dir);
+            }
+        }
+    }
+    ret
+}
+
+/// Resolves the `.` and `..` components and the symbolic links of `path`, so that it can be
+/// compared with the directories of the packages. The components are only resolved if it
+/// doesn't exist.
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    let mut ret = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !ret.pop() {
+                    ret.push(component);
+                }
+            }
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+fn parent(path: &Path) -> PathBuf {
+    path.parent().unwrap_or_else(|| Path::new("")).to_path_buf()
+}
+
+fn read_error(path: &Path, e: ::std::io::Error) -> FileError {
+    FileError::new(
+        &path.display().to_string(),
+        None,
+        format!("Unable to read file: {}", e),
+    )
+}
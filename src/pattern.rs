@@ -0,0 +1,151 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+use std::fmt::{self, Debug, Formatter};
+
+const REGEX_PREFIX: &str = "re:";
+
+/// A pattern matched against paths relative to the directory to work on, with `/` as separator.
+///
+/// Patterns starting with `re:` are regular expressions (with the syntax of the `regex` crate),
+/// searched anywhere in the path (use `^` and `$` to anchor them).
+///
+/// Other patterns are globs, following the `.gitignore` rules: `*` and `?` don't match `/`,
+/// `**` matches any number of directories, `[...]` matches one character of a set and `{a,b}`
+/// one of the alternatives. A glob without `/` (other than a trailing one) matches the file or
+/// directory name at any depth, otherwise it matches the path from the directory to work on. A
+/// trailing `/` only matches directories.
+#[derive(Clone)]
+pub struct Pattern {
+    source: String,
+    kind: Kind,
+    dir_only: bool,
+    anchored: bool,
+}
+
+#[derive(Clone)]
+enum Kind {
+    Glob(GlobMatcher),
+    Regex(Regex),
+    Literal(String),
+}
+
+impl Debug for Pattern {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Pattern({:?})", self.source)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.source == other.source && self.dir_only == other.dir_only
+    }
+}
+
+impl Pattern {
+    /// Builds a regex pattern if `pattern` starts with `re:`, a glob otherwise.
+    pub fn new(pattern: &str) -> Result<Pattern, String> {
+        match pattern.strip_prefix(REGEX_PREFIX) {
+            Some(regex) => Pattern::regex(regex),
+            None => Pattern::glob(pattern),
+        }
+    }
+
+    pub fn regex(regex: &str) -> Result<Pattern, String> {
+        let compiled = Regex::new(regex).map_err(|e| match e {
+            regex::Error::Syntax(_) => format!(
+                "Invalid regex `{}`: {}",
+                regex,
+                e.to_string()
+                    .lines()
+                    .last()
+                    .unwrap_or("")
+                    .trim_start_matches("error: ")
+            ),
+            e => format!("Invalid regex `{}`: {}", regex, e),
+        })?;
+        Ok(Pattern {
+            source: format!("{}{}", REGEX_PREFIX, regex),
+            kind: Kind::Regex(compiled),
+            dir_only: false,
+            anchored: true,
+        })
+    }
+
+    pub fn glob(pattern: &str) -> Result<Pattern, String> {
+        let mut glob = pattern;
+        let dir_only = glob.len() > 1 && glob.ends_with('/');
+        if dir_only {
+            glob = &glob[..glob.len() - 1];
+        }
+        let anchored = glob.contains('/');
+        let glob = glob.trim_start_matches('/');
+        if glob.is_empty() {
+            return Err(format!("Invalid pattern `{}`", pattern));
+        }
+        let matcher = GlobBuilder::new(glob)
+            .literal_separator(true)
+            .backslash_escape(true)
+            .build()
+            .map_err(|e| format!("Invalid pattern `{}`: {}", pattern, e.kind()))?
+            .compile_matcher();
+        Ok(Pattern {
+            source: pattern.to_owned(),
+            kind: Kind::Glob(matcher),
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// A pattern matching only `path`.
+    pub fn literal(path: &str) -> Pattern {
+        Pattern {
+            source: path.to_owned(),
+            kind: Kind::Literal(path.trim_start_matches("./").to_owned()),
+            dir_only: false,
+            anchored: true,
+        }
+    }
+
+    /// Returns `true` if `path` (a directory if `is_dir` is `true`) matches.
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        match self.kind {
+            Kind::Literal(ref p) => p == path,
+            Kind::Regex(ref regex) => regex.is_match(path),
+            Kind::Glob(ref glob) => {
+                let path = if self.anchored {
+                    path
+                } else {
+                    path.rsplit('/').next().unwrap_or(path)
+                };
+                glob.is_match(path)
+            }
+        }
+    }
+
+    /// Returns `true` if `path` or one of its parent directories matches.
+    pub fn matches_path_or_parent(&self, path: &str, is_dir: bool) -> bool {
+        if self.matches(path, is_dir) {
+            return true;
+        }
+        path.char_indices()
+            .filter(|&(_, c)| c == '/')
+            .any(|(pos, _)| self.matches(&path[..pos], true))
+    }
+}
@@ -12,19 +12,175 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use consts::{END_INFO, FILE, FILE_COMMENT, MOD_COMMENT};
-use std::collections::HashMap;
-use std::fs::{remove_file, File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
+use cache::{hash, Cache};
+use comments::{CommentsFile, DocEntry, EntryKind, FileSelector, Section};
+use consts::{END_INFO, FILE_COMMENT, MOD_COMMENT};
+use diagnostics::{Diagnostic, Diagnostics, Sink};
+use error::{FileError, ParsePathError};
+use filter::FileFilter;
+use fuzzy::find_matches;
+use report::{ItemLocation, Reason, Report, Unplaced};
+use selector::ItemFilter;
+use std::collections::{HashMap, VecDeque};
 use std::iter;
+use std::mem;
 use std::ops::Deref;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 use strip;
+use stripper::Stripper;
 use types::{EventType, ParseResult, Type, TypeStruct};
-use utils::{join, loop_over_files, remove_macro_parent, write_comment, write_file};
+use utils::{
+    jobs_count, list_files_of, listed, parallel_map, remove_macro_parent, write_all,
+    write_atomically,
+};
+use vfs::{DiskFs, FileSystem};
 
-type Infos = HashMap<Option<String>, Vec<(Option<TypeStruct>, Vec<String>)>>;
+type Entry = (Option<TypeStruct>, Vec<String>);
+type Entries = Vec<Entry>;
+type Infos = HashMap<Option<String>, Entries>;
+/// The signatures of the items of the entries, by file.
+type Signatures = HashMap<(Option<String>, TypeStruct), u64>;
 
+/// What to do when regenerating the doc comments of an item which already has some.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Leave the item untouched.
+    Skip,
+    /// Remove the existing doc comments.
+    Replace,
+    /// Put the doc comments after the existing ones.
+    Append,
+    /// Put the doc comments before the existing ones.
+    Prepend,
+    /// Return an error.
+    Fail,
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OnConflict, String> {
+        match s {
+            "skip" => Ok(OnConflict::Skip),
+            "replace" => Ok(OnConflict::Replace),
+            "append" => Ok(OnConflict::Append),
+            "prepend" => Ok(OnConflict::Prepend),
+            "fail" => Ok(OnConflict::Fail),
+            _ => Err(format!(
+                "Unknown conflict policy '{}', expected one of: skip, replace, append, prepend, \
+                 fail",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings used when putting doc comments back, see [`Stripper::regenerate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegenerateOptions {
+    pub(crate) verbose: bool,
+    pub(crate) ignore_macros: bool,
+    pub(crate) on_conflict: OnConflict,
+    pub(crate) jobs: usize,
+    pub(crate) fuzzy: bool,
+    pub(crate) update_comment_file: bool,
+    pub(crate) filter: FileFilter,
+    pub(crate) items: ItemFilter,
+    pub(crate) diagnostics: Sink,
+}
+
+impl Default for RegenerateOptions {
+    fn default() -> RegenerateOptions {
+        RegenerateOptions {
+            verbose: false,
+            ignore_macros: false,
+            on_conflict: OnConflict::Append,
+            jobs: 1,
+            fuzzy: false,
+            update_comment_file: false,
+            filter: FileFilter::new(),
+            items: ItemFilter::new(),
+            diagnostics: Sink::default(),
+        }
+    }
+}
+
+impl RegenerateOptions {
+    pub fn new() -> RegenerateOptions {
+        RegenerateOptions::default()
+    }
+
+    /// Prints the files which are gone through.
+    pub fn verbose(mut self, verbose: bool) -> RegenerateOptions {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Sends the diagnostics of the run (the files gone through when verbose, the warnings...)
+    /// to `sink` instead of printing them on the standard error output.
+    pub fn diagnostics(mut self, sink: Arc<dyn Diagnostics>) -> RegenerateOptions {
+        self.diagnostics = Sink::new(sink);
+        self
+    }
+
+    /// Must be the same as when the comments file was generated.
+    pub fn ignore_macros(mut self, ignore_macros: bool) -> RegenerateOptions {
+        self.ignore_macros = ignore_macros;
+        self
+    }
+
+    /// What to do with the items which already have doc comments, [`OnConflict::Append`] by
+    /// default.
+    pub fn on_conflict(mut self, on_conflict: OnConflict) -> RegenerateOptions {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    /// Number of threads processing the files, `0` meaning one per available CPU. The result is
+    /// the same whatever the number of threads.
+    pub fn jobs(mut self, jobs: usize) -> RegenerateOptions {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Entries which can't be placed are compared to the undocumented items to find out if their
+    /// item has been renamed or moved: if `true`, they are put on the matching items, otherwise
+    /// the matches are only printed.
+    pub fn fuzzy(mut self, fuzzy: bool) -> RegenerateOptions {
+        self.fuzzy = fuzzy;
+        self
+    }
+
+    /// Removes the entries which have been put back from the comments file (which is deleted if
+    /// none remain).
+    pub fn update_comment_file(mut self, update_comment_file: bool) -> RegenerateOptions {
+        self.update_comment_file = update_comment_file;
+        self
+    }
+
+    /// Which files of the directory to work on are gone through, all the Rust files by default.
+    pub fn filter(mut self, filter: FileFilter) -> RegenerateOptions {
+        self.filter = filter;
+        self
+    }
+
+    /// Which doc comments are put back, all of them by default.
+    pub fn items(mut self, items: ItemFilter) -> RegenerateOptions {
+        self.items = items;
+        self
+    }
+
+    fn regen_options(&self) -> RegenOptions {
+        RegenOptions {
+            ignore_macros: self.ignore_macros,
+            on_conflict: self.on_conflict,
+        }
+    }
+}
+
+#[allow(clippy::manual_repeat_n)]
 fn gen_indent(indent: usize) -> String {
     iter::repeat("    ")
         .take(indent)
@@ -41,31 +197,163 @@ fn gen_indent_from(from: &str) -> String {
     String::new()
 }
 
-/// Returns `true` in case a "// rustdoc-stripper-ignore-next-stop" was inserted.
+/// Lines to add into a file, stored by the line of the original content they have to be put
+/// before so that the new content can be generated in one go.
+struct Insertions {
+    original: Vec<String>,
+    before: Vec<Vec<String>>,
+    removed: Vec<bool>,
+}
+
+impl Insertions {
+    fn new(original: Vec<String>) -> Insertions {
+        let before = vec![vec![]; original.len() + 1];
+        let removed = vec![false; original.len()];
+        Insertions {
+            original,
+            before,
+            removed,
+        }
+    }
+
+    fn insert(&mut self, line: usize, content: String) {
+        let line = line.min(self.original.len());
+        self.before[line].push(content);
+    }
+
+    /// Removes the original line `line`.
+    fn remove(&mut self, line: usize) {
+        self.removed[line] = true;
+    }
+
+    /// Returns the line which is currently right before the original line `line`.
+    fn line_before(&self, mut line: usize) -> Option<&str> {
+        loop {
+            if let Some(l) = self.before[line].last() {
+                return Some(l);
+            }
+            if line == 0 {
+                return None;
+            }
+            line -= 1;
+            if !self.removed[line] {
+                return Some(&self.original[line]);
+            }
+        }
+    }
+
+    fn into_content(self) -> String {
+        let mut lines = Vec::with_capacity(
+            self.original.len() + self.before.iter().map(|b| b.len()).sum::<usize>(),
+        );
+        let mut original = self.original.into_iter().zip(self.removed);
+        for before in self.before {
+            lines.extend(before);
+            if let Some((line, false)) = original.next() {
+                lines.push(line);
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// Available entries, indexed by their item path.
+struct EntryIndex<'a> {
+    positions: HashMap<&'a TypeStruct, VecDeque<usize>>,
+}
+
+impl<'a> EntryIndex<'a> {
+    fn new(elements: &'a [Entry], used: &[bool]) -> EntryIndex<'a> {
+        let mut positions: HashMap<_, VecDeque<_>> = HashMap::new();
+        for (pos, entry) in elements.iter().enumerate() {
+            if let (Some(ref ty), false) = (&entry.0, used[pos]) {
+                positions.entry(ty).or_default().push_back(pos);
+            }
+        }
+        EntryIndex { positions }
+    }
+
+    fn first(&self, ty: &TypeStruct) -> Option<usize> {
+        self.positions.get(ty).and_then(|p| p.front().cloned())
+    }
+
+    /// Returns (and removes from the index) the position of the first entry matching `to_find`.
+    ///
+    /// If `to_find` has the same path as other items and no entry tells which of them it's for,
+    /// an entry without ordinal is used and the second value is `true`.
+    fn take(&mut self, to_find: &TypeStruct) -> Option<(usize, bool)> {
+        if let Some(pos) = self.take_exact(to_find) {
+            return Some((pos, false));
+        }
+        if to_find.ordinal == 0 {
+            return None;
+        }
+        let mut tmp = to_find.clone();
+        tmp.ordinal = 0;
+        self.take_exact(&tmp).map(|pos| (pos, true))
+    }
+
+    fn take_exact(&mut self, to_find: &TypeStruct) -> Option<usize> {
+        // is true if a is a top level Type, or if is inside a macro and Type and name match
+        /* The result is that if there is a struct defined inside a macro,
+        the documentation (if it has) of that struct will be written inside the macro. */
+        let mut candidates = vec![to_find.clone()];
+        let mut tmp = to_find.clone();
+        remove_macro_parent(&mut tmp);
+        candidates.push(tmp);
+        // to detect variants
+        if to_find.ty == Type::Unknown
+            && match to_find.parent {
+                Some(ref p) => p.ty == Type::Struct || p.ty == Type::Enum || p.ty == Type::Use,
+                None => false,
+            }
+        {
+            let mut tmp = to_find.clone();
+            tmp.ty = Type::Variant;
+            candidates.push(tmp);
+        }
+        let (pos, ty) = candidates
+            .iter()
+            .filter_map(|c| self.first(c).map(|pos| (pos, c)))
+            .min_by_key(|&(pos, _)| pos)?;
+        if let Some(p) = self.positions.get_mut(ty) {
+            p.pop_front();
+        }
+        Some(pos)
+    }
+}
+
 fn regenerate_comment(
     is_file_comment: bool,
-    position: usize,
+    line: usize,
     indent: usize,
     comment: &str,
-    original_content: &mut Vec<String>,
+    insertions: &mut Insertions,
     need_check_ignore_doc_comment: bool,
-) -> bool {
+) {
     let mut need_to_add_ignore_next_comment_stop = false;
-    if need_check_ignore_doc_comment && position > 0 {
-        let prev = original_content[position - 1].trim();
-        if strip::DOC_COMMENT_ID.iter().any(|d| prev.starts_with(d)) {
-            need_to_add_ignore_next_comment_stop = true;
+    if need_check_ignore_doc_comment {
+        if let Some(prev) = insertions.line_before(line) {
+            let prev = prev.trim();
+            if strip::DOC_COMMENT_ID.iter().any(|d| prev.starts_with(d)) {
+                need_to_add_ignore_next_comment_stop = true;
+            }
         }
     }
     let is_empty = comment.trim().is_empty();
     let read_indent = if is_file_comment {
         gen_indent(indent)
     } else {
-        let tmp = original_content[position].clone();
-        gen_indent_from(&tmp)
+        gen_indent_from(&insertions.original[line])
     };
-    original_content.insert(
-        position,
+    if need_to_add_ignore_next_comment_stop {
+        insertions.insert(
+            line,
+            format!("{}{}", &read_indent, strip::IGNORE_NEXT_COMMENT_STOP,),
+        );
+    }
+    insertions.insert(
+        line,
         format!(
             "{}{}{}{}",
             &read_indent,
@@ -74,110 +362,154 @@ fn regenerate_comment(
             if is_empty { "" } else { comment }
         ),
     );
-    if need_to_add_ignore_next_comment_stop {
-        original_content.insert(
-            position,
-            format!("{}{}", &read_indent, strip::IGNORE_NEXT_COMMENT_STOP,),
-        );
+}
+
+/// Returns the original lines of the doc comments of the item at `line`.
+fn existing_doc_lines(original: &[String], mut line: usize) -> Vec<usize> {
+    let mut lines = vec![];
+
+    while line > 0 {
+        let prev = original[line - 1].trim();
+        if prev.starts_with('#') {
+            line -= 1;
+        } else if (prev.starts_with("///") && !prev.starts_with("////"))
+            || prev == strip::IGNORE_NEXT_COMMENT_STOP
+            || prev == strip::IGNORE_NEXT_COMMENT
+        {
+            line -= 1;
+            lines.push(line);
+        } else if prev.ends_with("*/") {
+            let start = match (0..line)
+                .rev()
+                .find(|&l| original[l].trim_start().starts_with("/*"))
+            {
+                Some(start) if original[start].trim_start().starts_with("/**") => start,
+                _ => break,
+            };
+            lines.extend(start..line);
+            line = start;
+        } else {
+            break;
+        }
     }
-    need_to_add_ignore_next_comment_stop
+    lines
 }
 
-#[allow(clippy::useless_let_if_seq)]
+/// Puts the entry matching `to_find` (if any) on the item at `line`. `conflict` is the policy to
+/// apply if the item already has doc comments.
 fn get_corresponding_type(
-    elements: &[(Option<TypeStruct>, Vec<String>)],
+    elements: &[Entry],
+    index: &mut EntryIndex,
     to_find: &Option<TypeStruct>,
     mut line: usize,
-    decal: &mut usize,
-    original_content: &mut Vec<String>,
+    insertions: &mut Insertions,
     ignore_macros: bool,
-) -> Option<usize> {
-    if to_find.is_none() {
-        return None;
-    }
-    let to_find = to_find.as_ref().unwrap();
-    let mut pos = 0;
-
-    while pos < elements.len() {
-        if match elements[pos].0 {
-            Some(ref a) => {
-                // is true if a is a top level Type, or if is inside a macro and Type and name match
-                /* The result is that if there is a struct defined inside a macro,
-                the documentation (if it has) of that struct will be written inside the macro. */
-                let ret = a == to_find || {
-                    let mut tmp = to_find.clone();
-                    remove_macro_parent(&mut tmp);
-                    *a == tmp
-                };
+    conflict: Option<OnConflict>,
+) -> Option<(usize, bool)> {
+    let (pos, ambiguous) = index.take(to_find.as_ref()?)?;
+    let mut file_comment = false;
 
-                // to detect variants
-                if !ret
-                    && to_find.ty == Type::Unknown
-                    && to_find.parent.is_some()
-                    && a.parent.is_some()
-                    && a.parent == to_find.parent
-                {
-                    if match to_find.parent {
-                        Some(ref p) => {
-                            p.ty == Type::Struct || p.ty == Type::Enum || p.ty == Type::Use
-                        }
-                        None => false,
-                    } {
-                        let mut tmp = to_find.clone();
-                        tmp.ty = Type::Variant;
-                        a == &tmp
-                    } else {
-                        false
-                    }
-                } else {
-                    ret
+    if !elements[pos].1.is_empty() && elements[pos].1[0].starts_with("//!") {
+        line += 1;
+        file_comment = true;
+    } else {
+        match conflict {
+            Some(OnConflict::Fail) => return Some((pos, ambiguous)),
+            Some(OnConflict::Replace) => {
+                for l in existing_doc_lines(&insertions.original, line) {
+                    insertions.remove(l);
                 }
             }
-            _ => false,
-        } {
-            let mut file_comment = false;
-
-            if !elements[pos].1.is_empty() && elements[pos].1[0].starts_with("//!") {
-                line += 1;
-                file_comment = true;
-            } else {
-                while line > 0
-                    && (line + *decal) > 0
-                    && original_content[line + *decal - 1]
-                        .trim_start()
-                        .starts_with('#')
-                {
-                    line -= 1;
+            Some(OnConflict::Prepend) => {
+                if let Some(&top) = existing_doc_lines(&insertions.original, line).last() {
+                    line = top;
                 }
             }
-            let mut first = true;
-            for comment in elements[pos].1.iter().skip(usize::from(file_comment)) {
-                let depth = if let Some(ref e) = elements[pos].0 {
-                    e.get_depth(ignore_macros)
-                } else {
-                    0
-                };
-                if regenerate_comment(
-                    file_comment,
-                    line + *decal,
-                    depth + 1,
-                    comment,
-                    original_content,
-                    first,
-                ) {
-                    *decal += 1;
-                }
-                *decal += 1;
-                first = false;
-            }
-            return Some(pos);
+            _ => {}
+        }
+        while line > 0
+            && insertions
+                .line_before(line)
+                .map(|l| l.trim_start().starts_with('#'))
+                .unwrap_or(false)
+        {
+            line -= 1;
         }
-        pos += 1;
     }
-    None
+    let depth = if let Some(ref e) = elements[pos].0 {
+        e.get_depth(ignore_macros)
+    } else {
+        0
+    };
+    let mut first = true;
+    for comment in elements[pos].1.iter().skip(usize::from(file_comment)) {
+        regenerate_comment(file_comment, line, depth + 1, comment, insertions, first);
+        first = false;
+    }
+    Some((pos, ambiguous))
+}
+
+/// Computes the content of `path` once its doc comments have been put back, without modifying
+/// anything. Returns `None` if there is nothing to regenerate in this file. Only `ignore_macros`
+/// and `on_conflict` of `options` are used.
+///
+/// The hashmap key is `Some(file name)` or `None` for entries that ignore file name.
+pub(crate) fn regenerate_file_with(
+    work_dir: &Path,
+    path: &str,
+    infos: &mut Infos,
+    options: &RegenerateOptions,
+) -> Result<Option<String>, FileError> {
+    regenerate_path(
+        &DiskFs,
+        work_dir,
+        path,
+        infos,
+        options.regen_options(),
+        &mut Notes::default(),
+    )
+}
+
+/// Same as [`regenerate_file_with`] but reads `path` from `fs` and also fills `notes`.
+fn regenerate_path(
+    fs: &dyn FileSystem,
+    work_dir: &Path,
+    path: &str,
+    infos: &mut Infos,
+    options: RegenOptions,
+    notes: &mut Notes,
+) -> Result<Option<String>, FileError> {
+    if !infos.contains_key(&None) && !infos.contains_key(&Some(path.to_owned())) {
+        return Ok(None);
+    }
+    let parse_result = fs
+        .read_to_string(&work_dir.join(path))
+        .map(|c| strip::parse_content(&c))
+        .map_err(|e| FileError::new(path, None, format!("Unable to read file: {}", e)))?;
+    regenerate_parsed(parse_result, path, infos, options, notes).map(Some)
+}
+
+fn regenerate_parsed(
+    mut parse_result: ParseResult,
+    path: &str,
+    infos: &mut Infos,
+    options: RegenOptions,
+    notes: &mut Notes,
+) -> Result<String, FileError> {
+    let mut insertions = Insertions::new(mem::take(&mut parse_result.original_content));
+    // exact path match
+    if let Some(v) = infos.get_mut(&Some(path.to_owned())) {
+        do_regenerate(&parse_result, path, &mut insertions, v, options, notes)?;
+    }
+    // apply to all files
+    if let Some(v) = infos.get_mut(&None) {
+        do_regenerate(&parse_result, path, &mut insertions, v, options, notes)?;
+    }
+    Ok(insertions.into_content())
 }
 
 // The hashmap key is `Some(file name)` or `None` for entries that ignore file name
+#[deprecated(note = "use `Stripper::regenerate` instead")]
 pub fn regenerate_comments(
     work_dir: &Path,
     path: &str,
@@ -185,148 +517,212 @@ pub fn regenerate_comments(
     ignore_macros: bool,
     ignore_doc_commented: bool,
 ) {
-    if !infos.contains_key(&None) && !infos.contains_key(&Some(path.to_owned())) {
-        return;
-    }
-    let full_path = work_dir.join(path);
-    match strip::build_event_list(&full_path) {
-        Ok(ref mut parse_result) => {
-            // exact path match
-            if let Some(v) = infos.get_mut(&Some(path.to_owned())) {
-                do_regenerate(
-                    &full_path,
-                    parse_result,
-                    v,
-                    ignore_macros,
-                    ignore_doc_commented,
-                );
-            }
-            // apply to all files
-            if let Some(v) = infos.get_mut(&None) {
-                do_regenerate(
-                    &full_path,
-                    parse_result,
-                    v,
-                    ignore_macros,
-                    ignore_doc_commented,
-                );
-            }
-        }
-        Err(e) => {
-            println!("Error in file '{}': {}", path, e);
-        }
+    let options = RegenerateOptions::new()
+        .ignore_macros(ignore_macros)
+        .on_conflict(if ignore_doc_commented {
+            OnConflict::Skip
+        } else {
+            OnConflict::Append
+        });
+    match regenerate_file_with(work_dir, path, infos, &options) {
+        Ok(Some(content)) => rewrite_file(&options.diagnostics, &work_dir.join(path), &content),
+        Ok(None) => {}
+        Err(e) => options.diagnostics.emit(&e.into()),
     }
 }
 
-fn check_if_regen(it: usize, parse_result: &ParseResult, ignore_doc_commented: bool) -> bool {
-    ignore_doc_commented
-        && it > 0
-        && matches!(
-            parse_result.event_list[it - 1].event,
-            EventType::Comment(_) | EventType::FileComment(_)
-        )
+/// Returns `true` if the item of the event `it` already has doc comments. Inner doc comments
+/// (`//!` and `/*! */`) right before the item are the ones of its parent.
+fn check_if_regen(it: usize, parse_result: &ParseResult) -> bool {
+    // The `pub` of fields is kept in the event list.
+    let mut it = it;
+    while it > 0
+        && matches!(parse_result.event_list[it - 1].event,
+            EventType::Type(ref t) if t.ty == Type::Unknown && t.name == "pub")
+    {
+        it -= 1;
+    }
+    if it == 0 {
+        return false;
+    }
+    let prev = &parse_result.event_list[it - 1];
+    match prev.event {
+        EventType::Comment(_) => true,
+        // The end of a `/** */` or of a `/*! */` block, the event being on its first line.
+        EventType::FileComment(ref c) if c == "*/" => parse_result
+            .original_content
+            .get(prev.line)
+            .is_some_and(|l| l.trim_start().starts_with("/**")),
+        _ => false,
+    }
 }
 
 fn do_regenerate(
-    path: &Path,
-    parse_result: &mut ParseResult,
-    elements: &mut Vec<(Option<TypeStruct>, Vec<String>)>,
-    ignore_macros: bool,
-    ignore_doc_commented: bool,
-) {
-    let mut position = 0;
-    let mut decal = 0;
+    parse_result: &ParseResult,
+    path: &str,
+    insertions: &mut Insertions,
+    elements: &mut Vec<Entry>,
+    options: RegenOptions,
+    notes: &mut Notes,
+) -> Result<(), FileError> {
+    let ignore_macros = options.ignore_macros;
+    let mut used = vec![false; elements.len()];
 
     // first, we need to put back file comment
-    for entry in elements.iter() {
-        if entry.0.is_none() {
-            let mut it = 0;
+    if let Some(position) = elements.iter().position(|entry| entry.0.is_none()) {
+        let original = &insertions.original;
+        let mut it = 0;
 
-            while it < parse_result.original_content.len()
-                && parse_result.original_content[it].starts_with('/')
-            {
-                it += 1;
+        while it < original.len() && original[it].starts_with('/') {
+            it += 1;
+        }
+        if it > 0 {
+            it += 1;
+        }
+        if it < original.len() {
+            for line in &elements[position].1 {
+                if line.trim().is_empty() {
+                    insertions.insert(it, "//!".to_string());
+                } else {
+                    insertions.insert(it, format!("//! {}", &line));
+                }
             }
-            if it > 0 {
-                it += 1;
+        }
+        insertions.insert(it, "".to_owned());
+        used[position] = true;
+    }
+    let mut index = EntryIndex::new(elements, &used);
+    let ordinals = strip::item_ordinals(parse_result);
+    let mut waiting_type = None;
+    let mut current = None;
+
+    for (it, event) in parse_result.event_list.iter().enumerate() {
+        let item = match event.event {
+            EventType::Type(ref t) => {
+                if t.ty != Type::Unknown {
+                    waiting_type = Some(t.clone());
+                }
+                strip::event_item(&current, t)
             }
-            if it < parse_result.original_content.len() {
-                for line in &entry.1 {
-                    if line.trim().is_empty() {
-                        parse_result.original_content.insert(it, "//!".to_string());
-                    } else {
-                        parse_result
-                            .original_content
-                            .insert(it, format!("//! {}", &line));
-                    }
-                    decal += 1;
-                    it += 1;
+            EventType::InScope => {
+                current = strip::add_to_type_scope(&current, &waiting_type);
+                waiting_type = None;
+                None
+            }
+            EventType::OutScope => {
+                current = strip::type_out_scope(&current);
+                waiting_type = None;
+                None
+            }
+            _ => None,
+        };
+        if item.is_none() {
+            continue;
+        }
+        let mut item = if ignore_macros {
+            erase_macro_path(item)
+        } else {
+            item
+        };
+        if let (Some(item), Some(&ordinal)) = (item.as_mut(), ordinals.get(&it)) {
+            item.ordinal = ordinal;
+        }
+        let conflict = if check_if_regen(it, parse_result) {
+            if options.on_conflict == OnConflict::Skip {
+                continue;
+            }
+            Some(options.on_conflict)
+        } else {
+            None
+        };
+        if let Some((l, ambiguous)) = get_corresponding_type(
+            elements,
+            &mut index,
+            &item,
+            event.line,
+            insertions,
+            ignore_macros,
+            conflict,
+        ) {
+            used[l] = true;
+            let item = match item {
+                Some(item) => item,
+                None => continue,
+            };
+            let location = ItemLocation {
+                file: path.to_owned(),
+                item,
+                line: event.line + 1,
+            };
+            if let Some(policy) = conflict {
+                if policy == OnConflict::Fail {
+                    return Err(FileError::new(
+                        path,
+                        Some(location.line),
+                        format!("`{}` already has doc comments", location.item),
+                    ));
                 }
+                notes.conflicts.push(location.clone());
+            }
+            if ambiguous {
+                notes.ambiguous.push(location);
             }
-            parse_result.original_content.insert(it, "".to_owned());
-            decal += 1;
-            break;
         }
-        position += 1;
-    }
-    if position < elements.len() {
-        elements.remove(position);
     }
+    let mut used = used.into_iter();
+    elements.retain(|_| !used.next().unwrap_or(false));
+    Ok(())
+}
+
+/// Returns the paths of the items of `parse_result`, as they would be looked for by
+/// `do_regenerate`, and whether they already have doc comments.
+pub(crate) fn file_items(
+    parse_result: &ParseResult,
+    ignore_macros: bool,
+) -> Vec<(TypeStruct, bool)> {
+    items_of(parse_result, ignore_macros)
+        .into_iter()
+        .map(|(_, item, documented)| (item, documented))
+        .collect()
+}
+
+/// Same as [`file_items`] with the position of the event of each item.
+fn items_of(parse_result: &ParseResult, ignore_macros: bool) -> Vec<(usize, TypeStruct, bool)> {
+    let ordinals = strip::item_ordinals(parse_result);
+    let mut items = vec![];
     let mut waiting_type = None;
     let mut current = None;
-    let mut it = 0;
 
-    while it < parse_result.event_list.len() {
-        match parse_result.event_list[it].event {
+    for (it, event) in parse_result.event_list.iter().enumerate() {
+        match event.event {
             EventType::Type(ref t) => {
-                if t.ty != Type::Unknown {
+                let item = if t.ty != Type::Unknown {
                     waiting_type = Some(t.clone());
-                    let tmp = {
-                        let t = strip::add_to_type_scope(&current, &waiting_type);
-                        if ignore_macros {
-                            erase_macro_path(t)
-                        } else {
-                            t
-                        }
-                    };
-
-                    if !check_if_regen(it, parse_result, ignore_doc_commented) {
-                        if let Some(l) = get_corresponding_type(
-                            elements,
-                            &tmp,
-                            parse_result.event_list[it].line,
-                            &mut decal,
-                            &mut parse_result.original_content,
-                            ignore_macros,
-                        ) {
-                            elements.remove(l);
+                    strip::add_to_type_scope(&current, &waiting_type)
+                } else {
+                    match current {
+                        Some(ref c)
+                            if (c.ty == Type::Struct || c.ty == Type::Enum)
+                                && is_field_name(parse_result, it, c.ty) =>
+                        {
+                            let mut t = t.clone();
+                            t.ty = Type::Variant;
+                            strip::add_to_type_scope(&current, &Some(t))
                         }
-                    }
-                } else if let Some(ref c) = current {
-                    if c.ty == Type::Struct || c.ty == Type::Enum || c.ty == Type::Mod {
-                        let tmp = Some(t.clone());
-                        let cc = {
-                            let t = strip::add_to_type_scope(&current, &tmp);
-                            if ignore_macros {
-                                erase_macro_path(t)
-                            } else {
-                                t
-                            }
-                        };
-
-                        if !check_if_regen(it, parse_result, ignore_doc_commented) {
-                            if let Some(l) = get_corresponding_type(
-                                elements,
-                                &cc,
-                                parse_result.event_list[it].line,
-                                &mut decal,
-                                &mut parse_result.original_content,
-                                ignore_macros,
-                            ) {
-                                elements.remove(l);
-                            }
+                        Some(ref c) if c.ty == Type::Mod => {
+                            strip::add_to_type_scope(&current, &Some(t.clone()))
                         }
+                        _ => None,
                     }
+                };
+                let item = if ignore_macros {
+                    erase_macro_path(item)
+                } else {
+                    item
+                };
+                if let Some(mut item) = item {
+                    item.ordinal = ordinals.get(&it).cloned().unwrap_or(0);
+                    items.push((it, item, check_if_regen(it, parse_result)));
                 }
             }
             EventType::InScope => {
@@ -339,93 +735,304 @@ fn do_regenerate(
             }
             _ => {}
         }
-        it += 1;
     }
-    rewrite_file(path, &parse_result.original_content);
+    items
 }
 
-fn rewrite_file(path: &Path, o_content: &[String]) {
-    match File::create(path) {
-        Ok(mut f) => {
-            write!(f, "{}", o_content.join("\n")).unwrap();
-        }
-        Err(e) => {
-            println!("Cannot open '{}': {}", path.display(), e);
-        }
+/// Returns `true` if the word of the event `it`, in a struct or an enum (`parent`), is the name
+/// of a field or of a variant and not a part of a type: the word followed by `:` in a struct, the
+/// first word of the line in an enum.
+fn is_field_name(parse_result: &ParseResult, it: usize, parent: Type) -> bool {
+    let events = &parse_result.event_list;
+    let word = |pos: usize| match events.get(pos).map(|e| &e.event) {
+        Some(EventType::Type(ref t)) if t.ty == Type::Unknown => Some(&t.name[..]),
+        _ => None,
+    };
+    let name = match word(it) {
+        Some(name) if !name.is_empty() && name != "pub" => name,
+        _ => return false,
+    };
+    if parent == Type::Struct {
+        // `:` is the only word which is empty once the stop characters have been removed.
+        word(it + 1) == Some("")
+    } else {
+        name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && (it == 0
+                || word(it - 1).is_none_or(|w| w == "pub")
+                || events[it - 1].line != events[it].line)
     }
 }
 
-fn parse_mod_line(line: &str) -> Option<TypeStruct> {
-    let line = line
-        .replace(FILE_COMMENT, "")
-        .replace(MOD_COMMENT, "")
-        .replace(END_INFO, "");
-    if line.is_empty() {
-        return None;
-    }
-    let parts: Vec<&str> = line.split("::").collect();
-    let mut current = None;
-
-    for part in parts {
-        let elems: Vec<&str> = part.split(' ').filter(|x| !x.is_empty()).collect();
+/// The items of each file, whether they are documented and their signature.
+type FileItems = Vec<(String, Vec<(TypeStruct, bool, Option<u64>)>)>;
 
-        current = strip::add_to_type_scope(
-            &current.clone(),
-            &Some(TypeStruct::new(
-                Type::from(elems[0]),
-                elems[elems.len() - 1],
-            )),
-        );
+/// Lists the items of every file of `files`, taking into account the modifications made in
+/// `regenerated`.
+fn parse_items(
+    fs: &dyn FileSystem,
+    work_dir: &Path,
+    files: &[String],
+    regenerated: &[(String, String)],
+    options: RegenOptions,
+    jobs: usize,
+) -> Result<FileItems, Vec<FileError>> {
+    let mut errors = vec![];
+    let mut items = vec![];
+    for (path, ret) in files.iter().zip(parallel_map(files, jobs, |path| {
+        current_content(fs, work_dir, path, regenerated).map(|c| {
+            let parse_result = strip::parse_content(&c);
+            items_of(&parse_result, options.ignore_macros)
+                .into_iter()
+                .map(|(it, item, documented)| {
+                    (item, documented, strip::item_signature(&parse_result, it))
+                })
+                .collect()
+        })
+    })) {
+        match ret {
+            Ok(i) => items.push((path.clone(), i)),
+            Err(e) => errors.push(e),
+        }
+    }
+    if errors.is_empty() {
+        Ok(items)
+    } else {
+        Err(errors)
     }
-    current
 }
 
-fn save_remainings(infos: &Infos, comment_file: &str) {
-    let mut remainings = 0;
+/// Looks for the undocumented items of `items` which are likely to be the new location of the
+/// entries of `infos` which couldn't be placed (because their item has been renamed or moved),
+/// comparing the `signatures` of the items if known. If `apply` is `true`, the entries are put
+/// there, otherwise the matches are only printed.
+///
+/// `regenerated` contains the files already modified and gets the newly modified ones.
+#[allow(clippy::too_many_arguments)]
+fn reconcile(
+    fs: &dyn FileSystem,
+    work_dir: &Path,
+    files: &[String],
+    items: &FileItems,
+    regenerated: &mut Vec<(String, String)>,
+    infos: &mut Infos,
+    signatures: &Signatures,
+    options: RegenOptions,
+    diagnostics: &dyn Diagnostics,
+    apply: bool,
+) -> Result<(), Vec<FileError>> {
+    let mut keys = infos
+        .iter()
+        .filter(|&(_, entries)| entries.iter().any(|e| e.0.is_some()))
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    if keys.is_empty() {
+        return Ok(());
+    }
+    keys.sort();
+    let orphans = keys
+        .iter()
+        .flat_map(|key| {
+            infos[key]
+                .iter()
+                .enumerate()
+                .filter_map(move |(pos, e)| e.0.as_ref().map(|ty| (key, pos, ty)))
+        })
+        .collect::<Vec<_>>();
+    let undocumented = items
+        .iter()
+        .map(|(path, items)| {
+            let items = items
+                .iter()
+                .filter(|&&(_, documented, _)| !documented)
+                .map(|(item, _, signature)| (item.clone(), *signature))
+                .collect();
+            (path.clone(), items)
+        })
+        .collect::<Vec<_>>();
+    let matches = find_matches(
+        &orphans
+            .iter()
+            .map(|&(key, _, ty)| {
+                let signature = signatures.get(&(key.clone(), ty.clone())).cloned();
+                (key.as_ref().map(|s| &s[..]), ty, signature)
+            })
+            .collect::<Vec<_>>(),
+        &undocumented,
+    );
 
-    for content in infos.values() {
-        if !content.is_empty() {
-            remainings += 1;
+    let mut moved: HashMap<Option<String>, Vec<usize>> = HashMap::new();
+    let mut to_place: Infos = HashMap::new();
+    for (&(key, pos, ty), suggestion) in orphans.iter().zip(matches) {
+        let suggestion = match suggestion {
+            Some(s) => s,
+            None => continue,
+        };
+        if !apply {
+            diagnostics.emit(&Diagnostic::warning(format!(
+                "Documentation of `{}` could go to `{}` in '{}' ({}).",
+                ty, suggestion.item, suggestion.file, suggestion.kind
+            )));
+            continue;
         }
+        diagnostics.emit(&Diagnostic::info(format!(
+            "Documentation of `{}` has been put on `{}` in '{}' ({}).",
+            ty, suggestion.item, suggestion.file, suggestion.kind
+        )));
+        moved.entry(key.clone()).or_default().push(pos);
+        to_place
+            .entry(Some(suggestion.file))
+            .or_default()
+            .push((Some(suggestion.item), infos[key][pos].1.clone()));
     }
-    if remainings < 1 {
-        let _ = remove_file(comment_file);
-        return;
+    for (key, positions) in moved {
+        if let Some(entries) = infos.get_mut(&key) {
+            let mut pos = 0;
+            entries.retain(|_| {
+                pos += 1;
+                !positions.contains(&(pos - 1))
+            });
+        }
     }
-    match File::create(comment_file) {
-        Ok(mut out_file) => {
-            println!(
-                "Some comments haven't been regenerated to the files. Saving them \
-                      back to '{}'.",
-                comment_file
-            );
-            for (key, content) in infos {
-                if content.is_empty() {
-                    continue;
-                }
-                // Set the name to "*" for entries that ignore file name
-                let key = key.as_ref().map(|s| &s[..]).unwrap_or("*");
-                let _ = writeln!(out_file, "{}", &write_file(key));
-                for line in content {
-                    if let Some(ref d) = line.0 {
-                        let _ = writeln!(
-                            out_file,
-                            "{}",
-                            write_comment(d, &join(&line.1, "\n"), false)
-                        );
+
+    let mut targets = to_place
+        .keys()
+        .filter_map(|k| k.clone())
+        .collect::<Vec<_>>();
+    targets.sort();
+    for path in targets {
+        let content = current_content(fs, work_dir, &path, regenerated).map_err(|e| vec![e])?;
+        let content = regenerate_parsed(
+            strip::parse_content(&content),
+            &path,
+            &mut to_place,
+            options,
+            &mut Notes::default(),
+        )
+        .map_err(|e| vec![e])?;
+        match regenerated.iter_mut().find(|r| r.0 == path) {
+            Some(r) => r.1 = content,
+            None => regenerated.push((path, content)),
+        }
+    }
+    regenerated.sort_by_key(|r| files.iter().position(|f| *f == r.0));
+    Ok(())
+}
+
+/// Lists the entries of `infos` with the reason why they haven't been put back.
+fn unplaced_entries(
+    files: &[String],
+    items: &FileItems,
+    infos: &Infos,
+    on_conflict: OnConflict,
+) -> Vec<Unplaced> {
+    let mut keys = infos.keys().collect::<Vec<_>>();
+    keys.sort();
+    let mut unplaced = vec![];
+
+    for key in keys {
+        let file_exists = match *key {
+            Some(ref file) => files.contains(file),
+            None => true,
+        };
+        for entry in &infos[key] {
+            let reason = match entry.0 {
+                _ if !file_exists => Reason::FileNotFound,
+                Some(ref ty) => {
+                    let mut without_macro = ty.clone();
+                    remove_macro_parent(&mut without_macro);
+                    let documented = items
+                        .iter()
+                        .filter(|&(path, _)| key.as_ref().map(|k| k == path).unwrap_or(true))
+                        .flat_map(|(_, items)| items)
+                        .filter(|&(item, _, _)| *item == *ty || *item == without_macro)
+                        .any(|&(_, documented, _)| documented);
+                    if documented && on_conflict == OnConflict::Skip {
+                        Reason::AlreadyDocumented
+                    } else {
+                        Reason::ItemNotFound
                     }
                 }
-            }
+                None => Reason::ItemNotFound,
+            };
+            unplaced.push(Unplaced {
+                file: key.clone(),
+                item: entry.0.clone(),
+                reason,
+            });
         }
-        Err(e) => {
-            println!(
-                "An error occured while trying to open '{}': {}",
-                comment_file, e
+    }
+    unplaced
+}
+
+fn current_content(
+    fs: &dyn FileSystem,
+    work_dir: &Path,
+    path: &str,
+    regenerated: &[(String, String)],
+) -> Result<String, FileError> {
+    match regenerated.iter().find(|r| r.0 == path) {
+        Some(r) => Ok(r.1.clone()),
+        None => fs
+            .read_to_string(&work_dir.join(path))
+            .map_err(|e| FileError::new(path, None, format!("Unable to read file: {}", e))),
+    }
+}
+
+fn rewrite_file(diagnostics: &dyn Diagnostics, path: &Path, content: &str) {
+    if let Err(e) = write_atomically(path, content.as_bytes()) {
+        diagnostics.emit(
+            &Diagnostic::error(format!("Cannot write file: {}", e))
+                .with_path(path.display().to_string()),
+        );
+    }
+}
+
+/// Returns the item path of the entry header `line`, `None` if it has none.
+pub(crate) fn parse_mod_line(line: &str) -> Result<Option<TypeStruct>, ParsePathError> {
+    let line = line
+        .replace(FILE_COMMENT, "")
+        .replace(MOD_COMMENT, "")
+        .replace(END_INFO, "");
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    line.parse().map(Some)
+}
+
+fn save_remainings(
+    fs: &dyn FileSystem,
+    diagnostics: &dyn Diagnostics,
+    file: &CommentsFile,
+    comment_file: &Path,
+) {
+    if file.is_empty() {
+        if let Err(e) = fs.remove_file(comment_file) {
+            diagnostics.emit(
+                &Diagnostic::error(format!("Cannot remove file: {}", e))
+                    .with_path(comment_file.display().to_string()),
             );
         }
+        return;
+    }
+    diagnostics.emit(&Diagnostic::info(format!(
+        "Saving the remaining comments back to '{}'.",
+        comment_file.display()
+    )));
+    if let Err(e) = fs.write(comment_file, file.to_string().as_bytes()) {
+        diagnostics.emit(
+            &Diagnostic::error(format!("Cannot write file: {}", e))
+                .with_path(comment_file.display().to_string()),
+        );
     }
 }
 
+/// Puts back the doc comments from `comment_file` into the files of `directory`.
+///
+/// Every file is processed before any of them is written: if an error occurs, nothing is
+/// modified and the errors are sent to the diagnostics sink of the options. If one of them can't
+/// be written, the ones which already have been are restored and `comment_file` is left
+/// untouched. Use [`Stripper::regenerate`] to get the errors and the report of the regeneration.
+#[deprecated(note = "use `Stripper::regenerate` instead")]
 pub fn regenerate_doc_comments(
     directory: &str,
     verbose: bool,
@@ -433,29 +1040,415 @@ pub fn regenerate_doc_comments(
     ignore_macros: bool,
     ignore_doc_commented: bool,
 ) {
+    let options = RegenerateOptions::new()
+        .verbose(verbose)
+        .ignore_macros(ignore_macros)
+        .on_conflict(if ignore_doc_commented {
+            OnConflict::Skip
+        } else {
+            OnConflict::Append
+        })
+        .update_comment_file(true);
+    if let Err(errors) = Stripper::new(directory, comment_file).regenerate(&options) {
+        for e in errors {
+            options.diagnostics.emit(&e.into());
+        }
+    }
+}
+
+/// Puts back the doc comments from `comment_file` into the files of `work_dir` (or into `files`
+/// if given). If `check` is `true`, nothing is written. If one of the files can't be written,
+/// the ones which already have been are restored and `comment_file` isn't modified.
+pub(crate) fn regenerate(
+    fs: &dyn FileSystem,
+    work_dir: &Path,
+    comment_file: &Path,
+    files: Option<&[String]>,
+    options: &RegenerateOptions,
+    cache: Option<&mut Cache>,
+    check: bool,
+) -> Result<Report, Vec<FileError>> {
+    let (regenerated, remaining, report) =
+        prepare_regeneration(fs, work_dir, comment_file, files, options, cache)?;
+    if check {
+        return Ok(report);
+    }
+    // The comments file is only updated once all the files have been written.
+    write_all(
+        fs,
+        work_dir,
+        regenerated.iter().map(|(p, c)| (&p[..], &c[..])),
+    )?;
+    if options.update_comment_file {
+        save_remainings(fs, &options.diagnostics, &remaining, comment_file);
+    }
+    Ok(report)
+}
+
+/// New content of files, by path.
+pub type Modified = Vec<(String, String)>;
+type Prepared = (Modified, CommentsFile, Report);
+
+/// Reads `comment_file` and computes the new content of the files of `work_dir` (or of `files`
+/// if given) which have to be modified. Also returns the entries which haven't been put back.
+pub(crate) fn prepare_regeneration(
+    fs: &dyn FileSystem,
+    work_dir: &Path,
+    comment_file: &Path,
+    files: Option<&[String]>,
+    options: &RegenerateOptions,
+    cache: Option<&mut Cache>,
+) -> Result<Prepared, Vec<FileError>> {
+    let regen = options.regen_options();
     // we start by storing files info
-    let f = match OpenOptions::new().read(true).open(comment_file) {
-        Ok(f) => f,
-        Err(e) => {
-            println!(
-                "An error occured while trying to open '{}': {}",
-                comment_file, e
+    let comments = CommentsFile::read_from(fs, comment_file).map_err(|e| vec![e])?;
+    let signatures = signatures_of(&comments, options.ignore_macros);
+    let mut infos = infos_of(comments, options.ignore_macros);
+    let (files, mut skipped) = match files {
+        Some(files) => {
+            // Only the sections of the listed files and the `<!-- file * -->` ones are used.
+            let skipped = infos
+                .keys()
+                .filter(|key| key.as_ref().is_some_and(|k| !files.contains(k)))
+                .cloned()
+                .collect::<Vec<_>>();
+            let skipped = skipped
+                .into_iter()
+                .filter_map(|key| infos.remove_entry(&key))
+                .collect::<Infos>();
+            (files.to_vec(), skipped)
+        }
+        None => (
+            listed(list_files_of(
+                fs,
+                &options.diagnostics,
+                work_dir,
+                &options.filter,
+                options.verbose,
+            ))?,
+            Infos::new(),
+        ),
+    };
+    if !options.items.is_empty() {
+        // The doc comments which aren't selected stay in the comments file.
+        for (key, entries) in infos.iter_mut() {
+            let (kept, others) = entries.drain(..).partition(|(path, lines)| {
+                // Inner doc comments are marked by a `//!` line.
+                let kind = if path.is_none() || lines.first().is_some_and(|l| l == "//!") {
+                    EntryKind::FileComment
+                } else {
+                    EntryKind::Item
+                };
+                options.items.selects(key.as_deref(), kind, path.as_ref())
+            });
+            *entries = kept;
+            if !others.is_empty() {
+                skipped.entry(key.clone()).or_default().extend(others);
+            }
+        }
+    }
+    if !options.items.visibility.is_empty() {
+        // The visibility of the items is only known from the source files.
+        for (file, items) in parse_items(fs, work_dir, &files, &[], regen, options.jobs)? {
+            let hidden = items
+                .iter()
+                .filter(|(item, _, _)| !options.items.selects_visibility(Some(item)))
+                .map(|(item, _, _)| item)
+                .collect::<Vec<_>>();
+            let key = Some(file);
+            let entries = match infos.get_mut(&key) {
+                Some(entries) => entries,
+                None => continue,
+            };
+            let (others, kept) = entries.drain(..).partition(|(path, _)| match *path {
+                Some(ref path) => hidden.contains(&path),
+                None => !options.items.selects_visibility(None),
+            });
+            *entries = kept;
+            if !others.is_empty() {
+                skipped.entry(key).or_default().extend(others);
+            }
+        }
+    }
+    let (mut regenerated, notes) =
+        regenerate_files(fs, work_dir, &files, &mut infos, regen, options.jobs, cache)?;
+    let mut unplaced = vec![];
+    if infos.values().any(|entries| !entries.is_empty()) {
+        // Without fuzzy matching, only the files the remaining entries belong to are needed to
+        // know why they haven't been put back, all of them for the `<!-- file * -->` ones.
+        let parsed = if options.fuzzy || infos.get(&None).is_some_and(|e| !e.is_empty()) {
+            files.clone()
+        } else {
+            files
+                .iter()
+                .filter(|f| {
+                    infos
+                        .get(&Some(f.to_string()))
+                        .is_some_and(|e| !e.is_empty())
+                })
+                .cloned()
+                .collect()
+        };
+        let items = parse_items(fs, work_dir, &parsed, &regenerated, regen, options.jobs)?;
+        reconcile(
+            fs,
+            work_dir,
+            &files,
+            &items,
+            &mut regenerated,
+            &mut infos,
+            &signatures,
+            regen,
+            &options.diagnostics,
+            options.fuzzy,
+        )?;
+        unplaced = unplaced_entries(&files, &items, &infos, regen.on_conflict);
+    }
+    let report = Report {
+        modified: regenerated.iter().map(|r| r.0.clone()).collect(),
+        conflicts: notes.conflicts,
+        ambiguous: notes.ambiguous,
+        unplaced,
+    };
+    for (key, entries) in skipped {
+        infos.entry(key).or_default().extend(entries);
+    }
+    Ok((regenerated, comments_file_of(&infos, &signatures), report))
+}
+
+#[derive(Clone, Copy)]
+struct RegenOptions {
+    ignore_macros: bool,
+    on_conflict: OnConflict,
+}
+
+/// Items which need to be reported.
+#[derive(Default)]
+struct Notes {
+    conflicts: Vec<ItemLocation>,
+    ambiguous: Vec<ItemLocation>,
+}
+
+enum Outcome {
+    /// The file content has to be replaced.
+    Modified(String, Notes),
+    /// Nothing has been put into the file. Contains the hashes of the file content and of the
+    /// entries if they've been computed.
+    Unchanged(Option<(u64, u64)>),
+}
+
+fn entries_hash(entries: Option<&Entries>) -> u64 {
+    hash(format!("{:?}", entries).as_bytes())
+}
+
+/// Regenerates `path` unless `cache` knows that nothing can be put into it. `all_files_hash` is
+/// the hash of the `<!-- file * -->` entries if it has already been computed.
+fn regenerate_cached(
+    fs: &dyn FileSystem,
+    work_dir: &Path,
+    path: &str,
+    infos: &mut Infos,
+    options: RegenOptions,
+    cache: Option<&Cache>,
+    all_files_hash: Option<u64>,
+) -> Result<Outcome, FileError> {
+    let key = Some(path.to_owned());
+    if !infos.contains_key(&None) && !infos.contains_key(&key) {
+        return Ok(Outcome::Unchanged(None));
+    }
+    let hashes = match cache {
+        Some(cache) => {
+            let content = fs
+                .read(&work_dir.join(path))
+                .map_err(|e| FileError::new(path, None, format!("Unable to read file: {}", e)))?;
+            let content = hash(&content);
+            let entries = hash(
+                format!(
+                    "{} {:?} {:016x} {:016x}",
+                    options.ignore_macros,
+                    options.on_conflict,
+                    entries_hash(infos.get(&key)),
+                    all_files_hash.unwrap_or_else(|| entries_hash(infos.get(&None))),
+                )
+                .as_bytes(),
             );
-            return;
+            if cache.is_regenerated(path, content, entries) {
+                return Ok(Outcome::Unchanged(None));
+            }
+            Some((content, entries))
         }
+        None => None,
     };
-    let reader = BufReader::new(f);
-    let lines = reader.lines().map(|line| line.unwrap());
-    let mut infos = parse_cmts(lines, ignore_macros);
-    let ignores: &[&str] = &[];
-
-    loop_over_files(
-        directory.as_ref(),
-        &mut |w, s| regenerate_comments(w, s, &mut infos, ignore_macros, ignore_doc_commented),
-        ignores,
-        verbose,
-    );
-    save_remainings(&infos, comment_file);
+    let count = |infos: &Infos| infos.get(&key).map(|v| v.len()).unwrap_or(0);
+    let all_files_count = |infos: &Infos| infos.get(&None).map(|v| v.len()).unwrap_or(0);
+    let before = (count(infos), all_files_count(infos));
+    let mut notes = Notes::default();
+    let content = regenerate_path(fs, work_dir, path, infos, options, &mut notes)?;
+    match content {
+        Some(content) if before != (count(infos), all_files_count(infos)) => {
+            Ok(Outcome::Modified(content, notes))
+        }
+        _ => Ok(Outcome::Unchanged(hashes)),
+    }
+}
+
+type Regenerated = (Vec<(String, String)>, Notes);
+
+/// Computes the new content of each file of `files` which has to be modified, in the same
+/// order, and what happened to the items. `infos` only keeps the entries which couldn't be
+/// placed.
+fn regenerate_files(
+    fs: &dyn FileSystem,
+    work_dir: &Path,
+    files: &[String],
+    infos: &mut Infos,
+    options: RegenOptions,
+    jobs: usize,
+    mut cache: Option<&mut Cache>,
+) -> Result<Regenerated, Vec<FileError>> {
+    let mut regenerated = vec![];
+    let mut notes = Notes::default();
+    let mut errors = vec![];
+    let mut add_result = |path: &str, ret, cache: &mut Option<&mut Cache>| match ret {
+        Ok(Outcome::Modified(content, c)) => {
+            regenerated.push((path.to_owned(), content));
+            notes.conflicts.extend(c.conflicts);
+            notes.ambiguous.extend(c.ambiguous);
+        }
+        Ok(Outcome::Unchanged(hashes)) => {
+            if let (Some(cache), Some((content, entries))) = (cache, hashes) {
+                cache.set_regenerated(path, content, entries);
+            }
+        }
+        Err(e) => errors.push(e),
+    };
+
+    if jobs_count(jobs) <= 1 {
+        let mut all_files_hash = None;
+        for path in files {
+            if cache.is_some() && all_files_hash.is_none() {
+                all_files_hash = Some(entries_hash(infos.get(&None)));
+            }
+            let ret = regenerate_cached(
+                fs,
+                work_dir,
+                path,
+                infos,
+                options,
+                cache.as_deref(),
+                all_files_hash,
+            );
+            if let Ok(Outcome::Modified(..)) = ret {
+                // Some `<!-- file * -->` entries might have been used.
+                all_files_hash = None;
+            }
+            add_result(path, ret, &mut cache);
+        }
+    } else {
+        // Entries of `<!-- file * -->` go into the first file (in `files` order) where they
+        // match. To get the same result as when running sequentially, every file is processed
+        // with all of them and then, if a file used an entry already used by a previous file,
+        // it's processed again with the remaining ones.
+        let all_files = infos.remove(&None);
+        let all_files_hash = cache.as_ref().map(|_| entries_hash(all_files.as_ref()));
+        let items = files
+            .iter()
+            .map(|path| (path.clone(), infos.remove(&Some(path.clone()))))
+            .collect::<Vec<_>>();
+        let regen = |path: &str,
+                     own: Option<Entries>,
+                     all_files: Option<Entries>,
+                     cache: Option<&Cache>,
+                     all_files_hash: Option<u64>| {
+            let mut file_infos = HashMap::new();
+            if let Some(own) = own {
+                file_infos.insert(Some(path.to_owned()), own);
+            }
+            if let Some(all_files) = all_files {
+                file_infos.insert(None, all_files);
+            }
+            let ret = regenerate_cached(
+                fs,
+                work_dir,
+                path,
+                &mut file_infos,
+                options,
+                cache,
+                all_files_hash,
+            );
+            (ret, file_infos)
+        };
+        let results = {
+            let cache = cache.as_deref();
+            parallel_map(&items, jobs, |(path, own)| {
+                regen(path, own.clone(), all_files.clone(), cache, all_files_hash)
+            })
+        };
+        let all_files = all_files.unwrap_or_default();
+        let mut used = vec![false; all_files.len()];
+
+        for ((path, own), (ret, file_infos)) in items.into_iter().zip(results) {
+            let mut consumed = removed_entries(&all_files, file_infos.get(&None));
+            let (ret, mut file_infos) = if consumed.iter().any(|&i| used[i]) {
+                let available = (0..all_files.len())
+                    .filter(|&i| !used[i])
+                    .collect::<Vec<_>>();
+                let entries = available
+                    .iter()
+                    .map(|&i| all_files[i].clone())
+                    .collect::<Vec<_>>();
+                let (ret, file_infos) =
+                    regen(&path, own, Some(entries.clone()), cache.as_deref(), None);
+                consumed = removed_entries(&entries, file_infos.get(&None))
+                    .into_iter()
+                    .map(|i| available[i])
+                    .collect();
+                (ret, file_infos)
+            } else {
+                (ret, file_infos)
+            };
+            for i in consumed {
+                used[i] = true;
+            }
+            if let Some(remaining) = file_infos.remove(&Some(path.clone())) {
+                infos.insert(Some(path.clone()), remaining);
+            }
+            add_result(&path, ret, &mut cache);
+        }
+        if !all_files.is_empty() {
+            infos.insert(
+                None,
+                all_files
+                    .into_iter()
+                    .zip(used)
+                    .filter(|&(_, used)| !used)
+                    .map(|(entry, _)| entry)
+                    .collect(),
+            );
+        }
+    }
+    if errors.is_empty() {
+        Ok((regenerated, notes))
+    } else {
+        Err(errors)
+    }
+}
+
+/// Returns the positions in `entries` of the entries missing from `remaining`, which is what is
+/// left of `entries` once some of them have been removed.
+fn removed_entries(entries: &[Entry], remaining: Option<&Entries>) -> Vec<usize> {
+    let remaining = remaining.map(|r| &r[..]).unwrap_or(&[]);
+    let mut removed = vec![];
+    let mut it = 0;
+
+    for (pos, entry) in entries.iter().enumerate() {
+        if remaining.get(it) == Some(entry) {
+            it += 1;
+        } else {
+            removed.push(pos);
+        }
+    }
+    removed
 }
 
 fn sub_erase_macro_path(ty: Option<Box<TypeStruct>>, is_parent: bool) -> Option<Box<TypeStruct>> {
@@ -482,119 +1475,110 @@ fn erase_macro_path(ty: Option<TypeStruct>) -> Option<TypeStruct> {
     ty.map(|t| *sub_erase_macro_path(Some(Box::new(t)), false).unwrap())
 }
 
+/// Parses the content of a comments file. If it can't be, the error is sent to the diagnostics
+/// sink and no entries are returned.
+#[deprecated(note = "use `CommentsFile::parse` instead")]
 pub fn parse_cmts<S, I>(lines: I, ignore_macros: bool) -> Infos
 where
     S: Deref<Target = str>,
     I: Iterator<Item = S>,
 {
-    enum State {
-        Initial,
-        File {
-            file: Option<String>,
-            infos: Vec<(Option<TypeStruct>, Vec<String>)>,
-            ty: Option<TypeStruct>,
-            comments: Vec<String>,
-        },
-    }
-
-    // Returns `Some(name)` if the line matches FILE
-    // where name is Some for an actual file name and None for "*"
-    // The "*" entries are to be applied regardless of file name
-    #[allow(clippy::option_option)]
-    fn line_file(line: &str) -> Option<Option<String>> {
-        if let Some(after) = line.strip_prefix(FILE) {
-            let name = after.replace(END_INFO, "");
-            if name == "*" {
-                Some(None)
-            } else {
-                Some(Some(name))
-            }
-        } else {
-            None
+    let options = RegenerateOptions::new().ignore_macros(ignore_macros);
+    let content = lines
+        .map(|l| format!("{}\n", l.deref()))
+        .collect::<String>();
+    match CommentsFile::parse(&content) {
+        Ok(file) => infos_of(file, options.ignore_macros),
+        Err(e) => {
+            options.diagnostics.emit(&e.into());
+            Infos::new()
         }
     }
+}
 
-    let mut ret = HashMap::new();
-    let mut state = State::Initial;
-
-    for line in lines {
-        state = match state {
-            State::Initial => {
-                if let Some(file) = line_file(&line) {
-                    State::File {
-                        file,
-                        infos: vec![],
-                        ty: None,
-                        comments: vec![],
-                    }
-                } else {
-                    panic!("Unrecognized format on line: `{}`", line.deref());
-                }
-            }
-            State::File {
-                mut file,
-                mut infos,
-                mut ty,
-                mut comments,
-            } => {
-                if let Some(new_file) = line_file(&line) {
-                    if !comments.is_empty() {
-                        infos.push((ty.take(), comments));
-                        comments = vec![];
-                    }
-                    if !infos.is_empty() {
-                        ret.insert(file, infos);
-                        file = new_file;
-                        infos = vec![];
-                    }
-                } else if line.starts_with(FILE_COMMENT) {
-                    if let Some(ty) = ty.take() {
-                        if !comments.is_empty() {
-                            infos.push((Some(ty), comments));
-                            comments = vec!["//!".to_owned()];
-                        }
-                    } else if !comments.is_empty() {
-                        infos.push((None, comments));
-                        comments = vec![];
-                    }
-                    ty = parse_mod_line(&line[..]);
-                } else if line.starts_with(MOD_COMMENT) {
-                    if !comments.is_empty() {
-                        infos.push((ty, comments));
-                        comments = vec![];
-                    }
-                    ty = parse_mod_line(&line[..]);
-                } else {
-                    comments.push(line[..].to_owned());
+/// Converts `file` into the entries of each file. Inner doc comments of items are marked by a
+/// first `//!` line.
+pub(crate) fn infos_of(file: CommentsFile, ignore_macros: bool) -> Infos {
+    let mut ret: Infos = HashMap::new();
+    for section in file.sections {
+        // Inner doc comments of an item are only recognized after the doc comments of another
+        // item, they are put back as outer doc comments otherwise.
+        let mut after_item = false;
+        let entries = section
+            .entries
+            .into_iter()
+            .filter_map(|mut e| {
+                let inner = e.kind == EntryKind::FileComment && e.path.is_some() && after_item;
+                after_item = e.path.is_some() && !e.lines.is_empty();
+                if e.lines.is_empty() {
+                    return None;
                 }
-                State::File {
-                    file,
-                    infos,
-                    ty: if ignore_macros {
-                        erase_macro_path(ty)
-                    } else {
-                        ty
-                    },
-                    comments,
+                if inner {
+                    e.lines.insert(0, "//!".to_owned());
                 }
-            }
+                let path = if ignore_macros {
+                    erase_macro_path(e.path)
+                } else {
+                    e.path
+                };
+                Some((path, e.lines))
+            })
+            .collect::<Vec<_>>();
+        if !entries.is_empty() {
+            ret.entry(section.file.file().map(|f| f.to_owned()))
+                .or_default()
+                .extend(entries);
         }
     }
+    ret
+}
 
-    if let State::File {
-        file,
-        mut infos,
-        ty,
-        comments,
-    } = state
-    {
-        if !comments.is_empty() {
-            infos.push((ty, comments));
-        }
-        if !infos.is_empty() {
-            ret.insert(file, infos);
+/// Returns the signatures of the items of the entries of `file`, the same way [`infos_of`]
+/// returns their path.
+fn signatures_of(file: &CommentsFile, ignore_macros: bool) -> Signatures {
+    file.entries()
+        .filter(|(_, e)| e.kind == EntryKind::Item)
+        .filter_map(|(file, e)| {
+            let path = if ignore_macros {
+                erase_macro_path(e.path.clone())
+            } else {
+                e.path.clone()
+            };
+            let key = (file.file().map(|f| f.to_owned()), path?);
+            e.signature.map(|signature| (key, signature))
+        })
+        .collect()
+}
+
+/// Converts the entries of each file back into a comments file, the `<!-- file * -->` entries
+/// first and then the files in alphabetical order. The items keep their `signatures`.
+fn comments_file_of(infos: &Infos, signatures: &Signatures) -> CommentsFile {
+    let mut keys = infos.keys().collect::<Vec<_>>();
+    keys.sort();
+    let mut file = CommentsFile::new();
+    for key in keys {
+        let entries = infos[key]
+            .iter()
+            .map(|(path, lines)| match (path, lines.first()) {
+                (Some(_), Some(first)) if first == "//!" => {
+                    DocEntry::new(path.clone(), EntryKind::FileComment, lines[1..].to_vec())
+                }
+                (Some(p), _) => {
+                    let mut entry = DocEntry::new(path.clone(), EntryKind::Item, lines.clone());
+                    entry.signature = signatures.get(&(key.clone(), p.clone())).cloned();
+                    entry
+                }
+                (None, _) => DocEntry::new(None, EntryKind::FileComment, lines.clone()),
+            })
+            .collect::<Vec<_>>();
+        if !entries.is_empty() {
+            let mut section = Section::new(
+                key.as_ref()
+                    .map_or(FileSelector::All, |f| FileSelector::File(f.clone())),
+            );
+            section.entries = entries;
+            file.sections.push(section);
         }
     }
-
-    ret
+    file
 }
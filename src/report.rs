@@ -0,0 +1,187 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use types::TypeStruct;
+
+/// Why a doc entry couldn't be put back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The file of the entry doesn't exist (or isn't a `.rs` file of the working directory).
+    FileNotFound,
+    /// There is no item with this path.
+    ItemNotFound,
+    /// The item already has doc comments and `ignore_doc_commented` is set.
+    AlreadyDocumented,
+}
+
+impl Reason {
+    fn id(self) -> &'static str {
+        match self {
+            Reason::FileNotFound => "file-not-found",
+            Reason::ItemNotFound => "item-not-found",
+            Reason::AlreadyDocumented => "already-documented",
+        }
+    }
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Reason::FileNotFound => write!(f, "file not found"),
+            Reason::ItemNotFound => write!(f, "item not found"),
+            Reason::AlreadyDocumented => write!(f, "item already documented"),
+        }
+    }
+}
+
+/// A doc entry of the comments file which hasn't been put back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unplaced {
+    /// File of the entry, `None` for the `<!-- file * -->` entries.
+    pub file: Option<String>,
+    /// Path of the documented item, `None` for the file doc comments.
+    pub item: Option<TypeStruct>,
+    pub reason: Reason,
+}
+
+/// An item on which an entry has been put back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemLocation {
+    pub file: String,
+    pub item: TypeStruct,
+    /// Line (starting at 1) of the item in the original file.
+    pub line: usize,
+}
+
+/// What a regeneration did.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Report {
+    /// Files which have been (or would have been when only checking) modified.
+    pub modified: Vec<String>,
+    /// Items which already had doc comments.
+    pub conflicts: Vec<ItemLocation>,
+    /// Items which have the same path as other items of their file and got an entry which
+    /// didn't tell which of them it was for.
+    pub ambiguous: Vec<ItemLocation>,
+    pub unplaced: Vec<Unplaced>,
+}
+
+impl Report {
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{\n  \"modified\": [");
+        for (pos, file) in self.modified.iter().enumerate() {
+            if pos > 0 {
+                out.push(',');
+            }
+            out.push_str(&format!("\n    {}", json_string(Some(file))));
+        }
+        if !self.modified.is_empty() {
+            out.push_str("\n  ");
+        }
+        for &(name, locations) in &[
+            ("conflicts", &self.conflicts),
+            ("ambiguous", &self.ambiguous),
+        ] {
+            out.push_str(&format!("],\n  \"{}\": [", name));
+            for (pos, l) in locations.iter().enumerate() {
+                if pos > 0 {
+                    out.push(',');
+                }
+                out.push_str(&format!(
+                    "\n    {{\"file\": {}, \"item\": {}, \"line\": {}}}",
+                    json_string(Some(&l.file)),
+                    json_string(Some(&l.item.to_string())),
+                    l.line
+                ));
+            }
+            if !locations.is_empty() {
+                out.push_str("\n  ");
+            }
+        }
+        out.push_str("],\n  \"unplaced\": [");
+        for (pos, u) in self.unplaced.iter().enumerate() {
+            if pos > 0 {
+                out.push(',');
+            }
+            let item = u.item.as_ref().map(|i| i.to_string());
+            out.push_str(&format!(
+                "\n    {{\"file\": {}, \"item\": {}, \"reason\": \"{}\"}}",
+                json_string(u.file.as_ref().map(|s| &s[..])),
+                json_string(item.as_ref().map(|s| &s[..])),
+                u.reason.id()
+            ));
+        }
+        if !self.unplaced.is_empty() {
+            out.push_str("\n  ");
+        }
+        out.push_str("]\n}\n");
+        out
+    }
+}
+
+/// Lists the items with conflicts, the ambiguous ones and the unplaced entries, one per line.
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if !self.conflicts.is_empty() {
+            writeln!(f, "The following items already had doc comments:")?;
+            for l in &self.conflicts {
+                writeln!(f, "  {}:{}: `{}`", l.file, l.line, l.item)?;
+            }
+        }
+        if !self.ambiguous.is_empty() {
+            writeln!(
+                f,
+                "Warning: the following items have the same path as other items and were given \
+                 doc comments which didn't tell which one they were for:"
+            )?;
+            for l in &self.ambiguous {
+                writeln!(f, "  {}:{}: `{}`", l.file, l.line, l.item)?;
+            }
+        }
+        if !self.unplaced.is_empty() {
+            writeln!(f, "Some comments haven't been regenerated to the files:")?;
+        }
+        for u in &self.unplaced {
+            write!(f, "  {}: ", u.file.as_ref().map(|s| &s[..]).unwrap_or("*"))?;
+            match u.item {
+                Some(ref item) => writeln!(f, "`{}`: {}", item, u.reason)?,
+                None => writeln!(f, "file doc comments: {}", u.reason)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn json_string(s: Option<&str>) -> String {
+    let s = match s {
+        Some(s) => s,
+        None => return "null".to_owned(),
+    };
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
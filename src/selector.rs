@@ -0,0 +1,338 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use comments::EntryKind;
+use pattern::Pattern;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+use types::{Type, TypeStruct, Visibility, KINDS};
+
+/// Selects doc comments by the path of their item and by file.
+///
+/// The syntax is `[FILE@]PATH`: `FILE` is a [`Pattern`] matched against the path of the file
+/// (relative to the directory to work on) and `PATH` is a list of segments separated by `::`:
+///
+/// * `KINDS NAME` matches the items of one of the given kinds (`fn`, `struct`, `impl`...,
+///   separated by `|`, or `*` for any kind) whose name matches the `NAME` glob (where `*` matches
+///   any number of characters and `?` one character),
+/// * `NAME`, without spaces, matches the items of any kind whose name matches the glob,
+/// * `**` matches any number of segments.
+///
+/// A `PATH` with only one segment matches the items at any depth, otherwise it matches from the
+/// root of the file. For example, `trait *Ext::fn connect_*` matches the `connect_*` methods of
+/// the traits ending with `Ext` of the root of the files, `**::impl *::*` matches the doc
+/// comments of every item of every impl and `src/ffi/*.rs@` everything in the files of
+/// `src/ffi`. The name of an impl is its header, so `::` separates the segment of an impl from
+/// the next one only when it's followed by a kind: `impl fmt::Display for *::fn fmt`.
+///
+/// Without `PATH`, the file comment (`//!` at the top of the file) is matched as well. The entries
+/// of the `<!-- file * -->` sections of the comments file don't belong to a known file, so they
+/// aren't matched by the selectors having a `FILE` part.
+#[derive(Clone)]
+pub struct Selector {
+    source: String,
+    file: Option<Pattern>,
+    path: Option<Vec<Segment>>,
+}
+
+#[derive(Clone)]
+enum Segment {
+    /// `**`.
+    Any,
+    Item {
+        /// `None` for any kind.
+        kinds: Option<Vec<String>>,
+        name: Vec<char>,
+    },
+}
+
+impl Debug for Selector {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Selector({:?})", self.source)
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl PartialEq for Selector {
+    fn eq(&self, other: &Selector) -> bool {
+        self.source == other.source
+    }
+}
+
+/// Returns `true` if `word` is a kind, a list of kinds separated by `|` or `*`.
+fn is_kinds(word: &str) -> bool {
+    word == "*" || word.split('|').all(|k| KINDS.contains(&k))
+}
+
+/// Splits `path` into its segments.
+fn split_segments(path: &str) -> Vec<&str> {
+    let mut segments = vec![];
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut prev = ' ';
+    for (pos, c) in path.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            // `->` in `Fn() -> T`.
+            '>' if prev == '-' => {}
+            '>' | ')' | ']' => depth = depth.saturating_sub(1),
+            ':' if depth == 0 && pos >= start && path[pos..].starts_with("::") => {
+                let in_impl = path[start..pos]
+                    .split_whitespace()
+                    .next()
+                    .is_some_and(|w| is_kinds(w) && w.split('|').any(|k| k == "impl"));
+                let rest = path[pos + 2..].trim_start();
+                let next_is_kind = rest.split(' ').next().is_some_and(is_kinds)
+                    || rest.is_empty()
+                    || rest.starts_with("**");
+                if !in_impl || next_is_kind {
+                    segments.push(&path[start..pos]);
+                    start = pos + 2;
+                }
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    segments.push(&path[start..]);
+    segments
+}
+
+fn parse_segment(segment: &str) -> Result<Segment, String> {
+    let segment = segment.trim();
+    if segment.is_empty() {
+        return Err("empty path segment".to_owned());
+    }
+    if segment == "**" {
+        return Ok(Segment::Any);
+    }
+    // Like in the paths of `TypeStruct`, the first word is the kind if there are several.
+    let (kinds, name) = match segment.split_once(' ') {
+        Some(("*", name)) => (None, name.trim()),
+        Some((kinds, name)) => {
+            let kinds = kinds
+                .split('|')
+                .map(|k| k.parse::<Type>().map(|_| k.to_owned()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.message)?;
+            (Some(kinds), name.trim())
+        }
+        None => (None, segment),
+    };
+    Ok(Segment::Item {
+        kinds,
+        name: name.chars().collect(),
+    })
+}
+
+/// Returns `true` if `name` matches the glob `pattern`.
+fn glob_matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((&'*', rest)) => (0..=name.len()).any(|i| glob_matches(rest, &name[i..])),
+        Some((&c, rest)) => match name.split_first() {
+            Some((&n, name)) => (c == '?' || c == n) && glob_matches(rest, name),
+            None => false,
+        },
+    }
+}
+
+fn segments_match(pattern: &[Segment], path: &[&TypeStruct]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((Segment::Any, rest)) => (0..=path.len()).any(|i| segments_match(rest, &path[i..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((item, path)) => segment.matches(item) && segments_match(rest, path),
+            None => false,
+        },
+    }
+}
+
+impl Segment {
+    fn matches(&self, item: &TypeStruct) -> bool {
+        match *self {
+            Segment::Any => true,
+            Segment::Item {
+                ref kinds,
+                ref name,
+            } => {
+                kinds
+                    .as_ref()
+                    .is_none_or(|k| k.contains(&item.ty.to_string()))
+                    && glob_matches(name, &item.name.chars().collect::<Vec<_>>())
+            }
+        }
+    }
+}
+
+impl Selector {
+    pub fn new(selector: &str) -> Result<Selector, String> {
+        let err = |e: String| format!("Invalid selector '{}': {}", selector, e);
+        let (file, path) = match selector.split_once('@') {
+            Some((file, path)) => (Some(Pattern::new(file).map_err(err)?), path),
+            None => (None, selector),
+        };
+        let path = if path.trim().is_empty() {
+            None
+        } else {
+            Some(
+                split_segments(path)
+                    .into_iter()
+                    .map(parse_segment)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(err)?,
+            )
+        };
+        if file.is_none() && path.is_none() {
+            return Err(err("it's empty".to_owned()));
+        }
+        Ok(Selector {
+            source: selector.to_owned(),
+            file,
+            path,
+        })
+    }
+
+    /// Returns `true` if the selector matches the doc comments of `path` (`None` for the file
+    /// comment) in `file` (`None` if it isn't known).
+    pub fn matches(&self, file: Option<&str>, path: Option<&TypeStruct>) -> bool {
+        if let Some(ref pattern) = self.file {
+            if !file.is_some_and(|f| pattern.matches(f, false)) {
+                return false;
+            }
+        }
+        let segments = match self.path {
+            Some(ref segments) => segments,
+            None => return true,
+        };
+        let mut items = vec![];
+        let mut current = path;
+        while let Some(item) = current {
+            items.push(item);
+            current = item.parent.as_deref();
+        }
+        items.reverse();
+        match segments[..] {
+            [ref segment] => items.last().is_some_and(|item| segment.matches(item)),
+            _ => segments_match(segments, &items),
+        }
+    }
+}
+
+/// A kind of doc comments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DocKind {
+    /// Inner doc comments (`//!` and `/*! */`), of files and of modules.
+    Module,
+    /// Outer doc comments (`///` and `/** */`) of the items of the given type, fields being
+    /// [`Type::Variant`]s.
+    Item(Type),
+}
+
+impl DocKind {
+    /// The kind of the doc comments of `path` (`None` for the file comment) written as `kind` in
+    /// the comments file.
+    pub fn of(kind: EntryKind, path: Option<&TypeStruct>) -> DocKind {
+        match (kind, path) {
+            (EntryKind::Item, Some(path)) => DocKind::Item(path.ty),
+            _ => DocKind::Module,
+        }
+    }
+}
+
+impl Display for DocKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            DocKind::Module => write!(f, "module"),
+            DocKind::Item(ty) => write!(f, "{}", ty),
+        }
+    }
+}
+
+/// Parses `module`, `field` or an item kind as written in the comments file (`fn`, `struct`,
+/// `variant`...).
+impl FromStr for DocKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DocKind, String> {
+        match s {
+            "module" => Ok(DocKind::Module),
+            "field" => Ok(DocKind::Item(Type::Variant)),
+            _ => s.parse().map(DocKind::Item).map_err(|_| {
+                format!(
+                    "Unknown doc kind '{}', expected one of: module, field, {}",
+                    s,
+                    KINDS.join(", ")
+                )
+            }),
+        }
+    }
+}
+
+/// Which doc comments are processed, using [`Selector`]s and [`DocKind`]s. The default one
+/// selects all of them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ItemFilter {
+    /// If not empty, only the doc comments matched by one of these selectors are processed.
+    pub only: Vec<Selector>,
+    /// The doc comments matched by one of these selectors aren't processed.
+    pub exclude: Vec<Selector>,
+    /// If not empty, only the doc comments of these kinds are processed.
+    pub only_kinds: Vec<DocKind>,
+    /// The doc comments of these kinds aren't processed.
+    pub exclude_kinds: Vec<DocKind>,
+    /// If not empty, only the doc comments of the items having one of these visibilities (see
+    /// [`TypeStruct::effective_visibility`]) are processed.
+    pub visibility: Vec<Visibility>,
+}
+
+impl ItemFilter {
+    pub fn new() -> ItemFilter {
+        ItemFilter::default()
+    }
+
+    /// Returns `true` if all the doc comments are selected.
+    pub fn is_empty(&self) -> bool {
+        self.only.is_empty()
+            && self.exclude.is_empty()
+            && self.only_kinds.is_empty()
+            && self.exclude_kinds.is_empty()
+            && self.visibility.is_empty()
+    }
+
+    /// Returns `true` if the doc comments of `path` (`None` for the file comment) in `file`
+    /// (`None` if it isn't known), written as `kind` in the comments file, have to be processed.
+    pub fn selects(&self, file: Option<&str>, kind: EntryKind, path: Option<&TypeStruct>) -> bool {
+        let doc_kind = DocKind::of(kind, path);
+        (self.only.is_empty() || self.only.iter().any(|s| s.matches(file, path)))
+            && !self.exclude.iter().any(|s| s.matches(file, path))
+            && (self.only_kinds.is_empty() || self.only_kinds.contains(&doc_kind))
+            && !self.exclude_kinds.contains(&doc_kind)
+    }
+
+    /// Returns `true` if the doc comments of `item` (`None` for the file comment, which is
+    /// public) have to be processed given its visibility. The items of the comments file don't
+    /// have any, the ones of the source files have to be used.
+    pub fn selects_visibility(&self, item: Option<&TypeStruct>) -> bool {
+        self.visibility.is_empty()
+            || self
+                .visibility
+                .contains(&item.map_or(Visibility::Public, |item| item.effective_visibility()))
+    }
+}
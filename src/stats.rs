@@ -0,0 +1,110 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+use diagnostics::Diagnostics;
+use error::FileError;
+use filter::FileFilter;
+use regenerate::file_items;
+use strip::build_event_list;
+use types::{Type, Visibility};
+use utils::{list_files_of, parallel_map};
+use vfs::DiskFs;
+
+/// How many items the files of a directory contain and how many of them have doc comments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub files: usize,
+    pub items: usize,
+    pub documented: usize,
+}
+
+impl Stats {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\n  \"files\": {},\n  \"items\": {},\n  \"documented\": {}\n}}\n",
+            self.files, self.items, self.documented
+        )
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {} items, {} documented",
+            self.files, self.items, self.documented
+        )?;
+        if self.items > 0 {
+            write!(
+                f,
+                " ({:.1}%)",
+                self.documented as f64 * 100. / self.items as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the items of the files of `directory` kept by `filter` (using `jobs` threads) and those with doc
+/// comments. If `visibility` isn't empty, only the items having one of these visibilities (see
+/// [`TypeStruct::effective_visibility`](::TypeStruct::effective_visibility)) are counted. The
+/// files gone through are sent to `diagnostics` if `verbose` is `true`.
+pub fn collect_stats(
+    directory: &Path,
+    filter: &FileFilter,
+    ignore_macros: bool,
+    jobs: usize,
+    verbose: bool,
+    visibility: &[Visibility],
+    diagnostics: &dyn Diagnostics,
+) -> Result<Stats, Vec<FileError>> {
+    // The files which could be listed are counted anyway, so that all errors are reported.
+    let (files, mut errors) = list_files_of(&DiskFs, diagnostics, directory, filter, verbose);
+    let mut stats = Stats {
+        files: files.len(),
+        ..Stats::default()
+    };
+    for (path, ret) in files.iter().zip(parallel_map(&files, jobs, |path| {
+        build_event_list(&directory.join(path)).map(|p| file_items(&p, ignore_macros))
+    })) {
+        match ret {
+            Ok(items) => {
+                for (item, documented) in items {
+                    if item.ty != Type::Unknown
+                        && (visibility.is_empty()
+                            || visibility.contains(&item.effective_visibility()))
+                    {
+                        stats.items += 1;
+                        if documented {
+                            stats.documented += 1;
+                        }
+                    }
+                }
+            }
+            Err(e) => errors.push(FileError::new(
+                path,
+                None,
+                format!("Unable to read file: {}", e),
+            )),
+        }
+    }
+    if errors.is_empty() {
+        Ok(stats)
+    } else {
+        Err(errors)
+    }
+}
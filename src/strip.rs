@@ -12,14 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use cache::hash;
+use comments::EntryKind;
+use consts::{END_INFO, MOD_COMMENT, SIGNATURE};
+use diagnostics::{Diagnostic, Diagnostics, Sink};
+use error::FileError;
+use filter::FileFilter;
+use selector::ItemFilter;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as FmtWrite;
 use std::fs::File;
 use std::io::{self, Read, Write};
-use std::ops::Deref;
+use std::ops::{Deref, RangeInclusive};
 use std::path::Path;
-use std::process::exit;
-use types::{EventInfo, EventType, ParseResult, Type, TypeStruct};
-use utils::{join, write_comment, write_file, write_file_comment};
+use std::sync::Arc;
+use types::{EventInfo, EventType, ParseResult, Type, TypeStruct, Visibility};
+use utils::{join, write_atomically, write_comment, write_file, write_file_comment};
+use vfs::{DiskFs, FileSystem};
 
+/// Words which can be written between the visibility and the kind of an item.
+const QUALIFIERS: &[&str] = &["unsafe", "async", "extern", "default", "auto"];
 const STOP_CHARACTERS: &[char] = &['\t', '\n', '\r', '<', '{', ':', ';', '!', '(', ','];
 const COMMENT_ID: &[&str] = &["//", "/*"];
 pub(crate) const DOC_COMMENT_ID: &[&str] = &["///", "/*!", "//!", "/**"];
@@ -107,14 +119,7 @@ pub fn type_out_scope(current: &Option<TypeStruct>) -> Option<TypeStruct> {
 
 fn get_mod(current: &Option<TypeStruct>) -> bool {
     match *current {
-        Some(ref t) => {
-            if t.ty != Type::Mod {
-                println!("Mod/File comments cannot be put here!");
-                false
-            } else {
-                true
-            }
-        }
+        Some(ref t) => t.ty == Type::Mod,
         None => true,
     }
 }
@@ -283,37 +288,30 @@ fn clean_input(s: &str) -> String {
 fn clear_events(mut events: Vec<EventInfo>) -> Vec<EventInfo> {
     let mut current: Option<TypeStruct> = None;
     let mut waiting_type: Option<TypeStruct> = None;
-    let mut it = 0;
 
-    while it < events.len() {
-        if match events[it].event {
-            EventType::Type(ref t) => {
-                if t.ty != Type::Unknown {
-                    waiting_type = Some(t.clone());
-                    false
-                } else if let Some(ref parent) = current {
-                    !matches!(parent.ty, Type::Struct | Type::Enum)
-                } else {
-                    true
-                }
-            }
-            EventType::InScope => {
-                current = add_to_type_scope(&current, &waiting_type);
-                waiting_type = None;
-                false
-            }
-            EventType::OutScope => {
-                current = type_out_scope(&current);
-                waiting_type = None;
+    events.retain(|event| match event.event {
+        EventType::Type(ref t) => {
+            if t.ty != Type::Unknown {
+                waiting_type = Some(t.clone());
+                true
+            } else if let Some(ref parent) = current {
+                matches!(parent.ty, Type::Struct | Type::Enum)
+            } else {
                 false
             }
-            _ => false,
-        } {
-            events.remove(it);
-            continue;
         }
-        it += 1;
-    }
+        EventType::InScope => {
+            current = add_to_type_scope(&current, &waiting_type);
+            waiting_type = None;
+            true
+        }
+        EventType::OutScope => {
+            current = type_out_scope(&current);
+            waiting_type = None;
+            true
+        }
+        _ => true,
+    });
     events
 }
 
@@ -338,7 +336,10 @@ fn build_event_inner(
     mut par_count: Option<isize>,
 ) {
     let mut waiting_for_macro = false;
+    // The visibility of the next item.
+    let mut visibility = None;
     while *it < words.len() {
+        let event_count = event_list.len();
         match words[*it] {
             c if c.starts_with('"') => move_to(words, it, "\"", line, "\""),
             c if c.starts_with("b\"") => move_to(words, it, "\"", line, "b\""),
@@ -535,6 +536,22 @@ fn build_event_inner(
             "\n" => {
                 *line += 1;
             }
+            "pub" => {
+                event_list.push(EventInfo::new(
+                    *line,
+                    EventType::Type(TypeStruct::new(Type::Unknown, "pub")),
+                ));
+                visibility = Some(match get_restriction(words, *it + 1) {
+                    Some((restriction, end)) => {
+                        *line += words[*it + 1..end].iter().filter(|w| **w == "\n").count();
+                        *it = end - 1;
+                        Visibility::restricted(&restriction)
+                    }
+                    None => Visibility::Public,
+                });
+                *it += 1;
+                continue;
+            }
             s if s.starts_with("#[") || s.starts_with("#![") => {
                 while *it < words.len() {
                     *line += words[*it].split('\n').count() - 1;
@@ -556,15 +573,58 @@ fn build_event_inner(
                 *line += x.chars().filter(|c| *c == '\n').count();
             }
         }
+        if visibility.is_some() {
+            match event_list.get_mut(event_count).map(|e| &mut e.event) {
+                Some(EventType::Type(ref t))
+                    if t.ty == Type::Unknown && QUALIFIERS.contains(&&*t.name) => {}
+                Some(EventType::Type(ref mut t)) => t.visibility = visibility.take().unwrap(),
+                Some(_) => visibility = None,
+                None => {}
+            }
+        }
         *it += 1;
     }
 }
 
+/// Returns the restriction of `pub(...)` (without the parentheses) if the word at `start` begins
+/// one, and the position of the word following it.
+fn get_restriction(words: &[&str], start: usize) -> Option<(String, usize)> {
+    if !words.get(start)?.starts_with('(') {
+        return None;
+    }
+    let mut restriction = vec![];
+    for (pos, word) in words.iter().enumerate().skip(start) {
+        if *word != "\n" {
+            restriction.push(word.trim());
+        }
+        if word.contains(')') {
+            let restriction = restriction.join(" ");
+            let restriction = restriction
+                .trim_start_matches('(')
+                .split(')')
+                .next()?
+                .trim();
+            return match restriction.split(' ').next() {
+                Some("crate") | Some("super") | Some("self") | Some("in") => {
+                    Some((restriction.to_owned(), pos + 1))
+                }
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
 pub fn build_event_list(path: &Path) -> io::Result<ParseResult> {
     let mut f = File::open(path)?;
     let mut b_content = String::new();
-    f.read_to_string(&mut b_content).unwrap();
-    let content = clean_input(&b_content);
+    f.read_to_string(&mut b_content)?;
+    Ok(parse_content(&b_content))
+}
+
+/// Same as [`build_event_list`] but on an already read file content.
+pub fn parse_content(b_content: &str) -> ParseResult {
+    let content = clean_input(b_content);
     let b_content: Vec<String> = b_content.split('\n').map(|s| s.to_owned()).collect();
     let words: Vec<&str> = content.split(' ').filter(|s| !s.is_empty()).collect();
     let mut it = 0;
@@ -582,11 +642,11 @@ pub fn build_event_list(path: &Path) -> io::Result<ParseResult> {
         None,
     );
     let clear = clear_events(event_list);
-    Ok(ParseResult {
+    ParseResult {
         event_list: clear,
         comment_lines,
         original_content: b_content,
-    })
+    }
 }
 
 fn unformat_comment(c: &str) -> String {
@@ -613,180 +673,504 @@ fn unformat_comment(c: &str) -> String {
         .join("\n")
 }
 
-pub fn strip_comments<F: Write>(
+/// Returns the item declared by the word `t` in the scope `current`, `None` if it doesn't
+/// declare one. The words of enums, structs and `use`s are their variants (or fields), the ones
+/// of modules items of unknown kind.
+pub(crate) fn event_item(current: &Option<TypeStruct>, t: &TypeStruct) -> Option<TypeStruct> {
+    if t.ty != Type::Unknown {
+        return add_to_type_scope(current, &Some(t.clone()));
+    }
+    match *current {
+        Some(_) if t.name == "pub" || t.name.is_empty() => None,
+        Some(ref cur) if cur.ty == Type::Enum || cur.ty == Type::Struct || cur.ty == Type::Use => {
+            let mut copy = t.clone();
+            copy.ty = Type::Variant;
+            add_to_type_scope(current, &Some(copy))
+        }
+        Some(ref cur) if cur.ty == Type::Mod => add_to_type_scope(current, &Some(t.clone())),
+        _ => None,
+    }
+}
+
+/// Returns the ordinal of the items of `parse_result` which have the same path as another one,
+/// by position in the event list. The items are the ones returned by [`event_item`].
+pub(crate) fn item_ordinals(parse_result: &ParseResult) -> HashMap<usize, usize> {
+    let mut current: Option<TypeStruct> = None;
+    let mut waiting_type: Option<TypeStruct> = None;
+    let mut items = vec![];
+    let mut counts: HashMap<TypeStruct, usize> = HashMap::new();
+
+    for (it, event) in parse_result.event_list.iter().enumerate() {
+        match event.event {
+            EventType::Type(ref t) => {
+                if t.ty != Type::Unknown {
+                    waiting_type = Some(t.clone());
+                }
+                if let Some(item) = event_item(&current, t) {
+                    *counts.entry(item.clone()).or_insert(0) += 1;
+                    items.push((it, item));
+                }
+            }
+            EventType::InScope => {
+                current = add_to_type_scope(&current, &waiting_type);
+                waiting_type = None;
+            }
+            EventType::OutScope => {
+                current = type_out_scope(&current);
+                waiting_type = None;
+            }
+            _ => {}
+        }
+    }
+    let mut seen: HashMap<&TypeStruct, usize> = HashMap::new();
+    let mut ordinals = HashMap::new();
+    for (it, item) in &items {
+        if counts[item] > 1 {
+            let ordinal = seen.entry(item).or_insert(0);
+            *ordinal += 1;
+            ordinals.insert(*it, *ordinal);
+        }
+    }
+    ordinals
+}
+
+/// Settings used when stripping doc comments, see [`Stripper::strip`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StripOptions {
+    pub(crate) verbose: bool,
+    pub(crate) ignore_macros: bool,
+    pub(crate) jobs: usize,
+    pub(crate) filter: FileFilter,
+    pub(crate) items: ItemFilter,
+    pub(crate) signatures: bool,
+    pub(crate) diagnostics: Sink,
+}
+
+impl Default for StripOptions {
+    fn default() -> StripOptions {
+        StripOptions {
+            verbose: false,
+            ignore_macros: false,
+            jobs: 1,
+            filter: FileFilter::new(),
+            items: ItemFilter::new(),
+            signatures: false,
+            diagnostics: Sink::default(),
+        }
+    }
+}
+
+impl StripOptions {
+    pub fn new() -> StripOptions {
+        StripOptions::default()
+    }
+
+    /// Prints the files which are gone through.
+    pub fn verbose(mut self, verbose: bool) -> StripOptions {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Sends the diagnostics of the run (the files gone through when verbose, the warnings...)
+    /// to `sink` instead of printing them on the standard error output.
+    pub fn diagnostics(mut self, sink: Arc<dyn Diagnostics>) -> StripOptions {
+        self.diagnostics = Sink::new(sink);
+        self
+    }
+
+    /// Macros in hierarchy are ignored, so only macros with doc comments appear in the comments
+    /// file.
+    pub fn ignore_macros(mut self, ignore_macros: bool) -> StripOptions {
+        self.ignore_macros = ignore_macros;
+        self
+    }
+
+    /// Number of threads processing the files, `0` meaning one per available CPU.
+    pub fn jobs(mut self, jobs: usize) -> StripOptions {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Which files of the directory to work on are gone through, all the Rust files by default.
+    pub fn filter(mut self, filter: FileFilter) -> StripOptions {
+        self.filter = filter;
+        self
+    }
+
+    /// Which doc comments are stripped, all of them by default.
+    pub fn items(mut self, items: ItemFilter) -> StripOptions {
+        self.items = items;
+        self
+    }
+
+    /// Writes a hash of the declaration of each item, without its name, in the header of its
+    /// entry. A fuzzy regeneration uses it to find the item an entry belongs to once renamed.
+    pub fn signatures(mut self, signatures: bool) -> StripOptions {
+        self.signatures = signatures;
+        self
+    }
+}
+
+/// The result of stripping a file. Nothing is written until [`StrippedFile::write`] is called.
+#[derive(Debug, Clone)]
+pub struct StrippedFile {
+    /// Path of the file, relative to the working directory.
+    pub path: String,
+    /// What has to be added into the comments file for this file.
+    pub comments: String,
+    /// The new content of the file, `None` if it has no doc comments.
+    pub content: Option<String>,
+    /// Only some doc comments are stripped (see [`StripOptions::items`]): the new content can
+    /// still have some.
+    pub partial: bool,
+}
+
+impl StrippedFile {
+    /// Writes the doc comments into `out_file` and then the stripped content of the file.
+    pub fn write<F: Write + ?Sized>(&self, work_dir: &Path, out_file: &mut F) -> io::Result<()> {
+        if let Some(ref content) = self.content {
+            write!(out_file, "{}", self.comments)?;
+            write_atomically(&work_dir.join(&self.path), content.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Extracts the doc comments of `path` without modifying anything.
+pub fn strip_file(
     work_dir: &Path,
     path: &str,
-    out_file: &mut F,
     ignore_macros: bool,
-) {
-    let full_path = work_dir.join(path);
-    match build_event_list(&full_path) {
-        Ok(parse_result) => {
-            if parse_result.comment_lines.is_empty() {
-                return;
-            }
-            writeln!(out_file, "{}", &write_file(path)).unwrap();
-            let mut current: Option<TypeStruct> = None;
-            let mut waiting_type: Option<TypeStruct> = None;
-            let mut it = 0;
-
-            while it < parse_result.event_list.len() {
-                match parse_result.event_list[it].event {
-                    EventType::Type(ref t) => {
-                        if t.ty != Type::Unknown {
-                            waiting_type = Some(t.clone());
-                        }
-                    }
-                    EventType::InScope => {
-                        current = add_to_type_scope(&current, &waiting_type);
-                        waiting_type = None;
-                    }
-                    EventType::OutScope => {
-                        current = type_out_scope(&current);
-                        waiting_type = None;
+) -> Result<StrippedFile, FileError> {
+    strip_file_of(
+        &DiskFs,
+        work_dir,
+        path,
+        &StripOptions::new().ignore_macros(ignore_macros),
+    )
+}
+
+/// Same as [`strip_file`] but reads `path` from `fs` and only strips the doc comments selected
+/// by `options`.
+pub(crate) fn strip_file_of(
+    fs: &dyn FileSystem,
+    work_dir: &Path,
+    path: &str,
+    options: &StripOptions,
+) -> Result<StrippedFile, FileError> {
+    let (ignore_macros, items) = (options.ignore_macros, &options.items);
+    let parse_result = fs
+        .read_to_string(&work_dir.join(path))
+        .map(|c| parse_content(&c))
+        .map_err(|e| FileError::new(path, None, format!("Unable to read file: {}", e)))?;
+    let mut out = String::new();
+
+    if parse_result.comment_lines.is_empty() {
+        return Ok(StrippedFile {
+            path: path.to_owned(),
+            comments: out,
+            content: None,
+            partial: !items.is_empty(),
+        });
+    }
+    writeln!(out, "{}", &write_file(path)).unwrap();
+    let ordinals = item_ordinals(&parse_result);
+    let mut current: Option<TypeStruct> = None;
+    let mut waiting_type: Option<TypeStruct> = None;
+    let mut it = 0;
+    // Lines of the doc comments which aren't selected, left in the file.
+    let mut kept = HashSet::new();
+    let mut written = false;
+
+    while it < parse_result.event_list.len() {
+        let line = parse_result.event_list[it].line;
+        match parse_result.event_list[it].event {
+            EventType::Type(ref t) => {
+                if t.ty != Type::Unknown {
+                    waiting_type = Some(t.clone());
+                }
+            }
+            EventType::InScope => {
+                current = add_to_type_scope(&current, &waiting_type);
+                waiting_type = None;
+            }
+            EventType::OutScope => {
+                current = type_out_scope(&current);
+                waiting_type = None;
+            }
+            EventType::FileComment(ref c) => {
+                // first, we need to find if it belongs to a mod
+                if !get_mod(&current) {
+                    return Err(FileError::new(
+                        path,
+                        Some(line + 1),
+                        "Mod/File comments cannot be put here!",
+                    ));
+                }
+                let first = it;
+                let mut comment = unformat_comment(c);
+                it += 1;
+                while let Some(EventType::FileComment(c)) =
+                    parse_result.event_list.get(it).map(|x| &x.event)
+                {
+                    comment.push('\n');
+                    comment.push_str(&unformat_comment(c));
+                    it += 1;
+                }
+                if items.selects(Some(path), EntryKind::FileComment, current.as_ref())
+                    && items.selects_visibility(current.as_ref())
+                {
+                    writeln!(
+                        out,
+                        "{}",
+                        &write_file_comment(&comment, &current, ignore_macros)
+                    )
+                    .unwrap();
+                    written = true;
+                } else {
+                    let lines = event_lines(&parse_result.event_list[first..it]);
+                    // The empty line following them is removed with them.
+                    let next = *lines.end() + 1;
+                    if parse_result
+                        .original_content
+                        .get(next)
+                        .is_some_and(|l| l.is_empty())
+                    {
+                        kept.insert(next);
                     }
-                    EventType::FileComment(ref c) => {
-                        // first, we need to find if it belongs to a mod
-                        if !get_mod(&current) {
-                            exit(1);
-                        }
-                        it += 1;
-                        let mut comments = format!(
-                            "{}\n",
-                            &write_file_comment(&unformat_comment(c), &current, ignore_macros)
-                        );
-                        while parse_result
-                            .event_list
-                            .get(it)
-                            .map(|x| match x.event {
-                                EventType::FileComment(ref c) => {
-                                    use std::fmt::Write;
-                                    writeln!(comments, "{}", unformat_comment(c)).unwrap();
-                                    true
-                                }
-                                _ => false,
-                            })
-                            .unwrap_or(false)
-                        {
-                            it += 1;
+                    kept.extend(lines);
+                }
+                continue;
+            }
+            EventType::Comment(ref c) => {
+                let first = it;
+                let mut comments = format!("{}\n", c);
+
+                it += 1;
+                while let Some(x) = parse_result.event_list.get(it) {
+                    match x.event {
+                        EventType::Comment(ref c) => writeln!(comments, "{}", c).unwrap(),
+                        EventType::Type(_) => break,
+                        _ => {
+                            return Err(FileError::new(
+                                path,
+                                Some(x.line + 1),
+                                "Doc comments cannot be written everywhere",
+                            ))
                         }
-                        write!(out_file, "{}", comments).unwrap();
-                        continue;
                     }
-                    EventType::Comment(ref c) => {
-                        let mut comments = format!("{}\n", c);
-
-                        it += 1;
-                        while it < parse_result.event_list.len()
-                            && match parse_result.event_list[it].event {
-                                EventType::Comment(ref c) => {
-                                    use std::fmt::Write;
-                                    writeln!(comments, "{}", c).unwrap();
-                                    true
-                                }
-                                EventType::Type(_) => false,
-                                _ => panic!("[{}:{}]: Doc comments cannot be written everywhere:\n---> {:#?}", full_path.display(), parse_result.event_list[it].line, parse_result.event_list),
-                            }
-                        {
-                            it += 1;
+                    it += 1;
+                }
+                let lines = event_lines(&parse_result.event_list[first..it]);
+                let mut item = None;
+                while let Some(x) = parse_result.event_list.get(it) {
+                    let t = match x.event {
+                        EventType::Type(ref t) => t,
+                        _ => {
+                            return Err(FileError::new(
+                                path,
+                                Some(line + 1),
+                                format!("An item was expected for this comment: {}", comments),
+                            ))
                         }
-                        while parse_result
-                            .event_list
-                            .get(it)
-                            .map(|x| match x.event {
-                                EventType::Type(ref t) => match t.ty {
-                                    Type::Unknown => match current {
-                                        Some(ref cur) => {
-                                            if cur.ty == Type::Enum
-                                                || cur.ty == Type::Struct
-                                                || cur.ty == Type::Use
-                                            {
-                                                if t.name == "pub" {
-                                                    true
-                                                } else {
-                                                    let mut copy = t.clone();
-                                                    copy.ty = Type::Variant;
-                                                    let tmp =
-                                                        add_to_type_scope(&current, &Some(copy));
-                                                    write!(
-                                                        out_file,
-                                                        "{}",
-                                                        write_comment(
-                                                            &tmp.unwrap(),
-                                                            &unformat_comment(&comments),
-                                                            ignore_macros
-                                                        )
-                                                    )
-                                                    .unwrap();
-                                                    false
-                                                }
-                                            } else {
-                                                t.name == "pub"
-                                            }
-                                        }
-                                        None => t.name == "pub",
-                                    },
-                                    _ => {
-                                        let tmp = add_to_type_scope(&current, &Some(t.clone()));
-                                        write!(
-                                            out_file,
-                                            "{}",
-                                            write_comment(
-                                                &tmp.unwrap(),
-                                                &unformat_comment(&comments),
-                                                ignore_macros
-                                            )
-                                        )
-                                        .unwrap();
-                                        false
-                                    }
-                                },
-                                _ => panic!("An item was expected for this comment: {}", comments),
-                            })
-                            .unwrap_or(false)
-                        {
-                            it += 1;
+                    };
+                    let skip = t.ty == Type::Unknown && t.name == "pub";
+                    if !skip {
+                        // Only the doc comments of known items are stripped.
+                        item = event_item(&current, t)
+                            .filter(|i| i.ty != Type::Unknown)
+                            .map(|mut i| {
+                                i.ordinal = ordinals.get(&it).cloned().unwrap_or(0);
+                                i
+                            });
+                    }
+                    if !skip {
+                        break;
+                    }
+                    it += 1;
+                }
+                if let Some(item) = item {
+                    if items.selects(Some(path), EntryKind::Item, Some(&item))
+                        && items.selects_visibility(Some(&item))
+                    {
+                        let comments = unformat_comment(&comments);
+                        let signature = if options.signatures {
+                            item_signature(&parse_result, it)
+                        } else {
+                            None
+                        };
+                        match signature {
+                            Some(signature) => out.push_str(&write_signed_comment(
+                                &item,
+                                &comments,
+                                ignore_macros,
+                                signature,
+                            )),
+                            None => out.push_str(&write_comment(&item, &comments, ignore_macros)),
                         }
-                        continue;
+                        written = true;
+                    } else {
+                        kept.extend(lines);
                     }
                 }
-                it += 1;
+                continue;
             }
-            // we now remove doc comments from original file
-            remove_comments(
-                &full_path,
-                &parse_result.comment_lines,
-                parse_result.original_content,
-            );
         }
-        Err(e) => {
-            println!("Unable to open \"{}\": {}", path, e);
+        it += 1;
+    }
+    // we now remove doc comments from original file
+    let to_remove = parse_result
+        .comment_lines
+        .iter()
+        .filter(|l| !kept.contains(l))
+        .cloned()
+        .collect::<Vec<_>>();
+    if to_remove.is_empty() {
+        out.clear();
+    } else if !written && !kept.is_empty() {
+        // Only unselected doc comments and what goes with them.
+        out.clear();
+    }
+    let content = if to_remove.is_empty() {
+        None
+    } else {
+        Some(remove_comments(&to_remove, parse_result.original_content))
+    };
+    Ok(StrippedFile {
+        path: path.to_owned(),
+        comments: out,
+        content,
+        partial: !items.is_empty(),
+    })
+}
+
+/// Same as [`write_comment`] with the `signature` of the item in the header.
+fn write_signed_comment(
+    id: &TypeStruct,
+    comment: &str,
+    ignore_macros: bool,
+    signature: u64,
+) -> String {
+    let id = if ignore_macros {
+        id.to_string()
+    } else {
+        format!("{:?}", id)
+    };
+    format!(
+        "{}{}{}{:016x}{}\n{}",
+        MOD_COMMENT, id, SIGNATURE, signature, END_INFO, comment
+    )
+}
+
+/// Returns the hash of the declaration of the item of the event `it`: its line(s) until the
+/// body, the end of the declaration or of the field, without the name of the item and with
+/// whitespace collapsed. `None` if the event isn't an item.
+pub(crate) fn item_signature(parse_result: &ParseResult, it: usize) -> Option<u64> {
+    let event = parse_result.event_list.get(it)?;
+    let name = match event.event {
+        EventType::Type(ref t) => &t.name,
+        _ => return None,
+    };
+    let mut declaration = String::new();
+    let mut depth = 0usize;
+    let mut previous = ' ';
+    'lines: for line in parse_result
+        .original_content
+        .iter()
+        .skip(event.line)
+        .take(50)
+    {
+        for c in line.chars() {
+            match c {
+                '>' if previous == '-' => {}
+                '(' | '[' | '<' => depth += 1,
+                ')' | ']' | '>' => depth = depth.saturating_sub(1),
+                '{' | '}' | ';' | ',' if depth == 0 => break 'lines,
+                _ => {}
+            }
+            declaration.push(c);
+            previous = c;
         }
+        declaration.push(' ');
     }
+    let mut signature = String::new();
+    let mut word = String::new();
+    let mut name_removed = false;
+    for c in declaration.chars().chain(Some(' ')) {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        if !name_removed && word == *name {
+            name_removed = true;
+        } else {
+            signature.push_str(&word);
+        }
+        word.clear();
+        if !c.is_whitespace() {
+            signature.push(c);
+        } else if !signature.is_empty() && !signature.ends_with(' ') {
+            signature.push(' ');
+        }
+    }
+    Some(hash(signature.trim_end().as_bytes()))
 }
 
-fn remove_comments(path: &Path, to_remove: &[usize], mut o_content: Vec<String>) {
-    let mut decal = 0;
-    match File::create(path) {
-        Ok(mut f) => {
-            for line in to_remove.iter() {
-                if line - decal > 0
-                    && line - decal + 1 < o_content.len()
-                    && o_content[line - decal - 1].trim() == IGNORE_NEXT_COMMENT_STOP
-                {
-                    let l = o_content[line - decal + 1].trim();
-                    if DOC_COMMENT_ID.iter().any(|d| l.starts_with(d)) {
-                        o_content.remove(line - decal - 1);
-                        decal += 1;
-                    }
-                }
-                o_content.remove(line - decal);
-                decal += 1;
+/// Returns the lines of the doc comments of `events`.
+fn event_lines(events: &[EventInfo]) -> RangeInclusive<usize> {
+    let first = events.iter().map(|e| e.line).min().unwrap_or(0);
+    let last = events.iter().map(|e| e.line).max().unwrap_or(0);
+    first..=last
+}
+
+#[deprecated(note = "use `Stripper::strip` instead")]
+pub fn strip_comments<F: Write + ?Sized>(
+    work_dir: &Path,
+    path: &str,
+    out_file: &mut F,
+    ignore_macros: bool,
+) {
+    let options = StripOptions::new().ignore_macros(ignore_macros);
+    match strip_file(work_dir, path, options.ignore_macros) {
+        Ok(stripped) => {
+            if let Err(e) = stripped.write(work_dir, out_file) {
+                options
+                    .diagnostics
+                    .emit(&Diagnostic::error(format!("Cannot write file: {}", e)).with_path(path));
             }
-            write!(f, "{}", o_content.join("\n")).unwrap();
         }
-        Err(e) => {
-            println!("Cannot open '{}': {}", path.display(), e);
+        Err(e) => options.diagnostics.emit(&e.into()),
+    }
+}
+
+fn remove_comments(to_remove: &[usize], o_content: Vec<String>) -> String {
+    let mut to_remove = to_remove.to_vec();
+    to_remove.sort_unstable();
+    to_remove.dedup();
+    let mut to_remove = to_remove.into_iter().peekable();
+    let mut kept: Vec<String> = Vec::with_capacity(o_content.len());
+    let mut lines = o_content.into_iter().enumerate().peekable();
+
+    while let Some((pos, line)) = lines.next() {
+        if to_remove.peek() != Some(&pos) {
+            kept.push(line);
+            continue;
+        }
+        to_remove.next();
+        // If the doc comment follows a "// rustdoc-stripper-ignore-next-stop" and is followed by
+        // another doc comment, the ignore marker isn't needed anymore.
+        if kept
+            .last()
+            .map(|l| l.trim() == IGNORE_NEXT_COMMENT_STOP)
+            .unwrap_or(false)
+        {
+            if let Some((_, next)) = lines.peek() {
+                let next = next.trim();
+                if DOC_COMMENT_ID.iter().any(|d| next.starts_with(d)) {
+                    kept.pop();
+                }
+            }
         }
     }
+    kept.join("\n")
 }
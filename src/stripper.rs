@@ -0,0 +1,313 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use cache::{hash, Cache};
+use comments::CommentsFile;
+use consts::{END_INFO, FILE};
+use error::FileError;
+use regenerate::{prepare_regeneration, regenerate, Modified, RegenerateOptions};
+use report::Report;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use strip::{strip_file_of, StripOptions, StrippedFile};
+use utils::{list_files_of, listed, parallel_map, restore, write_all};
+use vfs::{DiskFs, FileSystem};
+
+/// Moves the doc comments of the files of a directory into a comments file and puts them back.
+pub struct Stripper {
+    work_dir: PathBuf,
+    comment_file: PathBuf,
+    files: Option<Vec<String>>,
+    cache: Option<Cache>,
+    fs: Arc<dyn FileSystem>,
+}
+
+impl Stripper {
+    /// Works on the Rust files of `work_dir`. `comment_file` isn't relative to `work_dir`.
+    pub fn new<P: AsRef<Path>, C: AsRef<Path>>(work_dir: P, comment_file: C) -> Stripper {
+        Stripper {
+            work_dir: work_dir.as_ref().to_path_buf(),
+            comment_file: comment_file.as_ref().to_path_buf(),
+            files: None,
+            cache: None,
+            fs: Arc::new(DiskFs),
+        }
+    }
+
+    /// Only processes `files` (relative to the directory to work on) instead of all the files
+    /// of the directory. When stripping, the sections of the other files in the comments file
+    /// are kept. When regenerating, only the sections of `files` and the `<!-- file * -->` ones
+    /// are used, the other ones are left as is.
+    pub fn with_files(mut self, files: Vec<String>) -> Stripper {
+        self.files = Some(files);
+        self
+    }
+
+    /// Skips the files which `cache` knows to be unaffected and updates it with the files where
+    /// nothing has to be done. It isn't saved, see [`Stripper::cache`].
+    pub fn with_cache(mut self, cache: Cache) -> Stripper {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Reads and writes the files (the comments file included) through `fs` instead of using
+    /// the disk. The cache and the backups stay on the disk.
+    pub fn with_file_system(mut self, fs: Arc<dyn FileSystem>) -> Stripper {
+        self.fs = fs;
+        self
+    }
+
+    pub fn work_dir(&self) -> &Path {
+        &self.work_dir
+    }
+
+    pub fn comment_file(&self) -> &Path {
+        &self.comment_file
+    }
+
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    pub fn file_system(&self) -> &dyn FileSystem {
+        &*self.fs
+    }
+
+    /// Extracts the doc comments of the files without modifying anything. Only the files which
+    /// have some are returned. If an error occurs, the other files are still processed and all
+    /// errors are returned.
+    pub fn stripped_files(
+        &mut self,
+        options: &StripOptions,
+    ) -> Result<Vec<StrippedFile>, Vec<FileError>> {
+        let files = match self.files {
+            Some(ref files) => files.clone(),
+            None => listed(list_files_of(
+                &*self.fs,
+                &options.diagnostics,
+                &self.work_dir,
+                &options.filter,
+                options.verbose,
+            ))?,
+        };
+        let work_dir = &self.work_dir;
+        let fs = &*self.fs;
+        let results = {
+            let cache = self.cache.as_ref();
+            parallel_map(&files, options.jobs, |s| {
+                let content_hash =
+                    cache.and_then(|_| fs.read(&work_dir.join(s)).ok().map(|c| hash(&c)));
+                if let (Some(cache), Some(h)) = (cache, content_hash) {
+                    if cache.is_stripped(s, h) {
+                        return (None, None);
+                    }
+                }
+                let ret = strip_file_of(fs, work_dir, s, options);
+                (content_hash, Some(ret))
+            })
+        };
+        let mut stripped = vec![];
+        let mut errors = vec![];
+        for (content_hash, ret) in results {
+            match ret {
+                Some(Ok(f)) => {
+                    if f.content.is_some() {
+                        stripped.push(f);
+                    } else if let (Some(ref mut cache), Some(h), true) =
+                        (&mut self.cache, content_hash, options.items.is_empty())
+                    {
+                        // Only files without any doc comment are marked as done.
+                        cache.set_stripped(&f.path, h);
+                    }
+                }
+                Some(Err(e)) => errors.push(e),
+                None => {}
+            }
+        }
+        if errors.is_empty() {
+            Ok(stripped)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Returns the content of the comments file once the `stripped` files have been stripped:
+    /// their doc comments and, if only some files are processed, the sections of the other
+    /// files which are already in it. If only some doc comments are stripped (see
+    /// [`StripOptions::items`]), they are merged into the comments file instead, the entries
+    /// already in it being kept unless they document the same items.
+    pub fn comments(&self, stripped: &[StrippedFile], options: &StripOptions) -> String {
+        if !options.items.is_empty() {
+            return merged_comments(&*self.fs, &self.comment_file, stripped);
+        }
+        let mut comments = match self.files {
+            Some(_) => other_sections(&*self.fs, &self.comment_file, stripped),
+            None => String::new(),
+        };
+        comments.extend(stripped.iter().map(|f| &f.comments[..]));
+        comments
+    }
+
+    /// Writes the new content of the `stripped` files. The comments file isn't written. If one
+    /// of them can't be written, the ones which already have been are restored.
+    pub fn write_files(&mut self, stripped: &[StrippedFile]) -> Result<(), Vec<FileError>> {
+        self.write_stripped(stripped, None)
+    }
+
+    /// Writes the new content of the `stripped` files and then, if any, `comments` into the
+    /// comments file. The files are written first so that the comments file never gets doc
+    /// comments which are still in them: if something can't be written, the files which
+    /// already have been are restored.
+    pub fn write_stripped(
+        &mut self,
+        stripped: &[StrippedFile],
+        comments: Option<&str>,
+    ) -> Result<(), Vec<FileError>> {
+        let originals = write_all(
+            &*self.fs,
+            &self.work_dir,
+            stripped
+                .iter()
+                .filter_map(|f| f.content.as_ref().map(|c| (&f.path[..], &c[..]))),
+        )?;
+        if let Some(comments) = comments {
+            if let Err(e) = self.fs.write(&self.comment_file, comments.as_bytes()) {
+                let mut errors = vec![FileError::new(
+                    &self.comment_file.display().to_string(),
+                    None,
+                    format!("Cannot write file: {}", e),
+                )];
+                errors.extend(restore(&*self.fs, originals));
+                return Err(errors);
+            }
+        }
+        if let Some(ref mut cache) = self.cache {
+            // The files which still have doc comments aren't done.
+            for f in stripped.iter().filter(|f| !f.partial) {
+                if let Some(ref content) = f.content {
+                    cache.set_stripped(&f.path, hash(content.as_bytes()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the doc comments of the files and writes them into the comments file. Nothing is
+    /// written if one of the files can't be stripped, and nothing is modified if one of them (or
+    /// the comments file) can't be written. Returns the files which have been stripped.
+    pub fn strip(&mut self, options: &StripOptions) -> Result<Vec<String>, Vec<FileError>> {
+        let stripped = self.stripped_files(options)?;
+        let comments = self.comments(&stripped, options);
+        self.write_stripped(&stripped, Some(&comments))?;
+        Ok(stripped.into_iter().map(|f| f.path).collect())
+    }
+
+    /// Puts back the doc comments from the comments file into the files.
+    ///
+    /// Every file is processed before any of them is written: if an error occurs, nothing is
+    /// modified and all errors are returned. If one of them can't be written, the ones which
+    /// already have been are restored and the comments file is left untouched.
+    ///
+    /// Returns the files which have been modified, the items which already had doc comments and
+    /// the entries which haven't been put back.
+    pub fn regenerate(&mut self, options: &RegenerateOptions) -> Result<Report, Vec<FileError>> {
+        regenerate(
+            &*self.fs,
+            &self.work_dir,
+            &self.comment_file,
+            self.files.as_deref(),
+            options,
+            self.cache.as_mut(),
+            false,
+        )
+    }
+
+    /// Same as [`Stripper::regenerate`] but nothing is written: the report tells which files
+    /// would be modified.
+    pub fn check(&mut self, options: &RegenerateOptions) -> Result<Report, Vec<FileError>> {
+        regenerate(
+            &*self.fs,
+            &self.work_dir,
+            &self.comment_file,
+            self.files.as_deref(),
+            options,
+            self.cache.as_mut(),
+            true,
+        )
+    }
+
+    /// Computes what [`Stripper::regenerate`] would do without writing anything: returns the new
+    /// content of the files which would be modified (in the order they are walked through) and
+    /// the report.
+    pub fn preview(
+        &self,
+        options: &RegenerateOptions,
+    ) -> Result<(Modified, Report), Vec<FileError>> {
+        prepare_regeneration(
+            &*self.fs,
+            &self.work_dir,
+            &self.comment_file,
+            self.files.as_deref(),
+            options,
+            None,
+        )
+        .map(|(regenerated, _, report)| (regenerated, report))
+    }
+}
+
+/// Returns the content of the comments file `path` without the sections of the `stripped` files.
+fn other_sections(fs: &dyn FileSystem, path: &Path, stripped: &[StrippedFile]) -> String {
+    let content = fs.read_to_string(path).unwrap_or_default();
+    let mut out = String::new();
+    let mut keep = true;
+    for line in content.split_inclusive('\n') {
+        let file = line
+            .trim_end()
+            .strip_prefix(FILE)
+            .and_then(|l| l.strip_suffix(END_INFO));
+        if let Some(file) = file {
+            keep = !stripped.iter().any(|f| f.path == file);
+        }
+        if keep {
+            out.push_str(line);
+        }
+    }
+    out
+}
+
+/// Returns the content of the comments file `path` with the doc comments of the `stripped` files
+/// added. If it can't be parsed, they are appended to it.
+fn merged_comments(fs: &dyn FileSystem, path: &Path, stripped: &[StrippedFile]) -> String {
+    let content = fs.read_to_string(path).unwrap_or_default();
+    let mut comments = match CommentsFile::parse(&content) {
+        Ok(comments) => comments,
+        Err(_) => {
+            let mut content = content;
+            content.extend(stripped.iter().map(|f| &f.comments[..]));
+            return content;
+        }
+    };
+    for f in stripped {
+        // The doc comments of a file are always readable.
+        let sections = CommentsFile::parse(&f.comments)
+            .map(|c| c.sections)
+            .unwrap_or_default();
+        for section in sections {
+            for entry in section.entries {
+                comments.insert(section.file.clone(), entry);
+            }
+        }
+    }
+    comments.to_string()
+}
@@ -12,17 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use error::ParsePathError;
 use std::borrow::Borrow;
 use std::fmt::{Debug, Display, Error, Formatter};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ParseResult {
     pub event_list: Vec<EventInfo>,
     pub comment_lines: Vec<usize>,
     pub original_content: Vec<String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EventInfo {
     pub line: usize,
     pub event: EventType,
@@ -40,7 +45,8 @@ impl Debug for EventInfo {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum EventType {
     Comment(String),
     FileComment(String),
@@ -61,12 +67,46 @@ impl Debug for EventType {
     }
 }
 
-#[derive(Clone, PartialEq)]
+/// The path of an item. Two paths are equal if they only differ by the visibility of their
+/// items, which isn't written in the comments file.
+///
+/// Use [`TypeStruct::new`] and the `with_*` methods (or parse it) to build one.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub struct TypeStruct {
     pub ty: Type,
     pub parent: Option<Box<TypeStruct>>,
     pub name: String,
     pub args: Vec<String>,
+    /// When several items of a file have the same path, their position (starting at 1) among
+    /// them. `0` otherwise.
+    pub ordinal: usize,
+    /// The visibility written before the item in the source file.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub visibility: Visibility,
+}
+
+impl PartialEq for TypeStruct {
+    fn eq(&self, other: &TypeStruct) -> bool {
+        self.ty == other.ty
+            && self.parent == other.parent
+            && self.name == other.name
+            && self.args == other.args
+            && self.ordinal == other.ordinal
+    }
+}
+
+impl Eq for TypeStruct {}
+
+impl Hash for TypeStruct {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ty.hash(state);
+        self.parent.hash(state);
+        self.name.hash(state);
+        self.args.hash(state);
+        self.ordinal.hash(state);
+    }
 }
 
 impl TypeStruct {
@@ -76,9 +116,26 @@ impl TypeStruct {
             name: name.to_owned(),
             args: vec![],
             parent: None,
+            ordinal: 0,
+            visibility: Visibility::Private,
         }
     }
 
+    pub fn with_parent(mut self, parent: TypeStruct) -> TypeStruct {
+        self.parent = Some(Box::new(parent));
+        self
+    }
+
+    pub fn with_ordinal(mut self, ordinal: usize) -> TypeStruct {
+        self.ordinal = ordinal;
+        self
+    }
+
+    pub fn with_visibility(mut self, visibility: Visibility) -> TypeStruct {
+        self.visibility = visibility;
+        self
+    }
+
     /*pub fn from_args(ty: Type, args: Vec<String>) -> TypeStruct {
         TypeStruct {
             ty: ty,
@@ -94,6 +151,24 @@ impl TypeStruct {
             name: String::new(),
             args: Vec::new(),
             parent: None,
+            ordinal: 0,
+            visibility: Visibility::Private,
+        }
+    }
+
+    /// Returns the visibility the item actually has: enum variants and the items of traits have
+    /// the one of their parent, impls and the items of trait impls are public. The visibility of
+    /// the modules containing the item isn't taken into account.
+    pub fn effective_visibility(&self) -> Visibility {
+        match (self.ty, self.parent.as_deref()) {
+            (Type::Impl, _) => Visibility::Public,
+            (_, Some(p)) if p.ty == Type::Impl && p.name.contains(" for ") => Visibility::Public,
+            (_, Some(p))
+                if p.ty == Type::Trait || (p.ty == Type::Enum && self.ty == Type::Variant) =>
+            {
+                p.effective_visibility()
+            }
+            _ => self.visibility.clone(),
         }
     }
 
@@ -125,9 +200,18 @@ impl Debug for TypeStruct {
                 self.ty,
                 self.name,
                 self.args.join(" ")
-            ),
-            _ => write!(f, "{} {}{}", self.ty, self.name, self.args.join(" ")),
+            )?,
+            _ => write!(f, "{} {}{}", self.ty, self.name, self.args.join(" "))?,
         }
+        write_ordinal(f, self.ordinal)
+    }
+}
+
+fn write_ordinal(f: &mut Formatter, ordinal: usize) -> Result<(), Error> {
+    if ordinal > 0 {
+        write!(f, " #{}", ordinal)
+    } else {
+        Ok(())
     }
 }
 
@@ -135,7 +219,8 @@ fn show(f: &mut Formatter, t: &TypeStruct, is_parent: bool) -> Result<(), Error>
     if is_parent {
         write!(f, "{} {}{}::", t.ty, t.name, t.args.join(" "))
     } else {
-        write!(f, "{} {}{}", t.ty, t.name, t.args.join(" "))
+        write!(f, "{} {}{}", t.ty, t.name, t.args.join(" "))?;
+        write_ordinal(f, t.ordinal)
     }
 }
 
@@ -162,7 +247,167 @@ impl Display for TypeStruct {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Parses an item path, as written by `Debug` (and in comments files) or `Display`:
+///
+/// ```text
+/// path    = segment ("::" segment)*
+/// segment = kind " " name [" #" ordinal]
+/// kind    = "struct" | "mod" | "enum" | "fn" | "const" | "static" | "type" | "variant"
+///         | "impl" | "use" | "trait" | "macro" | "flags" | "?"
+/// ```
+///
+/// A `name` goes up to the next `::` followed by a kind and a space which isn't inside `<>`,
+/// `()` or `[]`: in `impl <T> fmt::Display for Foo<T>::fn fmt`, the name of the impl is
+/// `<T> fmt::Display for Foo<T>`. The name can only be empty with `?`, its brackets must be closed and the
+/// one of an impl can't end with `for`. The ordinal starts at 1: a last word starting with `#` is
+/// always an ordinal, `fn a #x` is an error rather than a function named `a #x`. Spaces around segments and between
+/// the kind and the name are ignored.
+///
+/// The output of `Debug` always parses back to the same path (`args` being empty). `Display`
+/// doesn't write the parents which are macros nor the ordinals of the parents (only the one of
+/// the last segment is written) so its output only parses back to the same path if there are
+/// none.
+///
+/// ```
+/// use stripper_lib::{Type, TypeStruct};
+///
+/// let path = "impl <T> fmt::Display for Foo<T>::fn fmt #2";
+/// let parsed = path.parse::<TypeStruct>().unwrap();
+/// assert_eq!(parsed.ty, Type::Fn);
+/// assert_eq!(parsed.ordinal, 2);
+/// assert_eq!(parsed.parent.as_ref().unwrap().name, "<T> fmt::Display for Foo<T>");
+/// assert_eq!(parsed.to_string(), path);
+/// ```
+impl FromStr for TypeStruct {
+    type Err = ParsePathError;
+
+    fn from_str(s: &str) -> Result<TypeStruct, ParsePathError> {
+        let mut current: Option<TypeStruct> = None;
+        let mut start = 0;
+        for end in segment_ends(s) {
+            let mut t = parse_segment(&s[start..end], start)?;
+            t.parent = current.take().map(Box::new);
+            current = Some(t);
+            start = end + 2;
+        }
+        Ok(current.expect("a path has at least one segment"))
+    }
+}
+
+/// Returns the positions of the `::` separating the segments of the path `s`, and the length of
+/// `s`.
+fn segment_ends(s: &str) -> Vec<usize> {
+    let mut depth = 0usize;
+    let mut ends = vec![];
+    let mut prev = ' ';
+    for (pos, c) in s.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            // `->` in `Fn() -> T`.
+            '>' if prev == '-' => {}
+            '>' | ')' | ']' => depth = depth.saturating_sub(1),
+            ':' if depth == 0 && s[pos..].starts_with("::") => {
+                // Also split before missing segments to report them.
+                let rest = s[pos + 2..].trim_start();
+                if rest.is_empty()
+                    || rest.starts_with("::")
+                    || KINDS.iter().any(|k| {
+                        rest.starts_with(k)
+                            && (rest.len() == k.len() || rest[k.len()..].starts_with(' '))
+                    })
+                {
+                    ends.push(pos);
+                }
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    ends.push(s.len());
+    ends
+}
+
+/// Checks that `name`, found at `offset`, is a complete name for an item of type `ty`.
+fn check_name(ty: Type, name: &str, offset: usize) -> Result<(), ParsePathError> {
+    // The empty path (see `TypeStruct::empty`) has no name.
+    if name.is_empty() && ty != Type::Unknown {
+        return Err(ParsePathError::new(offset, "missing name"));
+    }
+    let mut open = vec![];
+    let mut prev = ' ';
+    for (pos, c) in name.char_indices() {
+        match c {
+            '<' | '(' | '[' => open.push(pos),
+            '>' if prev == '-' => {}
+            '>' | ')' | ']' => {
+                open.pop();
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    if let Some(pos) = open.first() {
+        let c = &name[*pos..*pos + 1];
+        return Err(ParsePathError::new(
+            offset + pos,
+            format!("unclosed `{}`", c),
+        ));
+    }
+    if ty == Type::Impl && (name == "for" || name.ends_with(" for")) {
+        return Err(ParsePathError::new(
+            offset + name.len() - 3,
+            "missing type after `for`",
+        ));
+    }
+    Ok(())
+}
+
+/// Parses the segment `s` of a path, found at `offset` in it.
+fn parse_segment(s: &str, offset: usize) -> Result<TypeStruct, ParsePathError> {
+    if s.trim().is_empty() {
+        return Err(ParsePathError::new(offset, "empty path segment"));
+    }
+    let offset = offset + (s.len() - s.trim_start().len());
+    let s = s.trim_start();
+    let (kind, name) = match s.split_once(' ') {
+        Some((kind, name)) => (kind, name),
+        None => {
+            return Err(ParsePathError::new(
+                offset,
+                format!("expected a kind and a name, found `{}`", s),
+            ))
+        }
+    };
+    let ty = kind
+        .parse::<Type>()
+        .map_err(|e| ParsePathError::new(offset, e.message))?;
+    let offset = offset + kind.len() + 1 + (name.len() - name.trim_start().len());
+    let name = name.trim();
+    let (name, ordinal) = match name.rfind('#') {
+        Some(pos) if pos == 0 || name[..pos].ends_with(' ') => {
+            let o = &name[pos + 1..];
+            match o.parse::<usize>() {
+                Ok(ordinal) if ordinal > 0 && o.bytes().all(|b| b.is_ascii_digit()) => {
+                    (name[..pos].trim_end(), ordinal)
+                }
+                _ => {
+                    return Err(ParsePathError::new(
+                        offset + pos + 1,
+                        format!("invalid ordinal `{}`", o),
+                    ))
+                }
+            }
+        }
+        _ => (name, 0),
+    };
+    check_name(ty, name, offset)?;
+    let mut ret = TypeStruct::new(ty, name);
+    ret.ordinal = ordinal;
+    Ok(ret)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Type {
     Struct,
     Mod,
@@ -229,3 +474,95 @@ impl Display for Type {
         }
     }
 }
+
+/// The visibility of an item.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Visibility {
+    /// No visibility (or `pub(self)`).
+    #[default]
+    Private,
+    /// `pub`.
+    Public,
+    /// `pub(crate)`.
+    Crate,
+    /// `pub(super)`.
+    Super,
+    /// `pub(in path)`.
+    In(String),
+}
+
+impl Visibility {
+    /// Parses the restriction of `pub(...)`, given without the parentheses.
+    pub(crate) fn restricted(restriction: &str) -> Visibility {
+        match restriction.trim() {
+            "crate" => Visibility::Crate,
+            "super" => Visibility::Super,
+            "self" => Visibility::Private,
+            r => match r.strip_prefix("in ") {
+                Some(path) => Visibility::In(path.split_whitespace().collect()),
+                None => Visibility::In(r.to_owned()),
+            },
+        }
+    }
+}
+
+/// Writes the visibility as in Rust, `private` for [`Visibility::Private`].
+impl Display for Visibility {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match *self {
+            Visibility::Private => write!(f, "private"),
+            Visibility::Public => write!(f, "pub"),
+            Visibility::Crate => write!(f, "pub(crate)"),
+            Visibility::Super => write!(f, "pub(super)"),
+            Visibility::In(ref path) => write!(f, "pub(in {})", path),
+        }
+    }
+}
+
+/// Parses the output of `Display`. `crate` and `super` can be used instead of `pub(crate)` and
+/// `pub(super)`.
+impl FromStr for Visibility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Visibility, String> {
+        let restriction = s
+            .strip_prefix("pub")
+            .map(|r| r.trim_start())
+            .and_then(|r| r.strip_prefix('('))
+            .and_then(|r| r.strip_suffix(')'));
+        match (s, restriction) {
+            ("private", _) => Ok(Visibility::Private),
+            ("pub", _) => Ok(Visibility::Public),
+            ("crate", _) => Ok(Visibility::Crate),
+            ("super", _) => Ok(Visibility::Super),
+            (_, Some(r)) if !r.trim().is_empty() => Ok(Visibility::restricted(r)),
+            _ => Err(format!(
+                "Unknown visibility '{}', expected one of: pub, pub(crate), pub(super), \
+                 pub(in PATH), private",
+                s
+            )),
+        }
+    }
+}
+
+/// The words written by `Display` for each kind of item.
+pub(crate) const KINDS: &[&str] = &[
+    "struct", "mod", "enum", "fn", "const", "static", "type", "variant", "impl", "use", "trait",
+    "macro", "flags", "?",
+];
+
+/// Parses the word written by `Display`. Unlike [`Type::from`], unknown words are errors and
+/// `?` is [`Type::Unknown`]. `macro` is [`Type::Macro`].
+impl FromStr for Type {
+    type Err = ParsePathError;
+
+    fn from_str(s: &str) -> Result<Type, ParsePathError> {
+        match s {
+            "variant" => Ok(Type::Variant),
+            "?" => Ok(Type::Unknown),
+            _ if KINDS.contains(&s) => Ok(Type::from(s)),
+            _ => Err(ParsePathError::new(0, format!("unknown item kind `{}`", s))),
+        }
+    }
+}
@@ -12,95 +12,424 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use consts::{END_INFO, FILE, FILE_COMMENT, MOD_COMMENT, OUTPUT_COMMENT_FILE};
+use consts::{BACKUP_DIR, END_INFO, FILE, FILE_COMMENT, MOD_COMMENT, OUTPUT_COMMENT_FILE};
+use diagnostics::{Diagnostic, Diagnostics, Stderr};
+use error::FileError;
+use filter::{is_cargo_target, slash_path, FileFilter, IgnoreFiles};
+use std::collections::HashSet;
 use std::ffi::OsStr;
-use std::fs;
+use std::fs::{self, File};
 use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 use types::TypeStruct;
+use vfs::{DiskFs, FileSystem, FileType};
 
 use crate::Type;
 
+/// Calls `func` on the Rust files of `path` except `files_to_ignore`. Errors met on the way
+/// (unreadable directories for example) don't stop the walk and are returned at the end.
 pub fn loop_over_files<S>(
     path: &Path,
-    func: &mut dyn FnMut(&Path, &str),
+    func: &mut dyn FnMut(&Path, &Path),
     files_to_ignore: &[S],
     verbose: bool,
-) where
+) -> Result<(), Vec<FileError>>
+where
     S: AsRef<Path>,
 {
     do_loop_over_files(path, path, func, files_to_ignore, verbose)
 }
 
-pub fn do_loop_over_files<S>(
-    work_dir: &Path,
+/// Returns the files `loop_over_files` would go through, in the same order, along with the
+/// errors met on the way.
+pub fn list_files<S>(
     path: &Path,
-    func: &mut dyn FnMut(&Path, &str),
     files_to_ignore: &[S],
     verbose: bool,
-) where
+) -> (Vec<String>, Vec<FileError>)
+where
     S: AsRef<Path>,
 {
-    match fs::read_dir(path) {
-        Ok(it) => {
-            let mut entries = vec![];
+    list_filtered_files(path, &FileFilter::ignoring(files_to_ignore), verbose)
+}
 
-            for entry in it {
-                entries.push(entry.unwrap().path().to_owned());
-            }
-            entries.sort();
-            for entry in entries {
-                check_path_type(work_dir, &entry, func, files_to_ignore, verbose);
+/// Calls `func` on the Rust files of `path` kept by `filter`, like [`loop_over_files`].
+pub fn walk_files(
+    path: &Path,
+    filter: &FileFilter,
+    func: &mut dyn FnMut(&Path, &Path),
+    verbose: bool,
+) -> Result<(), Vec<FileError>> {
+    Walker::new(&DiskFs, &Stderr, path, filter, verbose).walk(path, func)
+}
+
+/// Returns the files `walk_files` would go through, in the same order, along with the errors
+/// met on the way (unreadable directories or paths which aren't valid UTF-8 for example): an
+/// error doesn't prevent the other files from being listed, it's up to the caller to decide
+/// whether they can be used.
+pub fn list_filtered_files(
+    path: &Path,
+    filter: &FileFilter,
+    verbose: bool,
+) -> (Vec<String>, Vec<FileError>) {
+    list_files_of(&DiskFs, &Stderr, path, filter, verbose)
+}
+
+/// Same as [`list_filtered_files`] but goes through the files of `fs`.
+pub(crate) fn list_files_of(
+    fs: &dyn FileSystem,
+    diagnostics: &dyn Diagnostics,
+    path: &Path,
+    filter: &FileFilter,
+    verbose: bool,
+) -> (Vec<String>, Vec<FileError>) {
+    let mut files = vec![];
+    let mut errors = vec![];
+    let ret = Walker::new(fs, diagnostics, path, filter, verbose).walk(path, &mut |_, f| match f
+        .to_str()
+    {
+        Some(f) => files.push(f.to_owned()),
+        None => errors.push(FileError::new(
+            &f.to_string_lossy(),
+            None,
+            "The path isn't valid UTF-8".to_owned(),
+        )),
+    });
+    if let Err(e) = ret {
+        errors.extend(e);
+    }
+    (files, errors)
+}
+
+/// Turns the result of [`list_files_of`] into an error if some files couldn't be listed, for the
+/// callers which modify files and mustn't do it on an incomplete list.
+pub(crate) fn listed(
+    (files, errors): (Vec<String>, Vec<FileError>),
+) -> Result<Vec<String>, Vec<FileError>> {
+    if errors.is_empty() {
+        Ok(files)
+    } else {
+        Err(errors)
+    }
+}
+
+/// The original content of the files which have been written, by full path, to restore them if
+/// something goes wrong afterwards.
+pub(crate) type Originals = Vec<(PathBuf, Vec<u8>)>;
+
+/// Writes the new content of `files` (by path relative to `work_dir`) through `fs`. If one of
+/// them can't be written, the ones which already have been are restored and the errors are
+/// returned.
+pub(crate) fn write_all<'a, I>(
+    fs: &dyn FileSystem,
+    work_dir: &Path,
+    files: I,
+) -> Result<Originals, Vec<FileError>>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut originals = vec![];
+    for (path, content) in files {
+        let full_path = work_dir.join(path);
+        let ret = fs
+            .read(&full_path)
+            .and_then(|original| fs.write(&full_path, content.as_bytes()).map(|_| original));
+        match ret {
+            Ok(original) => originals.push((full_path, original)),
+            Err(e) => {
+                let mut errors = vec![FileError::new(
+                    path,
+                    None,
+                    format!("Cannot write file: {}", e),
+                )];
+                errors.extend(restore(fs, originals));
+                return Err(errors);
             }
         }
-        Err(e) => {
-            println!(
-                "Error while trying to iterate over {}: {}",
-                path.display(),
-                e
-            );
-        }
+    }
+    Ok(originals)
+}
+
+/// Writes back the `originals` files, returns the errors met.
+pub(crate) fn restore(fs: &dyn FileSystem, originals: Originals) -> Vec<FileError> {
+    originals
+        .into_iter()
+        .filter_map(|(path, original)| {
+            fs.write(&path, &original).err().map(|e| {
+                FileError::new(
+                    &path.display().to_string(),
+                    None,
+                    format!("Cannot restore file: {}", e),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Returns the number of threads to use for `jobs`: `0` means one per available CPU.
+pub fn jobs_count(jobs: usize) -> usize {
+    if jobs == 0 {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    } else {
+        jobs
     }
 }
 
-fn check_path_type<S>(
+/// Calls `func` on every element of `items` using `jobs` threads and returns the results in the
+/// same order as `items`.
+pub fn parallel_map<T, R, F>(items: &[T], jobs: usize, func: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let jobs = jobs_count(jobs).min(items.len());
+    if jobs <= 1 {
+        return items.iter().map(func).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let pos = next.fetch_add(1, Ordering::SeqCst);
+                if pos >= items.len() {
+                    break;
+                }
+                let ret = func(&items[pos]);
+                results.lock().unwrap()[pos] = Some(ret);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("parallel_map: missing result"))
+        .collect()
+}
+
+pub fn do_loop_over_files<S>(
     work_dir: &Path,
     path: &Path,
-    func: &mut dyn FnMut(&Path, &str),
+    func: &mut dyn FnMut(&Path, &Path),
     files_to_ignore: &[S],
     verbose: bool,
-) where
+) -> Result<(), Vec<FileError>>
+where
     S: AsRef<Path>,
 {
-    match fs::metadata(path) {
-        Ok(m) => {
-            if m.is_dir() {
-                if path == Path::new("..") || path == Path::new(".") {
-                    return;
-                }
-                do_loop_over_files(work_dir, path, func, files_to_ignore, verbose);
+    let filter = FileFilter::ignoring(files_to_ignore);
+    Walker::new(&DiskFs, &Stderr, work_dir, &filter, verbose).walk(path, func)
+}
+
+struct Walker<'a> {
+    fs: &'a dyn FileSystem,
+    diagnostics: &'a dyn Diagnostics,
+    work_dir: &'a Path,
+    filter: &'a FileFilter,
+    ignore_files: Option<IgnoreFiles>,
+    /// Directories already gone through when following symbolic links, to avoid loops.
+    visited: HashSet<PathBuf>,
+    errors: Vec<FileError>,
+    verbose: bool,
+}
+
+impl<'a> Walker<'a> {
+    fn new(
+        fs: &'a dyn FileSystem,
+        diagnostics: &'a dyn Diagnostics,
+        work_dir: &'a Path,
+        filter: &'a FileFilter,
+        verbose: bool,
+    ) -> Walker<'a> {
+        Walker {
+            fs,
+            diagnostics,
+            work_dir,
+            filter,
+            ignore_files: if filter.gitignore {
+                Some(IgnoreFiles::new(fs, work_dir))
             } else {
-                let path_suffix = strip_prefix(path, work_dir).unwrap();
-                let ignore = path == Path::new(&format!("./{}", OUTPUT_COMMENT_FILE))
-                    || path.extension() != Some(OsStr::new("rs"))
-                    || files_to_ignore.iter().any(|s| s.as_ref() == path_suffix);
-                if ignore {
-                    if verbose {
-                        println!("-> {}: ignored", path.display());
+                None
+            },
+            visited: HashSet::new(),
+            errors: vec![],
+            verbose,
+        }
+    }
+
+    fn walk(
+        mut self,
+        path: &Path,
+        func: &mut dyn FnMut(&Path, &Path),
+    ) -> Result<(), Vec<FileError>> {
+        if self.filter.follow_links {
+            if let Ok(dir) = self.fs.canonicalize(path) {
+                self.visited.insert(dir);
+            }
+        }
+        self.walk_dir(path, func);
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn error(&mut self, path: &Path, message: String) {
+        self.errors
+            .push(FileError::new(&path.to_string_lossy(), None, message));
+    }
+
+    fn walk_dir(&mut self, path: &Path, func: &mut dyn FnMut(&Path, &Path)) {
+        match self.fs.read_dir(path) {
+            Ok(mut entries) => {
+                entries.sort();
+                for entry in entries {
+                    self.check_path_type(&entry, func);
+                }
+            }
+            Err(e) => self.error(path, format!("Cannot read directory: {}", e)),
+        }
+    }
+
+    fn is_ignored(&self, rel: &str, is_dir: bool) -> bool {
+        self.filter.is_excluded(rel, is_dir)
+            || self
+                .ignore_files
+                .as_ref()
+                .is_some_and(|i| i.is_ignored(rel, is_dir))
+    }
+
+    fn check_path_type(&mut self, path: &Path, func: &mut dyn FnMut(&Path, &Path)) {
+        let work_dir = self.work_dir;
+        let file_type = match self.fs.file_type(path, false) {
+            Ok(FileType::Symlink) => {
+                if !self.filter.follow_links {
+                    if self.verbose {
+                        self.diagnostics.emit(&info(path, "symbolic link, ignored"));
                     }
                     return;
                 }
-                if verbose {
-                    println!("-> {}", path.display());
+                match self.fs.file_type(path, true) {
+                    Ok(t) => t,
+                    Err(e) => return self.error(path, format!("Broken symbolic link: {}", e)),
+                }
+            }
+            Ok(t) => t,
+            Err(e) => return self.error(path, format!("Cannot read metadata: {}", e)),
+        };
+        let path_suffix = strip_prefix(path, work_dir).unwrap_or(path);
+        let rel = slash_path(path_suffix);
+        if file_type == FileType::Dir {
+            if path == Path::new("..")
+                || path == Path::new(".")
+                || path.file_name() == Some(OsStr::new(BACKUP_DIR))
+            {
+                return;
+            }
+            if (self.filter.skip_target && is_cargo_target(self.fs, path))
+                || self.is_ignored(&rel, true)
+            {
+                if self.verbose {
+                    self.diagnostics.emit(&info(path, "ignored"));
                 }
-                func(work_dir, path_suffix.to_str().unwrap());
+                return;
             }
+            if self.filter.follow_links {
+                match self.fs.canonicalize(path) {
+                    Ok(dir) => {
+                        if !self.visited.insert(dir) {
+                            if self.verbose {
+                                self.diagnostics
+                                    .emit(&info(path, "already visited, ignored"));
+                            }
+                            return;
+                        }
+                    }
+                    Err(e) => return self.error(path, format!("Cannot resolve path: {}", e)),
+                }
+            }
+            let loaded = match self.ignore_files {
+                Some(ref mut i) => i.enter(self.fs, path, &rel),
+                None => 0,
+            };
+            self.walk_dir(path, func);
+            if let Some(ref mut i) = self.ignore_files {
+                i.leave(loaded);
+            }
+        } else {
+            let ignore = path == Path::new(&format!("./{}", OUTPUT_COMMENT_FILE))
+                || path.extension() != Some(OsStr::new("rs"))
+                || self.is_ignored(&rel, false)
+                || !self.filter.is_included(&rel);
+            if ignore {
+                if self.verbose {
+                    self.diagnostics.emit(&info(path, "ignored"));
+                }
+                return;
+            }
+            if self.verbose {
+                self.diagnostics.emit(&info(path, ""));
+            }
+            func(work_dir, path_suffix);
+        }
+    }
+}
+
+/// Returns the message shown in verbose mode about what is done with `path`.
+fn info(path: &Path, message: &str) -> Diagnostic {
+    Diagnostic::info(message).with_path(path.display().to_string())
+}
+
+/// Replaces the content of `path` with `content` without ever leaving it half-written: the new
+/// content is written into a temporary file in the same directory which is then renamed over
+/// `path`. If `path` is a symbolic link, the file it points to is replaced and the link is kept.
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let resolved;
+    let path = match fs::symlink_metadata(path) {
+        Ok(m) if m.file_type().is_symlink() => {
+            // A dangling link gets its target created.
+            resolved = fs::canonicalize(path).or_else(|_| {
+                fs::read_link(path).map(|target| match path.parent() {
+                    Some(dir) => dir.join(target),
+                    None => target,
+                })
+            })?;
+            &resolved
         }
-        Err(e) => {
-            println!("An error occurred on '{}': {}", path.display(), e);
+        _ => path,
+    };
+    let file_name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' isn't a file path", path.display()),
+            ))
         }
+    };
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+    let ret = File::create(&tmp_path).and_then(|mut f| {
+        f.write_all(content)?;
+        if let Ok(m) = fs::metadata(path) {
+            f.set_permissions(m.permissions())?;
+        }
+        f.sync_all()?;
+        fs::rename(&tmp_path, path)
+    });
+    if ret.is_err() {
+        let _ = fs::remove_file(&tmp_path);
     }
+    ret
 }
 
 pub fn join(s: &[String], join_part: &str) -> String {
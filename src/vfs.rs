@@ -0,0 +1,280 @@
+// Copyright 2015 Gomez Guillaume
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Access to the files to strip or regenerate, so that they don't have to be on the disk.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use utils::write_atomically;
+
+/// Type of an entry of a [`FileSystem`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// Where the files are read from and written to. [`DiskFs`] is used by default; the whole
+/// pipeline can be run on something else (like a [`MemoryFs`], or the tree of a git commit)
+/// with [`Stripper::with_file_system`](crate::Stripper::with_file_system).
+pub trait FileSystem: Send + Sync {
+    /// Returns the entries of the directory `path` (joined to `path`), in any order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Returns the type of `path`, following symbolic links if `follow_links` is `true`.
+    fn file_type(&self, path: &Path, follow_links: bool) -> io::Result<FileType>;
+
+    /// Returns the absolute form of `path`, with symbolic links resolved.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Replaces the content of `path` (creating it if needed) with `content`. The file must never
+    /// be seen half-written.
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Returns `true` if `path` is a file, following symbolic links.
+    fn is_file(&self, path: &Path) -> bool {
+        self.file_type(path, true).ok() == Some(FileType::File)
+    }
+
+    /// Returns `true` if `path` exists (symbolic links included, even broken ones).
+    fn exists(&self, path: &Path) -> bool {
+        self.file_type(path, false).is_ok()
+    }
+}
+
+/// The files of the disk.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiskFs;
+
+impl FileSystem for DiskFs {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect()
+    }
+
+    fn file_type(&self, path: &Path, follow_links: bool) -> io::Result<FileType> {
+        let m = if follow_links {
+            fs::metadata(path)?
+        } else {
+            fs::symlink_metadata(path)?
+        };
+        let t = m.file_type();
+        Ok(if t.is_symlink() {
+            FileType::Symlink
+        } else if t.is_dir() {
+            FileType::Dir
+        } else {
+            FileType::File
+        })
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        write_atomically(path, content)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+}
+
+/// Files kept in memory. Paths are resolved from `/`: `src/lib.rs`, `./src/lib.rs` and
+/// `/src/lib.rs` are the same file. Directories are created as needed when writing files and
+/// there are no symbolic links.
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    tree: Mutex<Tree>,
+}
+
+#[derive(Debug, Default)]
+struct Tree {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    /// The directories created with `create_dir` or containing files, and their parents. They
+    /// are kept when their files are removed.
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl Tree {
+    fn is_dir(&self, path: &Path) -> bool {
+        path == Path::new("/") || self.dirs.contains(path)
+    }
+
+    /// Adds the directory `path` and its parents.
+    fn insert_dir(&mut self, path: &Path) {
+        for dir in path.ancestors() {
+            // Its parents have been added with it.
+            if !self.dirs.insert(dir.to_path_buf()) {
+                break;
+            }
+        }
+    }
+
+    /// Adds the file `path` and the directories containing it.
+    fn insert_file(&mut self, path: PathBuf, content: Vec<u8>) {
+        if let Some(parent) = path.parent() {
+            self.insert_dir(parent);
+        }
+        self.files.insert(path, content);
+    }
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// Adds the file `path`, replacing it if it already exists.
+    pub fn insert<P: AsRef<Path>, C: Into<Vec<u8>>>(&self, path: P, content: C) {
+        let path = normalize(path.as_ref());
+        self.tree.lock().unwrap().insert_file(path, content.into());
+    }
+
+    /// Adds the directory `path` (and its parents), even if it doesn't contain any file.
+    pub fn create_dir<P: AsRef<Path>>(&self, path: P) {
+        let path = normalize(path.as_ref());
+        self.tree.lock().unwrap().insert_dir(&path);
+    }
+
+    /// Returns the content of the file `path`.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<Vec<u8>> {
+        let path = normalize(path.as_ref());
+        self.tree.lock().unwrap().files.get(&path).cloned()
+    }
+
+    /// Returns the paths of all the files, sorted.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.tree.lock().unwrap().files.keys().cloned().collect()
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("'{}' doesn't exist", path.display()),
+    )
+}
+
+/// Returns the absolute form of `path`, without `.` and `..` components.
+fn normalize(path: &Path) -> PathBuf {
+    let mut ret = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => ret.push(c),
+            Component::ParentDir => {
+                ret.pop();
+            }
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+    ret
+}
+
+impl FileSystem for MemoryFs {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = normalize(path);
+        let tree = self.tree.lock().unwrap();
+        if !tree.is_dir(&dir) {
+            return Err(not_found(path));
+        }
+        let names = tree
+            .files
+            .keys()
+            .chain(tree.dirs.iter())
+            .filter_map(|p| p.strip_prefix(&dir).ok())
+            .filter_map(|p| p.components().next())
+            .map(|c| c.as_os_str().to_owned())
+            .collect::<BTreeSet<_>>();
+        Ok(names.into_iter().map(|name| path.join(name)).collect())
+    }
+
+    fn file_type(&self, path: &Path, _follow_links: bool) -> io::Result<FileType> {
+        let path = normalize(path);
+        let tree = self.tree.lock().unwrap();
+        if tree.files.contains_key(&path) {
+            Ok(FileType::File)
+        } else if tree.is_dir(&path) {
+            Ok(FileType::Dir)
+        } else {
+            Err(not_found(&path))
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.file_type(path, true).map(|_| normalize(path))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.get(path).ok_or_else(|| not_found(path))
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        let path = normalize(path);
+        let mut tree = self.tree.lock().unwrap();
+        if path == Path::new("/") || (tree.is_dir(&path) && !tree.files.contains_key(&path)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is a directory", path.display()),
+            ));
+        }
+        tree.insert_file(path, content.to_vec());
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let content = tree
+            .files
+            .remove(&normalize(from))
+            .ok_or_else(|| not_found(from))?;
+        tree.insert_file(normalize(to), content);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.tree
+            .lock()
+            .unwrap()
+            .files
+            .remove(&normalize(path))
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+}
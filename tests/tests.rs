@@ -12,12 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+// The tests of the deprecated functions are kept as they were.
+#![allow(deprecated)]
+
+#[cfg(feature = "serde")]
+extern crate serde_json;
 extern crate stripper_lib;
 extern crate tempfile;
 
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
+use stripper_lib::{OnConflict, RegenerateOptions, StripOptions, Stripper};
 use tempfile::{tempdir, TempDir};
 
 const BASIC: &str = r#"//! File comment
@@ -1905,3 +1911,2415 @@ A function that takes no arguments.
     );
     compare_files(TARGET_RS, &temp_dir.path().join(src_path));
 }
+
+/// The two ways of running the stripper: the deprecated functions the original tests use and
+/// [`Stripper`]. Both must give the same results.
+#[derive(Clone, Copy, Debug)]
+enum EntryPoint {
+    Legacy,
+    Stripper,
+}
+
+impl EntryPoint {
+    fn strip(self, temp_dir: &TempDir, test_file: &str, comment_file: &str, ignore_macros: bool) {
+        match self {
+            EntryPoint::Legacy => {
+                let mut f = gen_file(temp_dir, comment_file, "");
+                stripper_lib::strip_comments(temp_dir.path(), test_file, &mut f, ignore_macros);
+            }
+            EntryPoint::Stripper => {
+                Stripper::new(temp_dir.path(), temp_dir.path().join(comment_file))
+                    .strip(&StripOptions::new().ignore_macros(ignore_macros))
+                    .unwrap();
+            }
+        }
+    }
+
+    fn regenerate(
+        self,
+        temp_dir: &TempDir,
+        comment_file: &str,
+        ignore_macros: bool,
+        ignore_doc_commented: bool,
+    ) {
+        match self {
+            EntryPoint::Legacy => stripper_lib::regenerate_doc_comments(
+                temp_dir.path().to_str().unwrap(),
+                false,
+                temp_dir.path().join(comment_file).to_str().unwrap(),
+                ignore_macros,
+                ignore_doc_commented,
+            ),
+            EntryPoint::Stripper => {
+                let on_conflict = if ignore_doc_commented {
+                    OnConflict::Skip
+                } else {
+                    OnConflict::Append
+                };
+                Stripper::new(temp_dir.path(), temp_dir.path().join(comment_file))
+                    .regenerate(
+                        &RegenerateOptions::new()
+                            .ignore_macros(ignore_macros)
+                            .on_conflict(on_conflict),
+                    )
+                    .unwrap();
+            }
+        }
+    }
+}
+
+const ENTRY_POINTS: [EntryPoint; 2] = [EntryPoint::Legacy, EntryPoint::Stripper];
+
+// The cases of the `*_strip` tests: the file name, its content, `ignore_macros`, and the
+// expected comments file and stripped file (if checked).
+type StripCase<'a> = (&'a str, &'a str, bool, Option<String>, Option<&'a str>);
+
+#[test]
+fn strip_entry_points() {
+    let cases: &[StripCase] = &[
+        (
+            "basic.rs",
+            BASIC,
+            false,
+            Some(get_basic_md("basic.rs")),
+            Some(BASIC_STRIPPED),
+        ),
+        (
+            "basic.rs",
+            BASIC2,
+            true,
+            Some(get_basic2_md("basic.rs")),
+            Some(BASIC2_STRIPPED),
+        ),
+        (
+            "basic.rs",
+            BASIC3,
+            false,
+            Some(get_basic3_md("basic.rs")),
+            Some(BASIC3_STRIPPED),
+        ),
+        (
+            "basic.rs",
+            BASIC4,
+            false,
+            Some(get_basic4_md()),
+            Some(BASIC4),
+        ),
+        (
+            "basic.rs",
+            BASIC5,
+            false,
+            Some(get_basic5_md("basic.rs")),
+            Some(BASIC5_STRIPPED),
+        ),
+        (
+            "basic.rs",
+            BASIC8,
+            false,
+            Some(get_basic8_md("basic.rs")),
+            Some(BASIC8_STRIPPED),
+        ),
+        (
+            "basic.rs",
+            BASIC9,
+            false,
+            Some(get_basic9_md("basic.rs")),
+            Some(BASIC9_STRIPPED),
+        ),
+        (
+            "basic.rs",
+            BASIC10,
+            false,
+            Some(get_basic10_md("basic.rs")),
+            Some(BASIC10_STRIPPED),
+        ),
+        (
+            "basic.rs",
+            BASIC11,
+            false,
+            Some(get_basic11_md("basic.rs")),
+            Some(BASIC11_STRIPPED),
+        ),
+        (
+            "basic.rs",
+            BASIC12,
+            false,
+            Some(get_basic12_md("basic.rs")),
+            Some(BASIC12),
+        ),
+        (
+            "basic.rs",
+            BASIC13,
+            false,
+            Some(get_basic13_md("basic.rs")),
+            Some(BASIC13_STRIPPED),
+        ),
+        (
+            "basic14.rs",
+            BASIC14,
+            true,
+            Some(get_basic14_md("basic14.rs")),
+            Some(BASIC14_STRIPPED),
+        ),
+        (
+            "basic15-strip.rs",
+            BASIC15,
+            true,
+            Some(get_basic15_md("basic15-strip.rs")),
+            Some(BASIC15_STRIPPED),
+        ),
+        ("basic16-strip.rs", BASIC16, true, None, Some(BASIC16)),
+        ("basic17-strip.rs", BASIC17, true, None, None),
+        (
+            "basic18-strip.rs",
+            BASIC18,
+            true,
+            Some(get_basic18_md("basic18-strip.rs")),
+            Some(BASIC18_STRIPPED),
+        ),
+    ];
+    for entry in &ENTRY_POINTS {
+        for &(test_file, content, ignore_macros, ref md, stripped) in cases {
+            println!("{:?}: {}", entry, test_file);
+            let comment_file = test_file.replace(".rs", ".md");
+            let temp_dir = tempdir().unwrap();
+            gen_file(&temp_dir, test_file, content);
+            entry.strip(&temp_dir, test_file, &comment_file, ignore_macros);
+            if let Some(md) = md {
+                compare_files(md, &temp_dir.path().join(&comment_file));
+            }
+            if let Some(stripped) = stripped {
+                compare_files(stripped, &temp_dir.path().join(test_file));
+            }
+        }
+    }
+}
+
+// The cases of the `*_regeneration` tests: the file name, its content, the comments file,
+// `ignore_macros`, `ignore_doc_commented` and the expected file.
+type RegenerateCase<'a> = (&'a str, &'a str, String, bool, bool, &'a str);
+
+#[test]
+fn regenerate_entry_points() {
+    let cases: &[RegenerateCase] = &[
+        (
+            "basic.rs",
+            BASIC_STRIPPED,
+            get_basic_md("basic.rs"),
+            false,
+            false,
+            BASIC,
+        ),
+        (
+            "basic.rs",
+            BASIC2_STRIPPED,
+            BASIC2_MD.to_owned(),
+            true,
+            false,
+            BASIC2,
+        ),
+        (
+            "basic.rs",
+            BASIC3_STRIPPED,
+            get_basic3_md("basic.rs"),
+            false,
+            false,
+            BASIC3_REGEN,
+        ),
+        ("basic.rs", BASIC4, get_basic4_md(), false, false, BASIC4),
+        (
+            "basic.rs",
+            BASIC5_STRIPPED,
+            get_basic5_md("basic.rs"),
+            false,
+            false,
+            BASIC5,
+        ),
+        (
+            "basic.rs",
+            BASIC6,
+            get_basic6_md("basic.rs"),
+            false,
+            true,
+            BASIC6_REGEN,
+        ),
+        (
+            "basic.rs",
+            BASIC7,
+            get_basic7_md("basic.rs"),
+            false,
+            true,
+            BASIC7,
+        ),
+        (
+            "basic.rs",
+            BASIC8,
+            get_basic8_md("basic.rs"),
+            false,
+            true,
+            BASIC8,
+        ),
+        (
+            "basic.rs",
+            BASIC9_STRIPPED,
+            get_basic9_md("basic.rs"),
+            false,
+            true,
+            BASIC9,
+        ),
+        (
+            "basic.rs",
+            BASIC10_STRIPPED,
+            get_basic10_md("basic.rs"),
+            true,
+            false,
+            BASIC10,
+        ),
+        (
+            "basic.rs",
+            BASIC10_STRIPPED,
+            get_basic10_md("basic.rs"),
+            false,
+            true,
+            BASIC10,
+        ),
+        (
+            "basic.rs",
+            BASIC10_STRIPPED,
+            get_basic10_md("basic.rs"),
+            false,
+            false,
+            BASIC10,
+        ),
+        (
+            "basic.rs",
+            BASIC10_STRIPPED,
+            get_basic10_md("basic.rs"),
+            true,
+            true,
+            BASIC10,
+        ),
+        (
+            "basic.rs",
+            BASIC11_STRIPPED,
+            get_basic11_md("basic.rs"),
+            true,
+            false,
+            BASIC11,
+        ),
+        (
+            "basic.rs",
+            BASIC13_STRIPPED,
+            get_basic13_md("basic.rs"),
+            false,
+            false,
+            BASIC13_WEIRD,
+        ),
+        (
+            "basic14.rs",
+            BASIC14_STRIPPED,
+            BASIC14_MD.to_owned(),
+            true,
+            false,
+            BASIC14,
+        ),
+        (
+            "basic15.rs",
+            BASIC15_STRIPPED,
+            get_basic15_md("basic15.rs"),
+            true,
+            false,
+            BASIC15,
+        ),
+    ];
+    for entry in &ENTRY_POINTS {
+        for &(test_file, content, ref md, ignore_macros, ignore_doc_commented, expected) in cases {
+            println!("{:?}: {}", entry, test_file);
+            let comment_file = test_file.replace(".rs", ".md");
+            let temp_dir = tempdir().unwrap();
+            gen_file(&temp_dir, test_file, content);
+            gen_file(&temp_dir, &comment_file, md);
+            entry.regenerate(
+                &temp_dir,
+                &comment_file,
+                ignore_macros,
+                ignore_doc_commented,
+            );
+            compare_files(expected, &temp_dir.path().join(test_file));
+        }
+    }
+}
+
+#[test]
+fn backup_and_undo() {
+    let test_file = "backup.rs";
+    let comment_file = "backup.md";
+    let temp_dir = tempdir().unwrap();
+    gen_file(&temp_dir, test_file, BASIC);
+    {
+        let mut backup = stripper_lib::Backup::new(temp_dir.path()).unwrap();
+        backup.save(&temp_dir.path().join(test_file)).unwrap();
+        backup.save(&temp_dir.path().join(comment_file)).unwrap();
+    }
+    Stripper::new(temp_dir.path(), temp_dir.path().join(comment_file))
+        .strip(&StripOptions::new())
+        .unwrap();
+    compare_files(BASIC_STRIPPED, &temp_dir.path().join(test_file));
+
+    let restored = stripper_lib::undo(temp_dir.path()).unwrap().unwrap();
+    assert_eq!(restored.len(), 2);
+    compare_files(BASIC, &temp_dir.path().join(test_file));
+    // The comments file didn't exist before the run so it's removed.
+    assert!(!temp_dir.path().join(comment_file).exists());
+    assert!(!temp_dir.path().join(stripper_lib::BACKUP_DIR).exists());
+    // Nothing left to undo.
+    assert!(stripper_lib::undo(temp_dir.path()).unwrap().is_none());
+
+    // Only the files which are regenerated are saved.
+    let root = std::fs::canonicalize(temp_dir.path()).unwrap();
+    std::fs::create_dir_all(root.join("target")).unwrap();
+    gen_file(&temp_dir, "target/CACHEDIR.TAG", "");
+    gen_file(&temp_dir, "target/gen.rs", "fn foo() {}\n");
+    gen_file(&temp_dir, "other.rs", "fn bar() {}\n");
+    gen_file(&temp_dir, test_file, BASIC_STRIPPED);
+    gen_file(&temp_dir, comment_file, &get_basic_md(test_file));
+    let dir = root.to_str().unwrap();
+    let comments = root.join(comment_file);
+    let comments = comments.to_str().unwrap();
+    assert_eq!(
+        run_cli(&["regenerate", "-b", "-d", dir, "-o", comments]).0,
+        Some(0)
+    );
+    compare_files(BASIC, &temp_dir.path().join(test_file));
+    let restored = stripper_lib::undo(&root).unwrap().unwrap();
+    assert_eq!(restored, [root.join(comment_file), root.join(test_file)]);
+    compare_files(BASIC_STRIPPED, &temp_dir.path().join(test_file));
+}
+
+#[test]
+fn strip_file_error() {
+    use std::io;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use stripper_lib::diagnostics::Collector;
+    use stripper_lib::{FileSystem, FileType, MemoryFs};
+
+    static SRC_RS: &str = r###"
+struct Foo {
+    //! misplaced file comment
+    a: u32,
+}
+"###;
+
+    let test_file = "strip-error.rs";
+    let temp_dir = tempdir().unwrap();
+    gen_file(&temp_dir, test_file, SRC_RS);
+    let err = stripper_lib::strip_file(temp_dir.path(), test_file, false).unwrap_err();
+    assert_eq!(err.path, test_file);
+    assert_eq!(err.line, Some(3));
+    // Nothing should have been modified.
+    compare_files(SRC_RS, &temp_dir.path().join(test_file));
+
+    // Nothing is modified either if a file (or the comments file) can't be written.
+    struct FailingFs {
+        fs: MemoryFs,
+        fail: &'static str,
+    }
+
+    impl FileSystem for FailingFs {
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            self.fs.read_dir(path)
+        }
+        fn file_type(&self, path: &Path, follow_links: bool) -> io::Result<FileType> {
+            self.fs.file_type(path, follow_links)
+        }
+        fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+            self.fs.canonicalize(path)
+        }
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.fs.read(path)
+        }
+        fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+            if path == Path::new(self.fail) {
+                return Err(io::Error::other("disk full"));
+            }
+            self.fs.write(path, content)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            self.fs.rename(from, to)
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            if path == Path::new(self.fail) {
+                return Err(io::Error::other("read-only"));
+            }
+            self.fs.remove_file(path)
+        }
+    }
+
+    static A_RS: &str = "/// A.\nstruct A;\n";
+    static B_RS: &str = "/// B.\nstruct B;\n";
+    for fail in &["src/b.rs", "docs.md"] {
+        let fs = FailingFs {
+            fs: MemoryFs::new(),
+            fail,
+        };
+        fs.fs.insert("src/a.rs", A_RS);
+        fs.fs.insert("src/b.rs", B_RS);
+        let fs = Arc::new(fs);
+        let errors = Stripper::new("src", "docs.md")
+            .with_file_system(fs.clone())
+            .strip(&StripOptions::new())
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Cannot write file: disk full");
+        assert_eq!(fs.fs.get("src/a.rs").unwrap(), A_RS.as_bytes());
+        assert_eq!(fs.fs.get("src/b.rs").unwrap(), B_RS.as_bytes());
+        assert!(fs.fs.get("docs.md").is_none());
+    }
+
+    // Same thing when regenerating, the comments file being left untouched.
+    static DOCS_MD: &str = "<!-- file a.rs -->\n<!-- struct A -->\nA.\n\
+                            <!-- file b.rs -->\n<!-- struct B -->\nB.\n";
+    let fs = FailingFs {
+        fs: MemoryFs::new(),
+        fail: "src/b.rs",
+    };
+    fs.fs.insert("src/a.rs", "struct A;\n");
+    fs.fs.insert("src/b.rs", "struct B;\n");
+    fs.fs.insert("docs.md", DOCS_MD);
+    let fs = Arc::new(fs);
+    let errors = Stripper::new("src", "docs.md")
+        .with_file_system(fs.clone())
+        .regenerate(&RegenerateOptions::new().update_comment_file(true))
+        .unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "Cannot write file: disk full");
+    assert_eq!(fs.fs.get("src/a.rs").unwrap(), b"struct A;\n");
+    assert_eq!(fs.fs.get("src/b.rs").unwrap(), b"struct B;\n");
+    assert_eq!(fs.fs.get("docs.md").unwrap(), DOCS_MD.as_bytes());
+
+    // An emptied comments file which can't be removed is reported.
+    let collector = Arc::new(Collector::new());
+    let fs = FailingFs {
+        fs: MemoryFs::new(),
+        fail: "docs.md",
+    };
+    fs.fs.insert("src/a.rs", "struct A;\n");
+    fs.fs
+        .insert("docs.md", "<!-- file a.rs -->\n<!-- struct A -->\nA.\n");
+    Stripper::new("src", "docs.md")
+        .with_file_system(Arc::new(fs))
+        .regenerate(
+            &RegenerateOptions::new()
+                .update_comment_file(true)
+                .diagnostics(collector.clone()),
+        )
+        .unwrap();
+    let diagnostics = collector.take();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "Cannot remove file: read-only");
+}
+
+#[test]
+fn regeneration_is_transactional() {
+    let test_file = "transaction.rs";
+    let comment_file = "transaction.md";
+    let temp_dir = tempdir().unwrap();
+    gen_file(&temp_dir, test_file, BASIC_STRIPPED);
+    gen_file(
+        &temp_dir,
+        comment_file,
+        &format!("invalid line\n{}", get_basic_md(test_file)),
+    );
+    let errors = Stripper::new(temp_dir.path(), temp_dir.path().join(comment_file))
+        .regenerate(&RegenerateOptions::new())
+        .unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, Some(1));
+    compare_files(BASIC_STRIPPED, &temp_dir.path().join(test_file));
+}
+
+#[test]
+fn parallel_regeneration() {
+    static DOCS_MD: &str = r###"<!-- file * -->
+<!-- struct Foo -->
+Only the first file gets this one.
+<!-- fn bar -->
+bar!
+<!-- file c.rs -->
+<!-- fn baz -->
+baz!
+"###;
+    static SRC_RS: &str = "struct Foo;\nfn bar() {}\nfn baz() {}\n";
+    let files = ["a.rs", "b.rs", "c.rs", "d.rs"];
+
+    let run = |jobs| {
+        let temp_dir = tempdir().unwrap();
+        for file in &files {
+            gen_file(&temp_dir, file, SRC_RS);
+        }
+        gen_file(&temp_dir, "docs.md", DOCS_MD);
+        Stripper::new(temp_dir.path(), temp_dir.path().join("docs.md"))
+            .regenerate(
+                &RegenerateOptions::new()
+                    .jobs(jobs)
+                    .update_comment_file(true),
+            )
+            .unwrap();
+        files
+            .iter()
+            .map(|file| std::fs::read_to_string(temp_dir.path().join(file)).unwrap())
+            .collect::<Vec<_>>()
+    };
+    let expected = run(1);
+    assert_eq!(
+        expected[0],
+        "/// Only the first file gets this one.\nstruct Foo;\n/// bar!\nfn bar() {}\nfn baz() {}\n"
+    );
+    assert_eq!(
+        expected[2],
+        "struct Foo;\nfn bar() {}\n/// baz!\nfn baz() {}\n"
+    );
+    assert_eq!(run(4), expected);
+}
+
+#[test]
+fn check_and_cache() {
+    static DOCS_MD: &str = r###"<!-- file a.rs -->
+<!-- fn bar -->
+bar!
+<!-- file b.rs -->
+<!-- fn qux -->
+qux!
+"###;
+    static SRC_RS: &str = "fn bar() {}\n";
+    let temp_dir = tempdir().unwrap();
+    gen_file(&temp_dir, "a.rs", SRC_RS);
+    gen_file(&temp_dir, "b.rs", SRC_RS);
+    gen_file(&temp_dir, "docs.md", DOCS_MD);
+    let options = RegenerateOptions::new().update_comment_file(true);
+    let mut stripper = Stripper::new(temp_dir.path(), temp_dir.path().join("docs.md"))
+        .with_cache(stripper_lib::Cache::load(temp_dir.path()));
+    assert_eq!(
+        stripper.check(&options).unwrap().modified,
+        vec!["a.rs".to_owned()]
+    );
+    compare_files(SRC_RS, &temp_dir.path().join("a.rs"));
+    compare_files(DOCS_MD, &temp_dir.path().join("docs.md"));
+
+    assert_eq!(
+        stripper.regenerate(&options).unwrap().modified,
+        vec!["a.rs".to_owned()]
+    );
+    compare_files("/// bar!\nfn bar() {}\n", &temp_dir.path().join("a.rs"));
+    compare_files(SRC_RS, &temp_dir.path().join("b.rs"));
+    stripper.cache().unwrap().save().unwrap();
+
+    // Only the files into which nothing was put are remembered.
+    let saved = std::fs::read_to_string(temp_dir.path().join(".rustdoc-stripper-cache")).unwrap();
+    assert!(saved
+        .lines()
+        .any(|l| l.starts_with("regenerate ") && l.ends_with(" b.rs")));
+    assert!(!saved.lines().any(|l| l.ends_with(" a.rs")));
+
+    gen_file(
+        &temp_dir,
+        "docs.md",
+        "<!-- file c.rs -->\n<!-- fn bar -->\nbar!\n",
+    );
+    let mut stripper = Stripper::new(temp_dir.path(), temp_dir.path().join("docs.md"))
+        .with_cache(stripper_lib::Cache::load(temp_dir.path()));
+    assert!(stripper.check(&options).unwrap().modified.is_empty());
+}
+
+#[test]
+fn fuzzy_regeneration() {
+    static DOCS_MD: &str = r###"<!-- file a.rs -->
+<!-- fn do_stuff -->
+Does stuff.
+<!-- impl Foo::fn bar -->
+Bar!
+<!-- fn unrelated -->
+Nowhere to go.
+"###;
+    static SRC_RS: &str = "fn do_stuf() {}\nimpl Baz {\n    fn bar() {}\n}\n";
+    let run = |fuzzy| {
+        let temp_dir = tempdir().unwrap();
+        gen_file(&temp_dir, "a.rs", SRC_RS);
+        gen_file(&temp_dir, "docs.md", DOCS_MD);
+        let modified = Stripper::new(temp_dir.path(), temp_dir.path().join("docs.md"))
+            .regenerate(
+                &RegenerateOptions::new()
+                    .fuzzy(fuzzy)
+                    .update_comment_file(true),
+            )
+            .unwrap()
+            .modified;
+        let read = |f| std::fs::read_to_string(temp_dir.path().join(f)).unwrap();
+        (modified, read("a.rs"), read("docs.md"))
+    };
+
+    let (modified, content, docs) = run(false);
+    assert!(modified.is_empty());
+    assert_eq!(content, SRC_RS);
+    assert_eq!(docs, DOCS_MD);
+
+    let (modified, content, docs) = run(true);
+    assert_eq!(modified, vec!["a.rs".to_owned()]);
+    assert_eq!(
+        content,
+        "/// Does stuff.\nfn do_stuf() {}\nimpl Baz {\n    /// Bar!\n    fn bar() {}\n}\n"
+    );
+    assert_eq!(
+        docs,
+        "<!-- file a.rs -->\n<!-- fn unrelated -->\nNowhere to go.\n"
+    );
+}
+
+#[test]
+fn fuzzy_regeneration_by_signature() {
+    use std::sync::Arc;
+    use stripper_lib::{CommentsFile, MemoryFs};
+
+    static SRC_RS: &str = r###"/// The answer.
+pub fn answer(question: &str) -> u32 {
+    42
+}
+
+/// Adds.
+pub fn add(a: u8, b: u8) -> u8 {
+    a + b
+}
+
+/// Gone.
+fn gone(x: f64) {}
+"###;
+    static RENAMED_RS: &str = r###"pub fn compute(question: &str) -> u32 {
+    42
+}
+
+pub fn sum(a: u8, b: u8) -> u8 {
+    a + b
+}
+"###;
+    let run = |signatures| {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("src/lib.rs", SRC_RS);
+        let read = |path: &str| String::from_utf8(fs.get(path).unwrap()).unwrap();
+        let mut stripper = Stripper::new("src", "docs.md").with_file_system(fs.clone());
+        stripper
+            .strip(&StripOptions::new().signatures(signatures))
+            .unwrap();
+        let docs = read("docs.md");
+        // The signatures are kept when the comments file is parsed and written back.
+        assert_eq!(CommentsFile::parse(&docs).unwrap().to_string(), docs);
+        fs.insert("src/lib.rs", RENAMED_RS);
+        let report = stripper
+            .regenerate(
+                &RegenerateOptions::new()
+                    .fuzzy(true)
+                    .update_comment_file(true),
+            )
+            .unwrap();
+        (
+            docs,
+            report.unplaced.len(),
+            read("src/lib.rs"),
+            read("docs.md"),
+        )
+    };
+
+    let (docs, unplaced, content, remaining) = run(false);
+    assert!(!docs.contains(" @"));
+    assert_eq!(unplaced, 3);
+    assert_eq!(content, RENAMED_RS);
+    assert_eq!(remaining, docs);
+
+    let (docs, unplaced, content, remaining) = run(true);
+    let headers = docs
+        .lines()
+        .filter(|l| l.starts_with("<!-- fn "))
+        .collect::<Vec<_>>();
+    assert_eq!(headers.len(), 3);
+    assert!(headers.iter().all(|h| h.contains(" @")));
+    assert_eq!(unplaced, 1);
+    assert_eq!(
+        content,
+        "/// The answer.\npub fn compute(question: &str) -> u32 {\n    42\n}\n\n/// Adds.\n\
+         pub fn sum(a: u8, b: u8) -> u8 {\n    a + b\n}\n"
+    );
+    // The signature of the remaining entry is kept.
+    assert_eq!(
+        remaining,
+        format!("<!-- file lib.rs -->\n{}\nGone.\n", headers[2])
+    );
+}
+
+#[test]
+fn unplaced_report() {
+    static DOCS_MD: &str = r###"<!-- file a.rs -->
+<!-- fn foo -->
+Foo!
+<!-- fn bar -->
+Bar!
+<!-- fn nothing_alike -->
+Nowhere to go.
+<!-- file missing.rs -->
+<!-- fn foo -->
+Lost.
+"###;
+    let temp_dir = tempdir().unwrap();
+    gen_file(
+        &temp_dir,
+        "a.rs",
+        "/// Already there.\nfn foo() {}\nfn bar() {}\n",
+    );
+    gen_file(&temp_dir, "docs.md", DOCS_MD);
+    let report = Stripper::new(temp_dir.path(), temp_dir.path().join("docs.md"))
+        .regenerate(&RegenerateOptions::new().on_conflict(OnConflict::Skip))
+        .unwrap();
+    assert_eq!(report.modified, vec!["a.rs".to_owned()]);
+    compare_files(
+        "/// Already there.\nfn foo() {}\n/// Bar!\nfn bar() {}\n",
+        &temp_dir.path().join("a.rs"),
+    );
+    // The comments file is left untouched.
+    compare_files(DOCS_MD, &temp_dir.path().join("docs.md"));
+    assert_eq!(
+        report.to_string(),
+        "Some comments haven't been regenerated to the files:\n  \
+         a.rs: `fn foo`: item already documented\n  \
+         a.rs: `fn nothing_alike`: item not found\n  \
+         missing.rs: `fn foo`: file not found\n"
+    );
+    assert_eq!(
+        report.to_json(),
+        r#"{
+  "modified": [
+    "a.rs"
+  ],
+  "conflicts": [],
+  "ambiguous": [],
+  "unplaced": [
+    {"file": "a.rs", "item": "fn foo", "reason": "already-documented"},
+    {"file": "a.rs", "item": "fn nothing_alike", "reason": "item-not-found"},
+    {"file": "missing.rs", "item": "fn foo", "reason": "file-not-found"}
+  ]
+}
+"#
+    );
+}
+
+#[test]
+fn conflict_policies() {
+    static DOCS_MD: &str = r###"<!-- file a.rs -->
+<!-- fn foo -->
+New foo.
+<!-- struct Bar -->
+New bar.
+"###;
+    static SRC_RS: &str = r###"/// Old foo.
+fn foo() {}
+/// Old bar.
+#[derive(Debug)]
+struct Bar;
+"###;
+    let run = |on_conflict| {
+        let temp_dir = tempdir().unwrap();
+        gen_file(&temp_dir, "a.rs", SRC_RS);
+        gen_file(&temp_dir, "docs.md", DOCS_MD);
+        let ret = Stripper::new(temp_dir.path(), temp_dir.path().join("docs.md"))
+            .regenerate(&RegenerateOptions::new().on_conflict(on_conflict));
+        let content = std::fs::read_to_string(temp_dir.path().join("a.rs")).unwrap();
+        (ret, content)
+    };
+
+    let (ret, content) = run(stripper_lib::OnConflict::Replace);
+    let report = ret.unwrap();
+    assert_eq!(
+        report
+            .conflicts
+            .iter()
+            .map(|c| (c.item.to_string(), c.line))
+            .collect::<Vec<_>>(),
+        vec![("fn foo".to_owned(), 2), ("struct Bar".to_owned(), 5)]
+    );
+    assert_eq!(
+        content,
+        "/// New foo.\nfn foo() {}\n/// New bar.\n#[derive(Debug)]\nstruct Bar;\n"
+    );
+
+    let (_, content) = run(stripper_lib::OnConflict::Prepend);
+    assert_eq!(
+        content,
+        "/// New foo.\n/// Old foo.\nfn foo() {}\n/// New bar.\n/// Old bar.\n#[derive(Debug)]\n\
+         struct Bar;\n"
+    );
+
+    let (_, content) = run(stripper_lib::OnConflict::Append);
+    assert_eq!(
+        content,
+        "/// Old foo.\n// rustdoc-stripper-ignore-next-stop\n/// New foo.\nfn foo() {}\n/// Old bar.\n\
+         // rustdoc-stripper-ignore-next-stop\n/// New bar.\n#[derive(Debug)]\nstruct Bar;\n"
+    );
+
+    let (ret, content) = run(stripper_lib::OnConflict::Skip);
+    assert!(ret.unwrap().conflicts.is_empty());
+    assert_eq!(content, SRC_RS);
+
+    let (ret, content) = run(stripper_lib::OnConflict::Fail);
+    let errors = ret.unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, Some(2));
+    assert_eq!(content, SRC_RS);
+}
+
+#[test]
+fn duplicate_item_paths() {
+    static SRC_RS: &str = r###"#[cfg(unix)]
+impl Foo {
+    /// Unix version.
+    fn new() {}
+}
+
+#[cfg(windows)]
+impl Foo {
+    /// Windows version.
+    fn new() {}
+}
+"###;
+    static STRIPPED: &str = r###"#[cfg(unix)]
+impl Foo {
+    fn new() {}
+}
+
+#[cfg(windows)]
+impl Foo {
+    fn new() {}
+}
+"###;
+    static DOCS_MD: &str = r###"<!-- file a.rs -->
+<!-- impl Foo::fn new #1 -->
+Unix version.
+<!-- impl Foo::fn new #2 -->
+Windows version.
+"###;
+    let temp_dir = tempdir().unwrap();
+    gen_file(&temp_dir, "a.rs", SRC_RS);
+    Stripper::new(temp_dir.path(), temp_dir.path().join("docs.md"))
+        .strip(&StripOptions::new())
+        .unwrap();
+    compare_files(STRIPPED, &temp_dir.path().join("a.rs"));
+    compare_files(DOCS_MD, &temp_dir.path().join("docs.md"));
+
+    let regenerate = |docs: &str| {
+        gen_file(&temp_dir, "a.rs", STRIPPED);
+        gen_file(&temp_dir, "docs.md", docs);
+        Stripper::new(temp_dir.path(), temp_dir.path().join("docs.md"))
+            .regenerate(&RegenerateOptions::new())
+            .unwrap()
+    };
+    // The numbered entries go to their own item, whatever their order.
+    let report = regenerate(
+        &DOCS_MD
+            .replace("#1", "#3")
+            .replace("#2", "#1")
+            .replace("#3", "#2"),
+    );
+    assert!(report.ambiguous.is_empty());
+    compare_files(
+        &SRC_RS
+            .replace("Unix", "Tmp")
+            .replace("Windows", "Unix")
+            .replace("Tmp", "Windows"),
+        &temp_dir.path().join("a.rs"),
+    );
+
+    // Entries without ordinal are put in order, with a warning.
+    let report = regenerate(&DOCS_MD.replace(" #1", "").replace(" #2", ""));
+    compare_files(SRC_RS, &temp_dir.path().join("a.rs"));
+    assert_eq!(
+        report
+            .ambiguous
+            .iter()
+            .map(|l| (l.item.to_string(), l.line))
+            .collect::<Vec<_>>(),
+        vec![
+            ("impl Foo::fn new #1".to_owned(), 3),
+            ("impl Foo::fn new #2".to_owned(), 8)
+        ]
+    );
+}
+
+fn run_cli(args: &[&str]) -> (Option<i32>, String) {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rustdoc-stripper"))
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn command_line() {
+    let temp_dir = tempdir().unwrap();
+    gen_file(&temp_dir, "a.rs", "/// foo\nfn foo() {}\n\nfn bar() {}\n");
+    let dir = temp_dir.path().to_str().unwrap();
+    let comments = temp_dir.path().join("c.md");
+    let comments = comments.to_str().unwrap();
+
+    assert_eq!(run_cli(&["--unknown"]).0, Some(2));
+    assert_eq!(run_cli(&["unknown"]).0, Some(2));
+    assert_eq!(run_cli(&["-s", "-g"]).0, Some(2));
+    assert_eq!(run_cli(&["strip", "--fuzzy"]).0, Some(2));
+    assert_eq!(run_cli(&["--verbose=yes"]).0, Some(2));
+    assert_eq!(run_cli(&["-j"]).0, Some(2));
+    assert_eq!(run_cli(&["stats", "-d", "does-not-exist"]).0, Some(1));
+
+    let (code, out) = run_cli(&["stats", &format!("--dir={}", dir)]);
+    assert_eq!(code, Some(0));
+    assert_eq!(out, "1 files, 2 items, 1 documented (50.0%)\n");
+
+    assert_eq!(run_cli(&["check", "-vd", dir]).0, Some(1));
+    let (code, out) = run_cli(&["diff", "-d", dir]);
+    assert_eq!(code, Some(0));
+    assert_eq!(
+        out,
+        "--- a/a.rs\n+++ b/a.rs\n@@ -1,4 +1,3 @@\n-/// foo\n fn foo() {}\n \n fn bar() {}\n"
+    );
+    compare_files(
+        "/// foo\nfn foo() {}\n\nfn bar() {}\n",
+        &temp_dir.path().join("a.rs"),
+    );
+
+    // Same as `strip -f -d DIR -o FILE`.
+    assert_eq!(
+        run_cli(&["-fd", dir, &format!("-o{}", comments)]).0,
+        Some(0)
+    );
+    compare_files(
+        "fn foo() {}\n\nfn bar() {}\n",
+        &temp_dir.path().join("a.rs"),
+    );
+    assert_eq!(run_cli(&["check", "-d", dir, "-o", comments]).0, Some(0));
+
+    let (code, out) = run_cli(&["diff", "-g", "-c", "-d", dir, "-o", comments]);
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        out,
+        "--- a/a.rs\n+++ b/a.rs\n@@ -1,3 +1,4 @@\n+/// foo\n fn foo() {}\n \n fn bar() {}\n"
+    );
+    assert_eq!(
+        run_cli(&["regenerate", "--dir", dir, "--comment-file", comments]).0,
+        Some(0)
+    );
+    compare_files(
+        "/// foo\nfn foo() {}\n\nfn bar() {}\n",
+        &temp_dir.path().join("a.rs"),
+    );
+
+    let (code, out) = run_cli(&["completions", "bash"]);
+    assert_eq!(code, Some(0));
+    assert!(out.contains("complete -F _rustdoc__stripper"));
+    assert!(out.contains("--on-conflict"));
+    assert_eq!(run_cli(&["completions", "powershell"]).0, Some(2));
+}
+
+#[test]
+fn config_file() {
+    let temp_dir = tempdir().unwrap();
+    std::fs::create_dir_all(temp_dir.path().join("src/sub")).unwrap();
+    gen_file(&temp_dir, "src/a.rs", "/// foo\nfn foo() {}\n");
+    gen_file(&temp_dir, "src/b.rs", "/// bar\nfn bar() {}\n");
+    gen_file(
+        &temp_dir,
+        "Cargo.toml",
+        r#"[package]
+name = "foo"
+description = """
+[package.metadata.rustdoc-stripper]
+"""
+
+[package.metadata."rustdoc-stripper"]
+dir = "src" # relative to this file
+comment-file = "docs.md"
+ignore = [
+    "b.rs",
+]
+on-conflict = 'replace'
+jobs = 2
+ignore-macros = true
+
+[dependencies]
+bar = { version = "1" }
+"#,
+    );
+    let config = stripper_lib::Config::find(&temp_dir.path().join("src/sub"))
+        .unwrap()
+        .unwrap();
+    let root = std::fs::canonicalize(temp_dir.path()).unwrap();
+    assert_eq!(config.path, root.join("Cargo.toml"));
+    assert_eq!(config.dir, Some(root.join("src")));
+    assert_eq!(config.comment_file, Some(root.join("docs.md")));
+    assert_eq!(config.ignore, Some(vec!["b.rs".to_owned()]));
+    assert_eq!(config.on_conflict, Some(stripper_lib::OnConflict::Replace));
+    assert_eq!(config.jobs, Some(2));
+    assert_eq!(config.ignore_macros, Some(true));
+    assert_eq!(config.fuzzy, None);
+
+    // The configuration is used, the command line overrides it.
+    let dir = temp_dir.path().join("src/sub");
+    let (code, _) = run_cli(&["-f", "--config", root.join("Cargo.toml").to_str().unwrap()]);
+    assert_eq!(code, Some(0));
+    compare_files("fn foo() {}\n", &temp_dir.path().join("src/a.rs"));
+    compare_files("/// bar\nfn bar() {}\n", &temp_dir.path().join("src/b.rs"));
+    compare_files(
+        "<!-- file a.rs -->\n<!-- fn foo -->\nfoo\n",
+        &temp_dir.path().join("docs.md"),
+    );
+    // The ignored files aren't regenerated either.
+    gen_file(
+        &temp_dir,
+        "docs.md",
+        "<!-- file a.rs -->\n<!-- fn foo -->\nfoo\n<!-- file b.rs -->\n<!-- fn bar -->\nnew bar\n",
+    );
+    let (code, _) = run_cli(&[
+        "regenerate",
+        "--config",
+        root.join("Cargo.toml").to_str().unwrap(),
+    ]);
+    assert_eq!(code, Some(0));
+    compare_files("/// foo\nfn foo() {}\n", &temp_dir.path().join("src/a.rs"));
+    compare_files("/// bar\nfn bar() {}\n", &temp_dir.path().join("src/b.rs"));
+    let (code, out) = run_cli(&[
+        "stats",
+        "--format=json",
+        "-i",
+        "a.rs",
+        "--config",
+        root.join("Cargo.toml").to_str().unwrap(),
+    ]);
+    assert_eq!(code, Some(0));
+    assert_eq!(
+        out,
+        "{\n  \"files\": 1,\n  \"items\": 1,\n  \"documented\": 1\n}\n"
+    );
+
+    // Dotted keys, inline tables, escapes and multi-line arrays are supported.
+    gen_file(
+        &temp_dir,
+        "Cargo.toml",
+        r#"[package]
+name = "foo"
+metadata.rustdoc-stripper = { jobs = 3, ignore = [
+    "\u0062.rs", # comment
+    'c.rs',
+] }
+"#,
+    );
+    let config = stripper_lib::Config::find(&dir).unwrap().unwrap();
+    assert_eq!(config.jobs, Some(3));
+    assert_eq!(
+        config.ignore,
+        Some(vec!["b.rs".to_owned(), "c.rs".to_owned()])
+    );
+
+    // `rustdoc-stripper.toml` has priority and errors are reported.
+    gen_file(&temp_dir, "src/rustdoc-stripper.toml", "jobs = \"two\"\n");
+    let err = stripper_lib::Config::find(&dir).unwrap_err();
+    assert_eq!(err.line, Some(1));
+    assert_eq!(err.message, "`jobs` expects a positive integer");
+    assert_eq!(
+        run_cli(&[
+            "stats",
+            "--config",
+            temp_dir
+                .path()
+                .join("src/rustdoc-stripper.toml")
+                .to_str()
+                .unwrap()
+        ])
+        .0,
+        Some(1)
+    );
+}
+
+#[test]
+fn file_filters() {
+    use stripper_lib::{list_filtered_files, FileFilter, Pattern};
+
+    let temp_dir = tempdir().unwrap();
+    for dir in &["src/gen", "tests/fixtures/deep", "target/debug", "logs"] {
+        std::fs::create_dir_all(temp_dir.path().join(dir)).unwrap();
+    }
+    for file in &[
+        "src/lib.rs",
+        "src/ast_generated.rs",
+        "src/gen/mod.rs",
+        "src/gen/keep.rs",
+        "tests/fixtures/deep/a.rs",
+        "tests/tests.rs",
+        "target/debug/build.rs",
+        "logs/x.rs",
+    ] {
+        gen_file(&temp_dir, file, "fn foo() {}\n");
+    }
+    gen_file(&temp_dir, "Cargo.toml", "[package]\nname = \"foo\"\n");
+    gen_file(
+        &temp_dir,
+        ".gitignore",
+        "# comment\nlogs/\n/src/gen/*\n!keep.rs\n",
+    );
+
+    let list = |filter: &FileFilter| {
+        let (files, errors) = list_filtered_files(temp_dir.path(), filter, false);
+        assert!(errors.is_empty());
+        files
+    };
+    let patterns = |patterns: &[&str]| {
+        patterns
+            .iter()
+            .map(|p| Pattern::new(p).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // `target` is skipped by default.
+    let mut filter = FileFilter::new();
+    assert_eq!(
+        list(&filter),
+        [
+            "logs/x.rs",
+            "src/ast_generated.rs",
+            "src/gen/keep.rs",
+            "src/gen/mod.rs",
+            "src/lib.rs",
+            "tests/fixtures/deep/a.rs",
+            "tests/tests.rs",
+        ]
+    );
+    filter.skip_target = false;
+    assert!(list(&filter).contains(&"target/debug/build.rs".to_owned()));
+
+    let mut filter = FileFilter::new();
+    filter.exclude = patterns(&["*_generated.rs", "tests/fixtures/**", "re:^logs/"]);
+    filter.include = patterns(&["src", "tests/", "logs/*.rs"]);
+    assert_eq!(
+        list(&filter),
+        [
+            "src/gen/keep.rs",
+            "src/gen/mod.rs",
+            "src/lib.rs",
+            "tests/tests.rs"
+        ]
+    );
+
+    let mut filter = FileFilter::new();
+    filter.gitignore = true;
+    assert_eq!(
+        list(&filter),
+        [
+            "src/ast_generated.rs",
+            "src/gen/keep.rs",
+            "src/lib.rs",
+            "tests/fixtures/deep/a.rs",
+            "tests/tests.rs",
+        ]
+    );
+
+    let regex = Pattern::new("re:^src/(a|b)[a-z_]{2,}\\.rs$").unwrap();
+    assert!(regex.matches("src/ast_generated.rs", false));
+    assert!(!regex.matches("src/lib.rs", false));
+    assert!(!regex.matches("src/gen/ast.rs", false));
+    assert!(Pattern::new("re:(a").is_err());
+    assert!(Pattern::new("[a").is_err());
+    assert!(Pattern::new("src/**/*.rs")
+        .unwrap()
+        .matches("src/a/b/c.rs", false));
+    assert!(!Pattern::new("src/*.rs")
+        .unwrap()
+        .matches("src/a/b.rs", false));
+    assert!(Pattern::new("*.{rs,txt}")
+        .unwrap()
+        .matches("src/a/b.txt", false));
+
+    // The functions not taking a filter go through everything but the given files.
+    let files = stripper_lib::utils::list_files(temp_dir.path(), &["src/lib.rs"], false).0;
+    assert!(files.contains(&"target/debug/build.rs".to_owned()));
+    assert!(!files.contains(&"src/lib.rs".to_owned()));
+
+    // From the command line.
+    let dir = temp_dir.path().to_str().unwrap();
+    let (code, out) = run_cli(&[
+        "stats", "-d", dir, "-G", "-i", "tests/", "-I", "*.rs", "--format", "json",
+    ]);
+    assert_eq!(code, Some(0));
+    assert!(out.contains("\"files\": 3"), "{}", out);
+    assert_eq!(run_cli(&["stats", "-d", dir, "-i", "re:("]).0, Some(2));
+}
+
+#[test]
+fn listed_files() {
+    let temp_dir = tempdir().unwrap();
+    gen_file(&temp_dir, "a.rs", "/// a\nfn a() {}\n");
+    gen_file(&temp_dir, "b.rs", "/// b\nfn b() {}\n");
+    gen_file(&temp_dir, "c.txt", "");
+    let dir = temp_dir.path().to_str().unwrap();
+    let path = |file: &str| temp_dir.path().join(file).to_str().unwrap().to_owned();
+    let comments = path("comments.md");
+
+    // Only the given Rust files are stripped, the other sections of the comments file are kept.
+    let (code, _) = run_cli(&["-f", "-d", dir, "-o", &comments, &path("b.rs")]);
+    assert_eq!(code, Some(0));
+    compare_files("/// a\nfn a() {}\n", &temp_dir.path().join("a.rs"));
+    let (code, _) = run_cli(&[
+        "strip",
+        "-fd",
+        dir,
+        "-o",
+        &comments,
+        &path("a.rs"),
+        &path("c.txt"),
+    ]);
+    assert_eq!(code, Some(0));
+    compare_files(
+        "<!-- file b.rs -->\n<!-- fn b -->\nb\n<!-- file a.rs -->\n<!-- fn a -->\na\n",
+        &temp_dir.path().join("comments.md"),
+    );
+
+    // Only the sections of the listed files are used.
+    gen_file(&temp_dir, "list", &format!("{}\0", path("a.rs")));
+    let (code, _) = run_cli(&[
+        "regenerate",
+        "-p",
+        "-d",
+        dir,
+        "-o",
+        &comments,
+        "--files-from",
+        &path("list"),
+    ]);
+    assert_eq!(code, Some(0));
+    compare_files("/// a\nfn a() {}\n", &temp_dir.path().join("a.rs"));
+    compare_files("fn b() {}\n", &temp_dir.path().join("b.rs"));
+    compare_files(
+        "<!-- file b.rs -->\n<!-- fn b -->\nb\n",
+        &temp_dir.path().join("comments.md"),
+    );
+
+    assert_eq!(
+        run_cli(&["-d", dir, "-o", &comments, "missing.rs"]).0,
+        Some(1)
+    );
+    assert_eq!(run_cli(&["stats", "-d", dir, &path("a.rs")]).0, Some(2));
+}
+
+#[test]
+fn cargo_workspace() {
+    let temp_dir = tempdir().unwrap();
+    for dir in &[
+        "crates/a/src/bin",
+        "crates/b/lib",
+        "crates/skip/src",
+        "tools",
+    ] {
+        std::fs::create_dir_all(temp_dir.path().join(dir)).unwrap();
+    }
+    gen_file(
+        &temp_dir,
+        "Cargo.toml",
+        "[workspace]\nmembers = [\n    \"./crates/*\", # all crates\n    \"tools\",\n]\n\
+         exclude = [\"crates/../crates/skip/\"]\ndefault-members = [\"crates/./a\"]\n",
+    );
+    gen_file(
+        &temp_dir,
+        "crates/a/Cargo.toml",
+        "[package]\nname = \"a\"\ndescription = \"\"\"\n[lib]\npath = \"nope.rs\"\n\"\"\"\n\n\
+         [dependencies]\nb = { path = \"../b\" }\n",
+    );
+    gen_file(&temp_dir, "crates/a/src/lib.rs", "/// a\nfn a() {}\n");
+    gen_file(&temp_dir, "crates/a/src/bin/tool.rs", "fn main() {}\n");
+    gen_file(
+        &temp_dir,
+        "crates/b/Cargo.toml",
+        "[package]\nname = \"b\"\n\n[lib]\npath = \"lib/b.rs\"\n",
+    );
+    gen_file(&temp_dir, "crates/b/lib/b.rs", "/// b\nfn b() {}\n");
+    gen_file(
+        &temp_dir,
+        "crates/skip/Cargo.toml",
+        "[package]\nname = \"skip\"\n",
+    );
+    gen_file(&temp_dir, "tools/Cargo.toml", "[workspace]\n");
+
+    let root = std::fs::canonicalize(temp_dir.path()).unwrap();
+    let workspace = stripper_lib::Workspace::load(&root.join("crates/b/Cargo.toml")).unwrap();
+    assert_eq!(workspace.root, root);
+    let names = workspace
+        .members
+        .iter()
+        .map(|p| &p.name[..])
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["a", "b"]);
+    assert_eq!(workspace.default_members, ["a"]);
+    assert_eq!(
+        workspace.members[0].targets,
+        [
+            root.join("crates/a/src/lib.rs"),
+            root.join("crates/a/src/bin/tool.rs")
+        ]
+    );
+    assert_eq!(workspace.members[0].source_dir(), root.join("crates/a/src"));
+    assert_eq!(workspace.members[1].source_dir(), root.join("crates/b/lib"));
+
+    let run = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_cargo-stripper"))
+            .arg("stripper")
+            .args(args)
+            .output()
+            .unwrap()
+            .status
+            .code()
+    };
+    let manifest = root.join("Cargo.toml");
+    let manifest = manifest.to_str().unwrap();
+    assert_eq!(
+        run(&["--manifest-path", manifest, "--workspace", "-f"]),
+        Some(0)
+    );
+    compare_files("fn a() {}\n", &root.join("crates/a/src/lib.rs"));
+    compare_files("fn b() {}\n", &root.join("crates/b/lib/b.rs"));
+    compare_files(
+        "<!-- file b.rs -->\n<!-- fn b -->\nb\n",
+        &root.join("crates/b/comments.md"),
+    );
+
+    assert_eq!(
+        run(&[
+            "--manifest-path",
+            manifest,
+            "-p",
+            "b",
+            "regenerate",
+            "-o",
+            "{package}.md"
+        ]),
+        Some(1)
+    );
+    assert_eq!(
+        run(&[
+            "--manifest-path",
+            manifest,
+            "regenerate",
+            "--prune-comment-file"
+        ]),
+        Some(0)
+    );
+    compare_files("/// a\nfn a() {}\n", &root.join("crates/a/src/lib.rs"));
+    compare_files("fn b() {}\n", &root.join("crates/b/lib/b.rs"));
+    assert!(!root.join("crates/a/comments.md").exists());
+    assert_eq!(run(&["--manifest-path", manifest, "-p", "skip"]), Some(1));
+    assert_eq!(run(&["--manifest-path", manifest, "-d", "src"]), Some(2));
+
+    // A target outside of `src` doesn't make the whole package directory the source one.
+    std::fs::create_dir_all(root.join("crates/c/src")).unwrap();
+    std::fs::create_dir_all(root.join("crates/c/tools")).unwrap();
+    gen_file(
+        &temp_dir,
+        "crates/c/Cargo.toml",
+        "[package]\nname = \"c\"\n\n[[bin]]\nname = \"gen\"\npath = \"tools/gen.rs\"\n",
+    );
+    gen_file(&temp_dir, "crates/c/src/lib.rs", "fn c() {}\n");
+    gen_file(&temp_dir, "crates/c/tools/gen.rs", "fn main() {}\n");
+    let package = stripper_lib::Package::load(&root.join("crates/c/Cargo.toml"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(package.source_dir(), root.join("crates/c/src"));
+    assert_eq!(
+        package.outside_targets(),
+        [root.join("crates/c/tools/gen.rs")]
+    );
+
+    std::fs::create_dir_all(root.join("crates/d")).unwrap();
+    gen_file(
+        &temp_dir,
+        "crates/d/Cargo.toml",
+        "[package]\nname = \"d\"\n\n[[bin]]\nname = \"d\"\npath = 1\n",
+    );
+    let err = stripper_lib::Package::load(&root.join("crates/d/Cargo.toml")).unwrap_err();
+    assert_eq!(err.line, Some(6));
+    assert_eq!(err.message, "`path` expects a string");
+}
+
+#[cfg(unix)]
+#[test]
+fn writing_through_links() {
+    use std::os::unix::fs::symlink;
+
+    let temp_dir = tempdir().unwrap();
+    std::fs::create_dir_all(temp_dir.path().join("real")).unwrap();
+    gen_file(&temp_dir, "real/a.rs", "/// foo\nfn foo() {}\n");
+    let link = temp_dir.path().join("a.rs");
+    symlink("real/a.rs", &link).unwrap();
+    stripper_lib::write_atomically(&link, b"fn foo() {}\n").unwrap();
+    assert!(std::fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    compare_files("fn foo() {}\n", &temp_dir.path().join("real/a.rs"));
+
+    let dangling = temp_dir.path().join("b.rs");
+    symlink("real/b.rs", &dangling).unwrap();
+    stripper_lib::write_atomically(&dangling, b"fn bar() {}\n").unwrap();
+    compare_files("fn bar() {}\n", &temp_dir.path().join("real/b.rs"));
+    assert_eq!(
+        std::fs::read_dir(temp_dir.path().join("real"))
+            .unwrap()
+            .count(),
+        2
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn walking_links_and_errors() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+    use stripper_lib::{list_filtered_files, walk_files, FileFilter};
+
+    let temp_dir = tempdir().unwrap();
+    let src = temp_dir.path().join("src");
+    std::fs::create_dir_all(src.join("sub")).unwrap();
+    gen_file(&temp_dir, "src/sub/a.rs", "fn a() {}\n");
+    symlink(&src, src.join("sub/loop")).unwrap();
+    symlink(src.join("sub/a.rs"), src.join("b.rs")).unwrap();
+    symlink(temp_dir.path().join("missing.rs"), src.join("broken.rs")).unwrap();
+
+    // Symbolic links are skipped by default.
+    let mut filter = FileFilter::new();
+    assert_eq!(
+        list_filtered_files(&src, &filter, false),
+        (vec!["sub/a.rs".to_owned()], vec![])
+    );
+
+    // When they are followed, every directory is only gone through once and errors are
+    // returned along with the other files.
+    filter.follow_links = true;
+    let (files, errors) = list_filtered_files(&src, &filter, false);
+    assert_eq!(files, ["b.rs", "sub/a.rs"]);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].message.starts_with("Broken symbolic link"));
+    std::fs::remove_file(src.join("broken.rs")).unwrap();
+    assert_eq!(
+        list_filtered_files(&src, &filter, false),
+        (vec!["b.rs".to_owned(), "sub/a.rs".to_owned()], vec![])
+    );
+
+    let name = OsStr::from_bytes(b"\xff.rs");
+    File::create(src.join(name)).unwrap();
+    let mut files = vec![];
+    walk_files(
+        &src,
+        &filter,
+        &mut |_, f| files.push(f.to_path_buf()),
+        false,
+    )
+    .unwrap();
+    assert_eq!(files.len(), 3);
+    assert!(files.iter().any(|f| f.as_os_str() == name));
+    let (files, errors) = list_filtered_files(&src, &filter, false);
+    assert_eq!(files, ["b.rs", "sub/a.rs"]);
+    assert_eq!(errors[0].message, "The path isn't valid UTF-8");
+}
+
+#[test]
+fn stripper_entry_point() {
+    let temp_dir = tempdir().unwrap();
+    std::fs::create_dir(temp_dir.path().join("gen")).unwrap();
+    gen_file(&temp_dir, "a.rs", "/// A.\nfn a() {}\n");
+    gen_file(&temp_dir, "b.rs", "/// B.\nfn b() {}\n");
+    gen_file(&temp_dir, "gen/c.rs", "/// C.\nfn c() {}\n");
+    let docs = temp_dir.path().join("docs.md");
+
+    let filter = stripper_lib::FileFilter {
+        exclude: vec![stripper_lib::Pattern::new("gen/").unwrap()],
+        ..stripper_lib::FileFilter::new()
+    };
+    let stripped = Stripper::new(temp_dir.path(), &docs)
+        .strip(&StripOptions::new().filter(filter))
+        .unwrap();
+    assert_eq!(stripped, ["a.rs", "b.rs"]);
+    compare_files("fn a() {}\n", &temp_dir.path().join("a.rs"));
+    compare_files("/// C.\nfn c() {}\n", &temp_dir.path().join("gen/c.rs"));
+    compare_files(
+        "<!-- file a.rs -->\n<!-- fn a -->\nA.\n<!-- file b.rs -->\n<!-- fn b -->\nB.\n",
+        &docs,
+    );
+
+    // Only the section of the listed file is used and removed.
+    let report = Stripper::new(temp_dir.path(), &docs)
+        .with_files(vec!["a.rs".to_owned()])
+        .regenerate(&RegenerateOptions::new().update_comment_file(true))
+        .unwrap();
+    assert_eq!(report.modified, ["a.rs"]);
+    compare_files("/// A.\nfn a() {}\n", &temp_dir.path().join("a.rs"));
+    compare_files("fn b() {}\n", &temp_dir.path().join("b.rs"));
+    compare_files("<!-- file b.rs -->\n<!-- fn b -->\nB.\n", &docs);
+
+    // The old functions still work.
+    stripper_lib::regenerate_doc_comments(
+        temp_dir.path().to_str().unwrap(),
+        false,
+        docs.to_str().unwrap(),
+        false,
+        false,
+    );
+    compare_files("/// B.\nfn b() {}\n", &temp_dir.path().join("b.rs"));
+    assert!(!docs.exists());
+}
+
+#[test]
+fn diagnostics() {
+    use std::sync::Arc;
+    use stripper_lib::diagnostics::Collector;
+    use stripper_lib::{Diagnostic, Severity};
+
+    let temp_dir = tempdir().unwrap();
+    gen_file(&temp_dir, "a.rs", "/// foo\nfn foo() {}\n");
+    gen_file(&temp_dir, "b.rs", "struct A {\n    //! misplaced\n}\n");
+    gen_file(&temp_dir, "c.txt", "");
+
+    let collector = Arc::new(Collector::new());
+    let dir = temp_dir.path().to_str().unwrap();
+    let options = StripOptions::new()
+        .verbose(true)
+        .diagnostics(collector.clone());
+    let _ = Stripper::new(temp_dir.path(), temp_dir.path().join("comments.md"))
+        .stripped_files(&options);
+    let diagnostics = collector
+        .take()
+        .into_iter()
+        .filter(|d| d.severity == Severity::Info)
+        .map(|d| d.to_string().replace(dir, "DIR"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        diagnostics,
+        ["-> DIR/a.rs", "-> DIR/b.rs", "-> DIR/c.txt: ignored"]
+    );
+
+    let d = Diagnostic::from(stripper_lib::FileError::new("b.rs", Some(2), "\"bad\""));
+    assert_eq!(d.severity, Severity::Error);
+    assert_eq!(d.to_string(), "error: b.rs:2: \"bad\"");
+    assert_eq!(
+        d.to_json(),
+        r#"{"severity": "error", "path": "b.rs", "line": 2, "message": "\"bad\""}"#
+    );
+    assert_eq!(
+        Diagnostic::warning("careful").to_string(),
+        "warning: careful"
+    );
+
+    // Only the doc comments are printed on stdout, the messages go to stderr.
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rustdoc-stripper"))
+        .args([
+            "-n",
+            "-v",
+            "--message-format=json",
+            "-d",
+            dir,
+            "a.rs",
+            "b.rs",
+        ])
+        .current_dir(temp_dir.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.lines().any(|l| l
+        == r#"{"severity": "error", "path": "b.rs", "line": 2, "message": "Mod/File comments cannot be put here!"}"#));
+
+    std::fs::remove_file(temp_dir.path().join("b.rs")).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rustdoc-stripper"))
+        .args(["-n", "-q", "-d", dir])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "<!-- file a.rs -->\n<!-- fn foo -->\nfoo\n"
+    );
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn in_memory_file_system() {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use stripper_lib::{FileSystem, FileType, MemoryFs};
+
+    let fs = Arc::new(MemoryFs::new());
+    fs.insert(
+        "crate/src/lib.rs",
+        "//! Crate.\n\n/// Foo.\npub fn foo() {}\n",
+    );
+    fs.insert("crate/src/a/b.rs", "/// B.\nstruct B;\n");
+    fs.insert("crate/src/notes.txt", "/// not Rust\n");
+    fs.insert("crate/src/target/x.rs", "/// X.\nfn x() {}\n");
+    fs.insert("crate/src/Cargo.toml", "");
+    fs.create_dir("crate/src/empty");
+    assert_eq!(
+        fs.read_dir(Path::new("./crate/src/")).unwrap().len(),
+        6,
+        "{:?}",
+        fs.files()
+    );
+    assert!(fs.read_dir(Path::new("crate/src/lib.rs")).is_err());
+    assert!(matches!(
+        fs.file_type(Path::new("/crate"), false),
+        Ok(FileType::Dir)
+    ));
+    assert!(fs.file_type(Path::new("crate/sr"), false).is_err());
+    assert!(fs.read(Path::new("crate/src/c.rs")).is_err());
+
+    let mut stripper = Stripper::new("crate/src", "crate/docs.md").with_file_system(fs.clone());
+    let stripped = stripper.strip(&StripOptions::new()).unwrap();
+    assert_eq!(stripped, ["a/b.rs", "lib.rs"]);
+    let read = |path: &str| String::from_utf8(fs.get(path).unwrap()).unwrap();
+    assert_eq!(read("crate/src/lib.rs"), "pub fn foo() {}\n");
+    assert_eq!(read("crate/src/a/b.rs"), "struct B;\n");
+    assert_eq!(read("crate/src/target/x.rs"), "/// X.\nfn x() {}\n");
+    assert_eq!(
+        read("/crate/docs.md"),
+        "<!-- file a/b.rs -->\n<!-- struct B -->\nB.\n<!-- file lib.rs -->\n\
+         <!-- file_comment -->\nCrate.\n<!-- fn foo -->\nFoo.\n",
+    );
+
+    let report = stripper
+        .regenerate(&RegenerateOptions::new().update_comment_file(true))
+        .unwrap();
+    assert_eq!(report.modified, ["a/b.rs", "lib.rs"]);
+    assert_eq!(
+        read("crate/src/lib.rs"),
+        "//! Crate.\n\n/// Foo.\npub fn foo() {}\n"
+    );
+    assert_eq!(read("crate/src/a/b.rs"), "/// B.\nstruct B;\n");
+    assert!(fs.get("crate/docs.md").is_none());
+    assert_eq!(
+        fs.files(),
+        [
+            "/crate/src/Cargo.toml",
+            "/crate/src/a/b.rs",
+            "/crate/src/lib.rs",
+            "/crate/src/notes.txt",
+            "/crate/src/target/x.rs",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn comments_file() {
+    use stripper_lib::{CommentsFile, DocEntry, EntryKind, FileSelector, Type, TypeStruct};
+
+    let content = "<!-- file * -->\n<!-- fn foo -->\nFoo.\n\
+                   <!-- file src/lib.rs -->\n<!-- file_comment -->\nCrate.\n\n\
+                   <!-- struct A::variant x -->\nField.\n<!-- fn dup #2 -->\nsecond\n\
+                   <!-- file_comment mod foo -->\nInner.\n<!-- file src/empty.rs -->\n";
+    for content in [
+        content.to_owned(),
+        content.replace('\n', "\r\n"),
+        content.trim_end().to_owned(),
+        "<!-- file a.rs -->\nno header\n<!--   fn   spaces -->\n".to_owned(),
+        "<!-- file a.rs -->\r\n<!-- fn foo -->\nFoo.\r\nend\n".to_owned(),
+        "<!-- file a.rs --> trailing\n<!-- fn foo -->\r\nFoo.\r".to_owned(),
+        String::new(),
+    ] {
+        let file = CommentsFile::parse(&content).unwrap();
+        assert_eq!(file.to_string(), content);
+    }
+    let mut file =
+        CommentsFile::parse("<!-- file a.rs --> trailing\r\n<!-- fn foo -->\nFoo.\n").unwrap();
+    assert_eq!(file.sections[0].file, FileSelector::from("a.rs"));
+    file.sections[0].entries[0].lines.push("Bar.".to_owned());
+    file.sections[0].file = FileSelector::from("b.rs");
+    assert_eq!(
+        file.to_string(),
+        "<!-- file b.rs -->\r\n<!-- fn foo -->\nFoo.\nBar.\r\n"
+    );
+    let err = CommentsFile::parse("\n<!-- file a.rs -->\n").unwrap_err();
+    assert_eq!(err.line, Some(1));
+
+    let mut file = content.parse::<CommentsFile>().unwrap();
+    assert_eq!(file.sections.len(), 3);
+    assert_eq!(file.sections[0].file, FileSelector::All);
+    let lib = FileSelector::from("src/lib.rs");
+    let entries = file
+        .entries()
+        .filter(|(f, _)| **f == lib)
+        .map(|(_, e)| {
+            (
+                e.kind,
+                e.path.as_ref().map(|p| p.to_string()),
+                e.lines.len(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        entries,
+        [
+            (EntryKind::FileComment, None, 2),
+            (EntryKind::Item, Some("struct A::variant x".to_owned()), 1),
+            (EntryKind::Item, Some("fn dup #2".to_owned()), 1),
+            (EntryKind::FileComment, Some("mod foo".to_owned()), 1),
+        ]
+    );
+    let foo = TypeStruct::new(Type::Fn, "foo");
+    assert_eq!(
+        file.get(&FileSelector::All, EntryKind::Item, Some(&foo))
+            .unwrap()
+            .lines,
+        ["Foo."]
+    );
+    assert!(file.get(&lib, EntryKind::Item, Some(&foo)).is_none());
+
+    // Replacing an entry keeps its position, new ones go at the end of their section.
+    let old = file.insert(
+        lib.clone(),
+        DocEntry::new(None, EntryKind::FileComment, vec!["New crate.".to_owned()]),
+    );
+    assert_eq!(old.unwrap().lines, ["Crate.", ""]);
+    file.insert(
+        FileSelector::from("b.rs"),
+        DocEntry::new(Some(foo.clone()), EntryKind::Item, vec!["B.".to_owned()]),
+    );
+    assert!(file
+        .remove(&FileSelector::All, EntryKind::Item, Some(&foo))
+        .is_some());
+    assert!(file
+        .remove(&FileSelector::All, EntryKind::Item, Some(&foo))
+        .is_none());
+
+    let mut other = CommentsFile::new();
+    other.insert(
+        FileSelector::from("b.rs"),
+        DocEntry::new(
+            Some(foo.clone()),
+            EntryKind::Item,
+            vec!["Other B.".to_owned()],
+        ),
+    );
+    other.insert(
+        FileSelector::from("c.rs"),
+        DocEntry::new(Some(foo), EntryKind::FileComment, vec!["C.".to_owned()]),
+    );
+    file.merge(other);
+    assert_eq!(
+        file.to_string(),
+        "<!-- file src/lib.rs -->\n<!-- file_comment -->\nNew crate.\n\
+         <!-- struct A::variant x -->\nField.\n<!-- fn dup #2 -->\nsecond\n\
+         <!-- file_comment mod foo -->\nInner.\n<!-- file src/empty.rs -->\n\
+         <!-- file b.rs -->\n<!-- fn foo -->\nOther B.\n\
+         <!-- file c.rs -->\n<!-- file_comment fn foo -->\nC.\n"
+    );
+}
+
+#[test]
+fn type_struct_paths() {
+    use stripper_lib::{CommentsFile, Type, TypeStruct};
+
+    for path in [
+        "fn foo",
+        "fn dup #2",
+        "struct A::variant x",
+        "mod a::impl fmt::Display for X::fn fmt",
+        "impl <T> std::ops::Deref for Vec<T, A> where T : Copy::fn c #1",
+        "impl From<Box<dyn Fn(u8) -> u8>> for mod_::fn from",
+        "trait Ext::type Item",
+        "? ::fn inner",
+        "macro foo!::struct D",
+    ] {
+        let parsed = path.parse::<TypeStruct>().unwrap();
+        assert_eq!(format!("{:?}", parsed), path);
+        assert_eq!(format!("{:?}", parsed).parse::<TypeStruct>(), Ok(parsed));
+    }
+    let parsed = "impl  Dialog ::  fn new".parse::<TypeStruct>().unwrap();
+    assert_eq!(parsed.to_string(), "impl Dialog::fn new");
+    assert_eq!((parsed.ty, &parsed.name[..]), (Type::Fn, "new"));
+    let parent = parsed.parent.unwrap();
+    assert_eq!((parent.ty, &parent.name[..]), (Type::Impl, "Dialog"));
+    // `Display` only writes the ordinal of the last segment.
+    let parsed = "impl Foo #2::fn new #3".parse::<TypeStruct>().unwrap();
+    assert_eq!(parsed.parent.as_ref().unwrap().ordinal, 2);
+    assert_eq!(parsed.to_string(), "impl Foo::fn new #3");
+    assert_eq!("fn from_fn".parse::<TypeStruct>().unwrap().name, "from_fn");
+    // `Display` doesn't write the macros containing an item.
+    let d = "macro foo!::struct D".parse::<TypeStruct>().unwrap();
+    assert_eq!(d.to_string(), "struct D");
+    let built = TypeStruct::new(Type::Fn, "dup")
+        .with_parent(TypeStruct::new(Type::Impl, "Dialog"))
+        .with_ordinal(2)
+        .with_visibility(stripper_lib::Visibility::Public);
+    assert_eq!(format!("{:?}", built), "impl Dialog::fn dup #2");
+    // The visibility isn't part of the path.
+    assert_eq!("impl Dialog::fn dup #2".parse::<TypeStruct>(), Ok(built));
+
+    for (path, position) in [
+        ("", 0),
+        ("fn a::", 6),
+        ("fn a::fn", 6),
+        ("mod a:: ::fn b", 7),
+        ("func a", 0),
+        ("fn", 0),
+        ("fn a #0", 6),
+        ("fn a #x", 6),
+        ("fn a #", 6),
+        ("fn ", 3),
+        ("fn  #2", 4),
+        ("mod a::fn ", 10),
+        ("impl <", 5),
+        ("impl Foo<(u8, T>::fn a", 8),
+        ("impl A for", 7),
+        ("impl A for ::fn a", 7),
+    ] {
+        let err = path.parse::<TypeStruct>().unwrap_err();
+        assert_eq!(err.position, position, "{}: {}", path, err);
+    }
+    assert_eq!("?".parse::<Type>(), Ok(Type::Unknown));
+    assert!("macro_rules".parse::<Type>().is_err());
+
+    let err = CommentsFile::parse("<!-- file a.rs -->\n<!-- fn a:: -->\nA.\n").unwrap_err();
+    assert_eq!(err.line, Some(2));
+
+    // Methods of impls whose header contains `::` get their doc comments back.
+    let temp_dir = tempdir().unwrap();
+    let content = "impl fmt::Display for X {\n    /// Fmt.\n    fn fmt() {}\n}\n";
+    gen_file(&temp_dir, "a.rs", content);
+    let mut stripper = Stripper::new(temp_dir.path(), temp_dir.path().join("docs.md"));
+    stripper.strip(&StripOptions::new()).unwrap();
+    compare_files(
+        "<!-- file a.rs -->\n<!-- impl fmt::Display for X::fn fmt -->\nFmt.\n",
+        &temp_dir.path().join("docs.md"),
+    );
+    stripper.regenerate(&RegenerateOptions::new()).unwrap();
+    compare_files(content, &temp_dir.path().join("a.rs"));
+}
+
+#[test]
+fn selectors() {
+    use std::sync::Arc;
+    use stripper_lib::{ItemFilter, MemoryFs, Selector, TypeStruct};
+
+    let path = |s: &str| s.parse::<TypeStruct>().unwrap();
+    let sel = |s: &str| Selector::new(s).unwrap();
+    assert!(sel("fn foo").matches(Some("lib.rs"), Some(&path("mod a::fn foo"))));
+    assert!(!sel("fn foo").matches(Some("lib.rs"), Some(&path("fn foo::fn bar"))));
+    assert!(sel("mod a::**").matches(None, Some(&path("mod a::struct B::variant c"))));
+    assert!(!sel("mod a::*").matches(None, Some(&path("mod a::struct B::variant c"))));
+    assert!(sel("impl Foo<T>::fn new").matches(None, Some(&path("impl Foo<T>::fn new"))));
+    assert!(sel("struct|enum F?o*").matches(None, Some(&path("enum Foo"))));
+    assert!(sel("src/*.rs@fn *").matches(Some("src/lib.rs"), Some(&path("fn f"))));
+    assert!(!sel("src/*.rs@fn *").matches(None, Some(&path("fn f"))));
+    assert!(sel("lib.rs@").matches(Some("lib.rs"), None));
+    assert!(Selector::new("").is_err());
+    assert!(Selector::new("struct|bad Foo").is_err());
+    assert!(Selector::new("a b c d").is_err());
+    assert!(Selector::new("pub(in a) fn x").is_err());
+    assert!(Selector::new("mod a::func b").is_err());
+    assert!(sel("? *").matches(None, Some(&TypeStruct::empty())));
+    assert!(Selector::new("a::::b").is_err());
+
+    let fs = Arc::new(MemoryFs::new());
+    let source = "//! Crate.\n\n/// Foo.\npub struct Foo {\n    /// A.\n    pub a: u32,\n}\n\n\
+                  impl Foo {\n    /// New.\n    pub fn new() {}\n}\n";
+    fs.insert("src/lib.rs", source);
+    let read = |path: &str| String::from_utf8(fs.get(path).unwrap()).unwrap();
+    let mut stripper = Stripper::new("src", "docs.md").with_file_system(fs.clone());
+    let items = ItemFilter {
+        only: vec![],
+        exclude: vec![sel("impl Foo::**"), sel("lib.rs@")],
+        ..ItemFilter::new()
+    };
+    assert!(stripper
+        .strip(&StripOptions::new().items(items))
+        .unwrap()
+        .is_empty());
+    assert_eq!(read("src/lib.rs"), source);
+
+    let items = ItemFilter {
+        only: vec![sel("struct Foo::**"), sel("impl Foo::**")],
+        exclude: vec![sel("variant a")],
+        ..ItemFilter::new()
+    };
+    stripper.strip(&StripOptions::new().items(items)).unwrap();
+    assert_eq!(
+        read("src/lib.rs"),
+        "//! Crate.\n\npub struct Foo {\n    /// A.\n    pub a: u32,\n}\n\n\
+         impl Foo {\n    pub fn new() {}\n}\n"
+    );
+    assert_eq!(
+        read("docs.md"),
+        "<!-- file lib.rs -->\n<!-- struct Foo -->\nFoo.\n<!-- impl Foo::fn new -->\nNew.\n"
+    );
+
+    let items = ItemFilter {
+        only: vec![sel("fn new")],
+        exclude: vec![],
+        ..ItemFilter::new()
+    };
+    stripper
+        .regenerate(
+            &RegenerateOptions::new()
+                .update_comment_file(true)
+                .items(items),
+        )
+        .unwrap();
+    assert_eq!(
+        read("src/lib.rs"),
+        "//! Crate.\n\npub struct Foo {\n    /// A.\n    pub a: u32,\n}\n\n\
+         impl Foo {\n    /// New.\n    pub fn new() {}\n}\n"
+    );
+    assert_eq!(
+        read("docs.md"),
+        "<!-- file lib.rs -->\n<!-- struct Foo -->\nFoo.\n"
+    );
+
+    assert_eq!(run_cli(&["strip", "--only", "fn|bad foo"]).0, Some(2));
+    assert_eq!(run_cli(&["stats", "--exclude", "fn foo"]).0, Some(2));
+}
+
+#[test]
+fn partial_strips() {
+    use std::sync::Arc;
+    use stripper_lib::cache::hash;
+    use stripper_lib::{Cache, ItemFilter, MemoryFs, Selector};
+
+    let temp_dir = tempdir().unwrap();
+    let fs = Arc::new(MemoryFs::new());
+    fs.insert(
+        "src/lib.rs",
+        "//! Crate.\n\n/// Foo.\npub struct Foo;\n\n/// Bar.\npub fn bar() {}\n",
+    );
+    let read = |path: &str| String::from_utf8(fs.get(path).unwrap()).unwrap();
+    let mut stripper = Stripper::new("src", "docs.md")
+        .with_file_system(fs.clone())
+        .with_cache(Cache::load(temp_dir.path()));
+    let only = |s: &str| ItemFilter {
+        only: vec![Selector::new(s).unwrap()],
+        ..ItemFilter::new()
+    };
+
+    stripper
+        .strip(&StripOptions::new().items(only("struct Foo")))
+        .unwrap();
+    assert_eq!(
+        read("docs.md"),
+        "<!-- file lib.rs -->\n<!-- struct Foo -->\nFoo.\n"
+    );
+    // The file still has doc comments.
+    let content = read("src/lib.rs");
+    assert!(!stripper
+        .cache()
+        .unwrap()
+        .is_stripped("lib.rs", hash(content.as_bytes())));
+
+    stripper
+        .strip(&StripOptions::new().items(only("fn bar")))
+        .unwrap();
+    assert_eq!(
+        read("docs.md"),
+        "<!-- file lib.rs -->\n<!-- struct Foo -->\nFoo.\n<!-- fn bar -->\nBar.\n"
+    );
+    assert_eq!(
+        read("src/lib.rs"),
+        "//! Crate.\n\npub struct Foo;\n\npub fn bar() {}\n"
+    );
+
+    stripper
+        .regenerate(&RegenerateOptions::new().update_comment_file(true))
+        .unwrap();
+    assert_eq!(
+        read("src/lib.rs"),
+        "//! Crate.\n\n/// Foo.\npub struct Foo;\n\n/// Bar.\npub fn bar() {}\n"
+    );
+}
+
+#[test]
+fn doc_kinds() {
+    use std::sync::Arc;
+    use stripper_lib::{DocKind, ItemFilter, MemoryFs, Type};
+
+    assert_eq!("module".parse(), Ok(DocKind::Module));
+    assert_eq!("field".parse(), Ok(DocKind::Item(Type::Variant)));
+    assert_eq!("fn".parse(), Ok(DocKind::Item(Type::Fn)));
+    assert!("function".parse::<DocKind>().is_err());
+
+    let fs = Arc::new(MemoryFs::new());
+    let source = "//! Crate.\n\n/// Foo.\npub struct Foo {\n    /// A.\n    pub a: u32,\n}\n\n\
+                  /// M.\nmod m {\n    //! Inner.\n\n    /// F.\n    fn f() {}\n}\n";
+    fs.insert("src/lib.rs", source);
+    let read = |path: &str| String::from_utf8(fs.get(path).unwrap()).unwrap();
+    let mut stripper = Stripper::new("src", "docs.md").with_file_system(fs.clone());
+    let items = ItemFilter {
+        exclude_kinds: vec![DocKind::Module, DocKind::Item(Type::Variant)],
+        ..ItemFilter::new()
+    };
+    stripper.strip(&StripOptions::new().items(items)).unwrap();
+    assert_eq!(
+        read("src/lib.rs"),
+        "//! Crate.\n\npub struct Foo {\n    /// A.\n    pub a: u32,\n}\n\n\
+         mod m {\n    //! Inner.\n\n    fn f() {}\n}\n"
+    );
+    assert_eq!(
+        read("docs.md"),
+        "<!-- file lib.rs -->\n<!-- struct Foo -->\nFoo.\n<!-- mod m -->\nM.\n\
+         <!-- mod m::fn f -->\nF.\n"
+    );
+
+    let items = ItemFilter {
+        only_kinds: vec![DocKind::Item(Type::Fn)],
+        ..ItemFilter::new()
+    };
+    let report = stripper
+        .regenerate(
+            &RegenerateOptions::new()
+                .update_comment_file(true)
+                .items(items),
+        )
+        .unwrap();
+    // The inner doc comments of the module aren't the ones of the function.
+    assert!(report.conflicts.is_empty());
+    assert_eq!(
+        read("src/lib.rs"),
+        "//! Crate.\n\npub struct Foo {\n    /// A.\n    pub a: u32,\n}\n\n\
+         mod m {\n    //! Inner.\n\n    /// F.\n    fn f() {}\n}\n"
+    );
+    assert_eq!(
+        read("docs.md"),
+        "<!-- file lib.rs -->\n<!-- struct Foo -->\nFoo.\n<!-- mod m -->\nM.\n"
+    );
+
+    assert_eq!(run_cli(&["strip", "--only-kind", "function"]).0, Some(2));
+}
+
+#[test]
+fn visibility() {
+    use std::sync::Arc;
+    use stripper_lib::{
+        collect_stats, FileFilter, FileSystem, ItemFilter, MemoryFs, TypeStruct, Visibility,
+    };
+
+    assert_eq!("pub".parse(), Ok(Visibility::Public));
+    assert_eq!("crate".parse(), Ok(Visibility::Crate));
+    assert_eq!("pub(super)".parse(), Ok(Visibility::Super));
+    assert_eq!(
+        "pub(in crate::a)".parse(),
+        Ok(Visibility::In("crate::a".to_owned()))
+    );
+    assert_eq!(
+        Visibility::In("super::b".to_owned()).to_string(),
+        "pub(in super::b)"
+    );
+    assert!("public".parse::<Visibility>().is_err());
+    let mut path = "struct Foo".parse::<TypeStruct>().unwrap();
+    path.visibility = Visibility::Public;
+    assert_eq!(path, "struct Foo".parse().unwrap());
+
+    let source = "//! Crate.\n\n/// Foo.\npub struct Foo {\n    /// A.\n    pub(crate) a: u32,\n    \
+                  /// B.\n    pub b: u32,\n}\n\n/// E.\npub enum E {\n    /// V.\n    V,\n}\n\n\
+                  /// Private.\nfn private() {}\n\nimpl Foo {\n    /// New.\n    pub unsafe fn new() {}\n    \
+                  /// Helper.\n    fn helper() {}\n}\n";
+    let fs = Arc::new(MemoryFs::new());
+    fs.insert("src/lib.rs", source);
+    let read = |path: &str| String::from_utf8(fs.get(path).unwrap()).unwrap();
+    let mut stripper = Stripper::new("src", "docs.md").with_file_system(fs.clone());
+    let items = ItemFilter {
+        visibility: vec![Visibility::Public],
+        ..ItemFilter::new()
+    };
+    stripper.strip(&StripOptions::new().items(items)).unwrap();
+    assert_eq!(
+        read("src/lib.rs"),
+        "pub struct Foo {\n    /// A.\n    pub(crate) a: u32,\n    pub b: u32,\n}\n\n\
+         pub enum E {\n    V,\n}\n\n/// Private.\nfn private() {}\n\n\
+         impl Foo {\n    pub unsafe fn new() {}\n    /// Helper.\n    fn helper() {}\n}\n"
+    );
+    assert_eq!(
+        read("docs.md"),
+        "<!-- file lib.rs -->\n<!-- file_comment -->\nCrate.\n<!-- struct Foo -->\nFoo.\n\
+         <!-- struct Foo::variant b -->\nB.\n<!-- enum E -->\nE.\n<!-- enum E::variant V -->\nV.\n\
+         <!-- impl Foo::fn new -->\nNew.\n"
+    );
+
+    fs.insert("src/lib.rs", source);
+    fs.remove_file(Path::new("docs.md")).unwrap();
+    stripper.strip(&StripOptions::new()).unwrap();
+    let items = ItemFilter {
+        visibility: vec![Visibility::Private, Visibility::Crate],
+        ..ItemFilter::new()
+    };
+    let report = stripper
+        .regenerate(
+            &RegenerateOptions::new()
+                .update_comment_file(true)
+                .items(items),
+        )
+        .unwrap();
+    assert!(report.conflicts.is_empty());
+    assert_eq!(
+        read("src/lib.rs"),
+        "pub struct Foo {\n    /// A.\n    pub(crate) a: u32,\n    pub b: u32,\n}\n\n\
+         pub enum E {\n    V,\n}\n\n/// Private.\nfn private() {}\n\n\
+         impl Foo {\n    pub unsafe fn new() {}\n    /// Helper.\n    fn helper() {}\n}\n"
+    );
+
+    let temp_dir = tempdir().unwrap();
+    gen_file(&temp_dir, "lib.rs", source);
+    let stats = |visibility: &[Visibility]| {
+        collect_stats(
+            temp_dir.path(),
+            &FileFilter::new(),
+            false,
+            1,
+            false,
+            visibility,
+            &stripper_lib::diagnostics::Stderr,
+        )
+        .unwrap()
+    };
+    let public = stats(&[Visibility::Public]);
+    // `Foo`, `b`, `E`, `V`, the impl and `new`.
+    assert_eq!((public.items, public.documented), (6, 5));
+    let restricted = stats(&[Visibility::Crate]);
+    assert_eq!((restricted.items, restricted.documented), (1, 1));
+    assert_eq!(
+        run_cli(&[
+            "stats",
+            "--visibility",
+            "pub(crate)",
+            "-d",
+            temp_dir.path().to_str().unwrap()
+        ]),
+        (
+            Some(0),
+            "1 files, 1 items, 1 documented (100.0%)\n".to_owned()
+        )
+    );
+}
+
+#[test]
+fn hashable_types() {
+    use std::collections::{HashMap, HashSet};
+    use stripper_lib::strip::parse_content;
+    use stripper_lib::{CommentsFile, DocEntry, EntryKind, TypeStruct};
+
+    let parsed = parse_content("/// Foo.\npub struct Foo;\n");
+    assert_eq!(parsed, parse_content("/// Foo.\npub struct Foo;\n"));
+    let events = parsed.event_list.iter().cloned().collect::<HashSet<_>>();
+    assert_eq!(events.len(), parsed.event_list.len());
+
+    let mut docs = HashMap::new();
+    docs.insert("struct Foo".parse::<TypeStruct>().unwrap(), "Foo.");
+    let file = "<!-- file lib.rs -->\n<!--   struct Foo   -->\nFoo.\n"
+        .parse::<CommentsFile>()
+        .unwrap();
+    let entry = &file.sections[0].entries[0];
+    assert_eq!(docs[entry.path.as_ref().unwrap()], "Foo.");
+    // The way the header is written doesn't matter.
+    assert_eq!(
+        *entry,
+        DocEntry::new(
+            Some("struct Foo".parse().unwrap()),
+            EntryKind::Item,
+            vec!["Foo.".to_owned()]
+        )
+    );
+    assert!(file.sections.iter().cloned().collect::<HashSet<_>>().len() == 1);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    use stripper_lib::strip::parse_content;
+    use stripper_lib::types::{EventInfo, ParseResult};
+    use stripper_lib::{CommentsFile, Section, TypeStruct, Visibility};
+
+    let mut path = "impl Foo::fn new #2".parse::<TypeStruct>().unwrap();
+    path.visibility = Visibility::In("crate::a".to_owned());
+    let json = serde_json::to_string(&path).unwrap();
+    let back = serde_json::from_str::<TypeStruct>(&json).unwrap();
+    assert_eq!(back, path);
+    assert_eq!(back.visibility, path.visibility);
+    // The visibility is optional.
+    let json = r#"{"ty":"Fn","parent":null,"name":"foo","args":[],"ordinal":0}"#;
+    assert_eq!(
+        serde_json::from_str::<TypeStruct>(json).unwrap(),
+        "fn foo".parse().unwrap()
+    );
+
+    let parsed = parse_content("/// Foo.\npub struct Foo;\n");
+    let json = serde_json::to_string(&parsed.event_list).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Vec<EventInfo>>(&json).unwrap(),
+        parsed.event_list
+    );
+    let json = serde_json::to_string(&parsed).unwrap();
+    assert_eq!(serde_json::from_str::<ParseResult>(&json).unwrap(), parsed);
+
+    let file = "<!-- file lib.rs -->\n<!-- file_comment -->\nCrate.\n<!-- struct Foo -->\nFoo.\n"
+        .parse::<CommentsFile>()
+        .unwrap();
+    let json = serde_json::to_string(&file.sections).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Vec<Section>>(&json).unwrap(),
+        file.sections
+    );
+}
+
+#[test]
+fn coverage() {
+    use stripper_lib::{collect_coverage, CoverageOptions, Type, Visibility};
+
+    let source =
+        "//! Crate.\n\n/// Foo.\npub struct Foo {\n    /// A.\n    pub a: u32,\n    b: u32,\n}\n\n\
+                  pub enum E {\n    /// V.\n    V,\n    W,\n}\n\n/// Inner.\npub mod inner {\n    \
+                  /// F.\n    pub fn f() {}\n    fn g() {}\n}\n\nimpl Foo {\n    \
+                  /// New.\n    pub fn new() -> Foo {\n        unimplemented!()\n    }\n}\n";
+    let temp_dir = tempdir().unwrap();
+    gen_file(&temp_dir, "lib.rs", source);
+    let dir = temp_dir.path().to_str().unwrap().to_owned();
+
+    let report = collect_coverage(temp_dir.path(), &CoverageOptions::new()).unwrap();
+    assert_eq!(report.files.len(), 1);
+    let file = &report.files[0];
+    assert_eq!(file.path, "lib.rs");
+    assert_eq!(file.coverage, report.total);
+    // `Foo`, `a`, `b`, `E`, `V`, `W`, `inner`, `f`, `g`, the impl and `new`.
+    assert_eq!(
+        (report.total.counts.items, report.total.counts.documented),
+        (11, 6)
+    );
+    let variants = report.total.kinds[&Type::Variant];
+    assert_eq!((variants.items, variants.documented), (4, 2));
+    assert_eq!(file.modules.len(), 1);
+    assert_eq!(file.modules[0].0, "mod inner");
+    assert_eq!(file.modules[0].1.counts.items, 2);
+    assert!((report.total.counts.percent() - 600. / 11.).abs() < 1e-9);
+
+    let public = collect_coverage(
+        temp_dir.path(),
+        &CoverageOptions::new().visibility(vec![Visibility::Public]),
+    )
+    .unwrap();
+    assert_eq!(
+        (public.total.counts.items, public.total.counts.documented),
+        (9, 6)
+    );
+
+    // Once stripped, the doc comments of the comments file are taken into account.
+    let comment_file = temp_dir.path().join("comments.md");
+    Stripper::new(temp_dir.path(), &comment_file)
+        .strip(&StripOptions::new())
+        .unwrap();
+    let stripped = collect_coverage(temp_dir.path(), &CoverageOptions::new()).unwrap();
+    assert_eq!(stripped.total.counts.documented, 0);
+    let stripped = collect_coverage(
+        temp_dir.path(),
+        &CoverageOptions::new().comment_file(Some(comment_file.clone())),
+    )
+    .unwrap();
+    assert_eq!(stripped, report);
+
+    let comment_file = comment_file.to_str().unwrap();
+    assert_eq!(
+        run_cli(&["coverage", "-d", &dir, "-o", comment_file]),
+        (
+            Some(0),
+            "lib.rs       6/11   54.5%  (struct 1/1, mod 1/1, enum 0/1, fn 2/3, variant 2/4, \
+             impl 0/1)\n  mod inner   1/2   50.0%  (fn 1/2)\nTotal        6/11   54.5%  \
+             (struct 1/1, mod 1/1, enum 0/1, fn 2/3, variant 2/4, impl 0/1)\n"
+                .to_owned()
+        )
+    );
+    let (code, out) = run_cli(&[
+        "coverage",
+        "-d",
+        &dir,
+        "-o",
+        comment_file,
+        "--format",
+        "json",
+    ]);
+    assert_eq!(code, Some(0));
+    assert!(out.starts_with(
+        "{\n  \"total\": {\"items\": 11, \"documented\": 6, \"kinds\": {\"struct\": {\"items\": 1, \
+         \"documented\": 1}, "
+    ));
+    assert!(out.contains(
+        "{\"module\": \"mod inner\", \"items\": 2, \"documented\": 1, \"kinds\": {\"fn\": \
+         {\"items\": 2, \"documented\": 1}}}"
+    ));
+    let fail_under = |percent: &str| {
+        run_cli(&[
+            "coverage",
+            "-d",
+            &dir,
+            "-o",
+            comment_file,
+            "--fail-under",
+            percent,
+        ])
+        .0
+    };
+    assert_eq!(fail_under("50"), Some(0));
+    assert_eq!(fail_under("60"), Some(1));
+    assert_eq!(fail_under("101"), Some(2));
+    assert_eq!(
+        run_cli(&["stats", "-d", &dir, "--fail-under", "50"]).0,
+        Some(2)
+    );
+}
+
+#[test]
+fn many_doc_blocks() {
+    use std::sync::Arc;
+    use stripper_lib::MemoryFs;
+
+    // Adjacent doc blocks (without blank line between the items), multi-line ones, items of an
+    // impl and a doc block on the last line of the file.
+    let mut source = String::from("//! Crate.\n\n");
+    let mut stripped = String::new();
+    for i in 0..2000 {
+        source.push_str(&format!("/// Fn {}.\n", i));
+        if i % 3 == 0 {
+            source.push_str("///\n/// More.\n");
+        }
+        source.push_str(&format!("pub fn f{}() {{}}\n", i));
+        stripped.push_str(&format!("pub fn f{}() {{}}\n", i));
+        if i % 10 == 0 {
+            let item = format!(
+                "\npub struct S{i};\n\nimpl S{i} {{\n    /// New.\n    pub fn new() -> S{i} {{\n        \
+                 S{i}\n    }}\n}}\n",
+                i = i
+            );
+            source.push_str(&item);
+            stripped.push_str(&item.replace("    /// New.\n", ""));
+        }
+    }
+    source.push_str("/// Last.\npub struct Last;");
+    stripped.push_str("pub struct Last;");
+
+    let fs = Arc::new(MemoryFs::new());
+    fs.insert("src/lib.rs", source.clone());
+    let read = |path: &str| String::from_utf8(fs.get(path).unwrap()).unwrap();
+    let mut stripper = Stripper::new("src", "docs.md").with_file_system(fs.clone());
+    stripper.strip(&StripOptions::new()).unwrap();
+    assert_eq!(read("src/lib.rs"), stripped);
+    let report = stripper.regenerate(&RegenerateOptions::new()).unwrap();
+    assert!(report.unplaced.is_empty());
+    assert_eq!(read("src/lib.rs"), source);
+}